@@ -6,10 +6,17 @@ use rig::completion::Prompt;
 use tracing::info;
 
 use crate::{
+    backtest::{self, BacktestReport},
     config::CONFIG,
-    core::vault::ManiXAIVault,
+    core::{
+        ai_journal::AiDecisionRecord, plan::RebalancePlan, rebalance_policy::RebalancePolicyStatus,
+        rpc_pool::RpcEndpointHealth, vault::ManiXAIVault,
+    },
     state::AppState,
-    types::{AdminAssociateVaultTokensRequest, ApiErrorResponse, ChatRequest, VaultDetails},
+    types::{
+        AdminAssociateVaultTokensRequest, AiDecisionsQuery, ApiErrorResponse, BacktestRequest,
+        ChatRequest, RebalancePlansQuery, RebalancePolicyQuery, VaultDetails,
+    },
 };
 
 #[utoipa::path(
@@ -86,20 +93,15 @@ async fn handle_admin_associate_vault_tokens(
         if !vault_details.is_vault_tokens_associated {
             let vault_contract = ManiXAIVault::new(vault_address, &app_state.evm_provider);
 
-            let associate_tx = match vault_contract.associateVaultTokens().send().await {
-                Ok(tx) => tx,
-                Err(e) => {
-                    return HttpResponse::InternalServerError().json(ApiErrorResponse {
-                        message: format!(
-                            "Failed to simulate associate vault tokens for : {:?}",
-                            address
-                        ),
-                        error: e.to_string(),
-                    });
-                }
-            };
+            let associate_tx = vault_contract
+                .associateVaultTokens()
+                .into_transaction_request();
 
-            let associate_tx_receipt = match associate_tx.get_receipt().await {
+            let associate_tx_receipt = match app_state
+                .tx_sender
+                .send(format!("associate tokens {}", address), associate_tx)
+                .await
+            {
                 Ok(receipt) => receipt,
                 Err(e) => {
                     return HttpResponse::InternalServerError().json(ApiErrorResponse {
@@ -170,3 +172,186 @@ async fn handle_chat(
         }),
     }
 }
+
+#[utoipa::path(
+    request_body = BacktestRequest,
+    responses(
+        (status = 200, description = "Backtest a strategy on a vault pool", body = BacktestReport),
+        (status = 400, description = "Bad request", body = ApiErrorResponse),
+        (status = 401, description = "Unauthorized", body = ApiErrorResponse),
+        (status = 404, description = "Vault not found", body = ApiErrorResponse),
+        (status = 500, description = "Internal server error", body = ApiErrorResponse),
+    )
+)]
+#[post("/api/v1/admin/backtest")]
+async fn handle_backtest(
+    app_state: web::Data<AppState>,
+    body: web::Json<BacktestRequest>,
+) -> impl Responder {
+    if body.password != CONFIG.admin_password {
+        return HttpResponse::Unauthorized().json(ApiErrorResponse {
+            message: "Unauthorized".to_string(),
+            error: "Wrong admin password".to_string(),
+        });
+    }
+
+    // The vaults are keyed by their configured address, whatever the case of the requested one
+    let vault_details = match CONFIG
+        .vault_config(&body.vault_address)
+        .and_then(|vault_config| app_state.all_vaults.get(&vault_config.address))
+    {
+        Some(vault_details) => vault_details.clone(),
+        None => {
+            return HttpResponse::NotFound().json(ApiErrorResponse {
+                message: format!("Vault {:?} not found", body.vault_address),
+                error: "Vault not found".to_string(),
+            });
+        }
+    };
+
+    let strategy = match app_state.strategies.get(&body.strategy) {
+        Ok(strategy) => strategy,
+        Err(e) => {
+            return HttpResponse::BadRequest().json(ApiErrorResponse {
+                message: format!("Unknown strategy {:?}", body.strategy),
+                error: e.to_string(),
+            });
+        }
+    };
+
+    // Every decision of these strategies is a paid prompt
+    if strategy.calls_model() {
+        return HttpResponse::BadRequest().json(ApiErrorResponse {
+            message: format!("Strategy {:?} can not be backtested", body.strategy),
+            error: "The strategy calls a model on each decision".to_string(),
+        });
+    }
+
+    let candles = match &body.ohlcv_file {
+        Some(file_name) => backtest::data::load_ohlcv_from_data_dir(file_name),
+        None => backtest::data::load_ohlcv_from_coingecko(&vault_details).await,
+    };
+
+    let candles = match candles {
+        Ok(candles) => candles,
+        Err(e) => {
+            return HttpResponse::BadRequest().json(ApiErrorResponse {
+                message: "Failed to load the backtest ohlcv data".to_string(),
+                error: e.to_string(),
+            });
+        }
+    };
+
+    match backtest::run_backtest(strategy.as_ref(), &vault_details, &candles, &body.config).await {
+        Ok(report) => HttpResponse::Ok().json(report),
+        Err(e) => HttpResponse::InternalServerError().json(ApiErrorResponse {
+            message: "Failed to run the backtest".to_string(),
+            error: e.to_string(),
+        }),
+    }
+}
+
+#[utoipa::path(
+    params(RebalancePlansQuery),
+    responses(
+        (status = 200, description = "Last rebalance plans of the vaults, newest first", body = Vec<RebalancePlan>),
+    )
+)]
+#[get("/api/v1/rebalance-plans")]
+async fn handle_get_rebalance_plans(
+    app_state: web::Data<AppState>,
+    query: web::Query<RebalancePlansQuery>,
+) -> impl Responder {
+    let vault_address = query
+        .vault_address
+        .as_ref()
+        .map(|address| address.to_lowercase());
+
+    let mut plans = app_state
+        .rebalance_plans
+        .iter()
+        .filter(|entry| {
+            vault_address
+                .as_ref()
+                .is_none_or(|address| entry.key() == address)
+        })
+        .flat_map(|entry| {
+            entry
+                .value()
+                .iter()
+                .cloned()
+                .collect::<Vec<RebalancePlan>>()
+        })
+        .collect::<Vec<RebalancePlan>>();
+
+    plans.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+
+    HttpResponse::Ok().json(plans)
+}
+
+#[utoipa::path(
+    params(AiDecisionsQuery),
+    responses(
+        (status = 200, description = "Calls of the AI strategy and the action taken on them, newest first", body = Vec<AiDecisionRecord>),
+    )
+)]
+#[get("/api/v1/ai-decisions")]
+async fn handle_get_ai_decisions(
+    app_state: web::Data<AppState>,
+    query: web::Query<AiDecisionsQuery>,
+) -> impl Responder {
+    let decisions = app_state
+        .ai_journal
+        .records(query.vault_address.as_deref(), query.limit);
+
+    HttpResponse::Ok().json(decisions)
+}
+
+#[utoipa::path(
+    params(RebalancePolicyQuery),
+    responses(
+        (status = 200, description = "Rebalance policy of the vaults with the last verdict of its rules", body = Vec<RebalancePolicyStatus>),
+    )
+)]
+#[get("/api/v1/rebalance-policy")]
+async fn handle_get_rebalance_policy(
+    app_state: web::Data<AppState>,
+    query: web::Query<RebalancePolicyQuery>,
+) -> impl Responder {
+    let vault_address = query
+        .vault_address
+        .as_ref()
+        .map(|address| address.to_lowercase());
+
+    let policies = CONFIG
+        .toml_config
+        .vaults
+        .iter()
+        .filter(|vault| {
+            vault_address
+                .as_ref()
+                .is_none_or(|address| &vault.address.to_lowercase() == address)
+        })
+        .map(|vault| RebalancePolicyStatus {
+            vault_address: vault.address.clone(),
+            config: vault.policy.clone(),
+            state: app_state
+                .rebalance_policies
+                .get(&vault.address.to_lowercase())
+                .map(|state| state.clone())
+                .unwrap_or_default(),
+        })
+        .collect::<Vec<RebalancePolicyStatus>>();
+
+    HttpResponse::Ok().json(policies)
+}
+
+#[utoipa::path(
+    responses(
+        (status = 200, description = "Health of the configured RPC endpoints, in config order", body = Vec<RpcEndpointHealth>),
+    )
+)]
+#[get("/api/v1/rpc-health")]
+async fn handle_get_rpc_health(app_state: web::Data<AppState>) -> impl Responder {
+    HttpResponse::Ok().json(app_state.rpc_pool.health())
+}
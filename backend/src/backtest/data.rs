@@ -0,0 +1,65 @@
+use std::{fs, path::Path};
+
+use color_eyre::eyre::{Context, Result};
+use serde_json::Value;
+
+use crate::{
+    core,
+    types::{CoingeckoOhlcvRes, OhlcvEntry, VaultDetails},
+};
+
+/// Directory where the local OHLCV files used by the backtests are stored
+pub const BACKTEST_DATA_DIR: &str = "backtest_data";
+
+/// Fetch the pool candles from coingecko, sorted oldest first
+pub async fn load_ohlcv_from_coingecko(vault_details: &VaultDetails) -> Result<Vec<OhlcvEntry>> {
+    let res =
+        core::coingecko::get_pool_ohlcv_data(&vault_details.pool.address, vault_details).await?;
+
+    Ok(res.data.attributes.ohlcv_list)
+}
+
+/// Load candles from a local json file, sorted oldest first.
+/// The file is either a raw coingecko ohlcv response, a candles file built from the pool swaps (see `core::swap_ohlcv`)
+/// or a plain array of `[timestamp, open, high, low, close, volume]`.
+pub fn load_ohlcv_from_file(path: impl AsRef<Path>) -> Result<Vec<OhlcvEntry>> {
+    let path = path.as_ref();
+
+    let raw = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read ohlcv file {}", path.display()))?;
+
+    let value: Value = serde_json::from_str(&raw)
+        .with_context(|| format!("Failed to parse ohlcv file {}", path.display()))?;
+
+    let mut candles: Vec<OhlcvEntry> = if value.is_array() {
+        serde_json::from_value(value)?
+    } else if value.get("candles").is_some() {
+        let ohlcv_file: core::swap_ohlcv::SwapOhlcvFile = serde_json::from_value(value)?;
+        ohlcv_file.candles
+    } else {
+        let res: CoingeckoOhlcvRes = serde_json::from_value(value)?;
+        res.data.attributes.ohlcv_list
+    };
+
+    candles.sort_by_key(|candle| candle.timestamp());
+
+    Ok(candles)
+}
+
+/// Load a file from the backtest data directory. Only plain file names are accepted so API callers can't read
+/// arbitrary files from the server.
+pub fn load_ohlcv_from_data_dir(file_name: &str) -> Result<Vec<OhlcvEntry>> {
+    let is_plain_file_name = Path::new(file_name)
+        .file_name()
+        .is_some_and(|name| name == file_name);
+
+    if !is_plain_file_name {
+        return Err(color_eyre::eyre::eyre!(
+            "Invalid ohlcv file name {:?}, expected a file inside {}",
+            file_name,
+            BACKTEST_DATA_DIR
+        ));
+    }
+
+    load_ohlcv_from_file(Path::new(BACKTEST_DATA_DIR).join(file_name))
+}
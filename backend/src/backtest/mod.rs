@@ -0,0 +1,624 @@
+/*
+    Offline backtesting of the range strategies.
+    A series of OHLCV candles is replayed through a strategy, the position is simulated with the uniswap v3
+    liquidity math and the report compares the result against simply holding the initial tokens.
+*/
+
+pub mod data;
+
+use alloy::primitives::U256;
+use color_eyre::eyre::Result;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, warn};
+use utoipa::ToSchema;
+
+use crate::{
+    config::MAX_BACKTEST_DECISIONS,
+    helpers::{
+        self,
+        amount::TokenAmount,
+        math::{
+            TickRounding,
+            uniswap_v3::{
+                liquidity_math::{get_amounts_for_liquidity, get_liquidity_for_amounts},
+                tick_math::get_sqrt_ratio_at_tick,
+            },
+        },
+    },
+    strategies::{Strategy, StrategyContext},
+    types::{OhlcvEntry, Position, VaultDetails},
+};
+
+// Liquidity used to get the token ratio of a range, big enough to not lose precision
+const RATIO_PROBE_LIQUIDITY: u128 = 1_000_000_000_000_000_000;
+
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct BacktestConfig {
+    /// Initial amount of token0 (formatted units)
+    pub initial_amount0: f64,
+    /// Initial amount of token1 (formatted units)
+    pub initial_amount1: f64,
+    /// Number of candles only used as history before the first strategy decision
+    pub warmup_candles: usize,
+    /// Run the strategy every `decision_interval` candles
+    pub decision_interval: usize,
+    /// Active liquidity of the pool, used to compute the share of the fees earned by the position
+    pub pool_liquidity: u128,
+    /// Factor converting a candle volume into token1 units (CoinGecko volumes are in USD)
+    pub volume_to_token1: f64,
+    /// Gas cost of one `rebalance` call in token1 units
+    pub rebalance_gas_cost: f64,
+}
+
+impl Default for BacktestConfig {
+    fn default() -> Self {
+        Self {
+            initial_amount0: 0.0,
+            initial_amount1: 0.0,
+            warmup_candles: 30,
+            decision_interval: 1,
+            pool_liquidity: 0,
+            volume_to_token1: 1.0,
+            rebalance_gas_cost: 0.0,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct BacktestStep {
+    pub timestamp: i64,
+    pub price: f64,
+    pub lower_tick: i32,
+    pub upper_tick: i32,
+    /// Fraction of the candle the price spent inside the position range
+    pub in_range: f64,
+    /// Value of the position, idle balances and earned fees in token1 units
+    pub value: f64,
+    pub rebalanced: bool,
+}
+
+/// All values are expressed in token1 units
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct BacktestReport {
+    pub strategy: String,
+    pub candles: usize,
+    pub start_timestamp: i64,
+    pub end_timestamp: i64,
+    pub initial_value: f64,
+    pub final_value: f64,
+    /// Value of the initial amounts held without providing liquidity
+    pub hodl_value: f64,
+    pub fees_earned: f64,
+    pub swap_costs: f64,
+    pub gas_costs: f64,
+    /// Value lost (negative) against holding, fees and costs excluded
+    pub impermanent_loss: f64,
+    pub impermanent_loss_pct: f64,
+    pub net_pnl: f64,
+    pub rebalances: u32,
+    pub time_in_range_pct: f64,
+    pub steps: Vec<BacktestStep>,
+}
+
+struct SimPosition {
+    lower_tick: i32,
+    upper_tick: i32,
+    liquidity: u128,
+}
+
+/// Replay the candles through the strategy. `vault_template` provides the pool metadata (tokens, fee, tick spacing),
+/// its live fields are overwritten at each step with the simulated state.
+pub async fn run_backtest(
+    strategy: &dyn Strategy,
+    vault_template: &VaultDetails,
+    candles: &[OhlcvEntry],
+    config: &BacktestConfig,
+) -> Result<BacktestReport> {
+    if candles.len() <= config.warmup_candles {
+        return Err(color_eyre::eyre::eyre!(
+            "Not enough candles to backtest: got {}, warmup needs more than {}",
+            candles.len(),
+            config.warmup_candles
+        ));
+    }
+
+    if config.decision_interval == 0 {
+        return Err(color_eyre::eyre::eyre!(
+            "decision_interval must be greater than 0"
+        ));
+    }
+
+    let decisions = (candles.len() - config.warmup_candles).div_ceil(config.decision_interval);
+
+    if decisions > MAX_BACKTEST_DECISIONS {
+        return Err(color_eyre::eyre::eyre!(
+            "The backtest would run {} strategy decisions, at most {} are allowed. Raise decision_interval",
+            decisions,
+            MAX_BACKTEST_DECISIONS
+        ));
+    }
+
+    let mut candles = candles.to_vec();
+    candles.sort_by_key(|candle| candle.timestamp());
+
+    let decimals0 = vault_template.pool.token0.decimals;
+    let decimals1 = vault_template.pool.token1.decimals;
+    // The pool fee is stored in percent
+    let fee = vault_template.pool.fee / 100.0;
+
+    let mut vault = vault_template.clone();
+
+    let mut idle0 = config.initial_amount0;
+    let mut idle1 = config.initial_amount1;
+    let mut position: Option<SimPosition> = None;
+
+    let mut fees_earned = 0.0;
+    let mut swap_costs = 0.0;
+    let mut gas_costs = 0.0;
+    let mut rebalances = 0;
+    let mut in_range_total = 0.0;
+    let mut steps = Vec::with_capacity(candles.len() - config.warmup_candles);
+
+    let start_price = candles[config.warmup_candles].close();
+    let initial_value = config.initial_amount0 * start_price + config.initial_amount1;
+
+    info!(
+        "Starting backtest of strategy {} over {} candles",
+        strategy.name(),
+        candles.len() - config.warmup_candles
+    );
+
+    for index in config.warmup_candles..candles.len() {
+        let candle = &candles[index];
+        let price = candle.close();
+        let tick = helpers::math::price_to_tick(price, decimals0, decimals1, TickRounding::Down)?;
+        let sqrt_price_x96 = get_sqrt_ratio_at_tick(tick)?;
+
+        // 1. Accrue the fees of the candle with the position opened on the previous steps
+        let mut in_range = 0.0;
+
+        if let Some(position) = &position {
+            let lower_price =
+                helpers::math::tick_to_price(position.lower_tick, decimals0, decimals1)?;
+            let upper_price =
+                helpers::math::tick_to_price(position.upper_tick, decimals0, decimals1)?;
+
+            in_range = range_overlap(candle.low(), candle.high(), lower_price, upper_price);
+
+            let share = position.liquidity as f64
+                / (position.liquidity as f64 + config.pool_liquidity as f64);
+            let candle_fees = candle.volume() * config.volume_to_token1 * fee * share * in_range;
+
+            // Fees are kept aside in token1 units and not compounded, so they don't carry any price exposure
+            fees_earned += candle_fees;
+        }
+
+        in_range_total += in_range;
+
+        // 2. Ask the strategy where the liquidity should be
+        let mut rebalanced = false;
+
+        if (index - config.warmup_candles).is_multiple_of(config.decision_interval) {
+            update_vault_snapshot(
+                &mut vault,
+                &position,
+                tick,
+                sqrt_price_x96,
+                price,
+                idle0,
+                idle1,
+            )?;
+
+            let decision = strategy
+                .decide(&StrategyContext {
+                    vault: &vault,
+                    ohlcv: Some(&candles[..=index]),
+                    journal: None,
+                })
+                .await?;
+
+            let range = decision.tick_range;
+            let is_same_range = position.as_ref().is_some_and(|position| {
+                position.lower_tick == range.lower_tick && position.upper_tick == range.upper_tick
+            });
+
+            if decision.rebalance_required && !is_same_range {
+                if range.lower_tick >= range.upper_tick {
+                    warn!(
+                        "Backtest: strategy {} returned an invalid range {:?} at {}. Ignoring it.",
+                        strategy.name(),
+                        range,
+                        candle.timestamp()
+                    );
+                } else {
+                    // 2.1 Burn the current position
+                    if let Some(old_position) = position.take() {
+                        let (amount0, amount1) =
+                            position_amounts(&old_position, sqrt_price_x96, decimals0, decimals1)?;
+                        idle0 += amount0;
+                        idle1 += amount1;
+                    }
+
+                    // 2.2 Swap to the token ratio of the new range
+                    let sqrt_lower = get_sqrt_ratio_at_tick(range.lower_tick)?;
+                    let sqrt_upper = get_sqrt_ratio_at_tick(range.upper_tick)?;
+
+                    let (probe0, probe1) = get_amounts_for_liquidity(
+                        sqrt_price_x96,
+                        sqrt_lower,
+                        sqrt_upper,
+                        RATIO_PROBE_LIQUIDITY,
+                    )?;
+                    let probe0 = from_raw(probe0, decimals0) * price;
+                    let probe1 = from_raw(probe1, decimals1);
+                    let target_share0 = probe0 / (probe0 + probe1);
+
+                    let total_value = idle0 * price + idle1;
+                    let excess0_value = idle0 * price - target_share0 * total_value;
+                    let swap_cost = excess0_value.abs() * fee;
+
+                    if excess0_value > 0.0 {
+                        // Sell token0 for token1, the fee is taken from the input
+                        idle0 -= excess0_value / price;
+                        idle1 += excess0_value - swap_cost;
+                    } else {
+                        idle1 -= excess0_value.abs() + swap_cost;
+                        idle0 += excess0_value.abs() / price;
+                    }
+                    swap_costs += swap_cost;
+
+                    // 2.3 Pay the gas of the rebalance transaction
+                    let gas_cost = config.rebalance_gas_cost;
+                    (idle0, idle1) = pay_in_token1(idle0, idle1, gas_cost, price);
+                    gas_costs += gas_cost;
+
+                    // 2.4 Mint the new position with the balances
+                    let liquidity = get_liquidity_for_amounts(
+                        sqrt_price_x96,
+                        sqrt_lower,
+                        sqrt_upper,
+                        to_raw(idle0, decimals0)?,
+                        to_raw(idle1, decimals1)?,
+                    )?;
+
+                    let new_position = SimPosition {
+                        lower_tick: range.lower_tick,
+                        upper_tick: range.upper_tick,
+                        liquidity,
+                    };
+
+                    let (used0, used1) =
+                        position_amounts(&new_position, sqrt_price_x96, decimals0, decimals1)?;
+                    idle0 = (idle0 - used0).max(0.0);
+                    idle1 = (idle1 - used1).max(0.0);
+
+                    debug!(
+                        "Backtest: rebalanced to [{}, {}] at {} with liquidity {}",
+                        range.lower_tick,
+                        range.upper_tick,
+                        candle.timestamp(),
+                        liquidity
+                    );
+
+                    position = Some(new_position);
+                    rebalances += 1;
+                    rebalanced = true;
+                }
+            }
+        }
+
+        // 3. Value the vault at the candle close
+        let (position0, position1) = match &position {
+            Some(position) => position_amounts(position, sqrt_price_x96, decimals0, decimals1)?,
+            None => (0.0, 0.0),
+        };
+
+        steps.push(BacktestStep {
+            timestamp: candle.timestamp(),
+            price,
+            lower_tick: position.as_ref().map_or(0, |position| position.lower_tick),
+            upper_tick: position.as_ref().map_or(0, |position| position.upper_tick),
+            in_range,
+            value: (position0 + idle0) * price + position1 + idle1 + fees_earned,
+            rebalanced,
+        });
+    }
+
+    let last_price = candles[candles.len() - 1].close();
+    let final_value = steps.last().map_or(initial_value, |step| step.value);
+    let hodl_value = config.initial_amount0 * last_price + config.initial_amount1;
+    let impermanent_loss = final_value - fees_earned + swap_costs + gas_costs - hodl_value;
+
+    let report = BacktestReport {
+        strategy: strategy.name().to_string(),
+        candles: steps.len(),
+        start_timestamp: candles[config.warmup_candles].timestamp(),
+        end_timestamp: candles[candles.len() - 1].timestamp(),
+        initial_value,
+        final_value,
+        hodl_value,
+        fees_earned,
+        swap_costs,
+        gas_costs,
+        impermanent_loss,
+        impermanent_loss_pct: if hodl_value > 0.0 {
+            impermanent_loss / hodl_value * 100.0
+        } else {
+            0.0
+        },
+        net_pnl: final_value - initial_value,
+        rebalances,
+        time_in_range_pct: in_range_total / steps.len() as f64 * 100.0,
+        steps,
+    };
+
+    info!(
+        "Backtest of strategy {} done: final value {}, hodl value {}, fees {}, rebalances {}",
+        report.strategy,
+        report.final_value,
+        report.hodl_value,
+        report.fees_earned,
+        report.rebalances
+    );
+
+    Ok(report)
+}
+
+/// Set the live fields of the simulated vault the way `core::vault::update_vault_live` does
+fn update_vault_snapshot(
+    vault: &mut VaultDetails,
+    position: &Option<SimPosition>,
+    tick: i32,
+    sqrt_price_x96: U256,
+    price: f64,
+    idle0: f64,
+    idle1: f64,
+) -> Result<()> {
+    let decimals0 = vault.pool.token0.decimals;
+    let decimals1 = vault.pool.token1.decimals;
+
+    vault.pool.current_tick = tick;
+    vault.pool.sqrt_price_x96 = sqrt_price_x96;
+    vault.pool.price1 = price;
+    vault.pool.price0 = 1.0 / price;
+
+    match position {
+        Some(position) => {
+            let (amount0, amount1) =
+                position_token_amounts(position, sqrt_price_x96, decimals0, decimals1)?;
+
+            vault.is_active = true;
+            vault.lower_tick = position.lower_tick;
+            vault.upper_tick = position.upper_tick;
+            vault.position = Position {
+                tick_lower: position.lower_tick,
+                tick_upper: position.upper_tick,
+                liquidity: position.liquidity,
+                amount0,
+                amount1,
+                fees0: TokenAmount::zero(decimals0),
+                fees1: TokenAmount::zero(decimals1),
+            };
+        }
+        None => {
+            vault.is_active = false;
+            vault.lower_tick = 0;
+            vault.upper_tick = 0;
+            vault.position = Position::empty(decimals0, decimals1);
+        }
+    }
+
+    vault.tvl.tvl0 = vault
+        .position
+        .amount0
+        .checked_add(&TokenAmount::from_f64(idle0.max(0.0), decimals0)?)?;
+    vault.tvl.tvl1 = vault
+        .position
+        .amount1
+        .checked_add(&TokenAmount::from_f64(idle1.max(0.0), decimals1)?)?;
+
+    Ok(())
+}
+
+fn position_amounts(
+    position: &SimPosition,
+    sqrt_price_x96: U256,
+    decimals0: u8,
+    decimals1: u8,
+) -> Result<(f64, f64)> {
+    let (amount0, amount1) =
+        position_token_amounts(position, sqrt_price_x96, decimals0, decimals1)?;
+
+    Ok((amount0.to_f64(), amount1.to_f64()))
+}
+
+fn position_token_amounts(
+    position: &SimPosition,
+    sqrt_price_x96: U256,
+    decimals0: u8,
+    decimals1: u8,
+) -> Result<(TokenAmount, TokenAmount)> {
+    let (amount0, amount1) = get_amounts_for_liquidity(
+        sqrt_price_x96,
+        get_sqrt_ratio_at_tick(position.lower_tick)?,
+        get_sqrt_ratio_at_tick(position.upper_tick)?,
+        position.liquidity,
+    )?;
+
+    Ok((
+        TokenAmount::new(amount0, decimals0),
+        TokenAmount::new(amount1, decimals1),
+    ))
+}
+
+/// Fraction of the [low, high] candle range that is inside [lower, upper]
+fn range_overlap(low: f64, high: f64, lower: f64, upper: f64) -> f64 {
+    if high <= low {
+        return if low >= lower && low <= upper {
+            1.0
+        } else {
+            0.0
+        };
+    }
+
+    let overlap = high.min(upper) - low.max(lower);
+
+    (overlap / (high - low)).clamp(0.0, 1.0)
+}
+
+/// Take `cost` from the token1 balance. What token1 can not cover, including a debt left by the swap,
+/// is sold from the token0 balance at `price`
+fn pay_in_token1(idle0: f64, idle1: f64, cost: f64, price: f64) -> (f64, f64) {
+    let remaining1 = idle1 - cost;
+
+    if remaining1 >= 0.0 {
+        (idle0, remaining1)
+    } else {
+        (idle0 + remaining1 / price, 0.0)
+    }
+}
+
+fn to_raw(amount: f64, decimals: u8) -> Result<U256> {
+    Ok(TokenAmount::from_f64(amount.max(0.0), decimals)?.raw)
+}
+
+fn from_raw(amount: U256, decimals: u8) -> f64 {
+    TokenAmount::new(amount, decimals).to_f64()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        strategies::{ai::AiStrategy, basic::BasicStrategy},
+        types::{LlmConfig, LlmProvider, test_vault},
+    };
+
+    fn vault_template() -> VaultDetails {
+        test_vault(0, 60, 0.0)
+    }
+
+    fn candles(prices: &[f64]) -> Vec<OhlcvEntry> {
+        prices
+            .iter()
+            .enumerate()
+            .map(|(i, price)| {
+                OhlcvEntry(
+                    i as i64 * 86_400,
+                    *price,
+                    price * 1.002,
+                    price * 0.998,
+                    *price,
+                    100_000.0,
+                )
+            })
+            .collect()
+    }
+
+    fn config() -> BacktestConfig {
+        BacktestConfig {
+            initial_amount0: 1_000.0,
+            initial_amount1: 200.0,
+            warmup_candles: 2,
+            decision_interval: 1,
+            pool_liquidity: 100_000_000_000_000,
+            volume_to_token1: 1.0,
+            rebalance_gas_cost: 0.5,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_flat_market_rebalances_once() {
+        let candles = candles(&[0.2; 20]);
+
+        let report = run_backtest(&BasicStrategy, &vault_template(), &candles, &config())
+            .await
+            .unwrap();
+
+        assert_eq!(report.rebalances, 1);
+        assert_eq!(report.candles, 18);
+        assert!(report.fees_earned > 0.0);
+        // The position is only minted at the close of the first candle
+        assert!(report.time_in_range_pct > 90.0);
+        // No price move, the only loss against holding is rounding dust
+        assert!(report.impermanent_loss.abs() < 1e-3);
+        assert!((report.gas_costs - 0.5).abs() < f64::EPSILON);
+    }
+
+    #[tokio::test]
+    async fn test_trending_market_has_impermanent_loss() {
+        let prices: Vec<f64> = (0..30).map(|i| 0.2 * 1.01f64.powi(i)).collect();
+        let candles = candles(&prices);
+
+        let report = run_backtest(&BasicStrategy, &vault_template(), &candles, &config())
+            .await
+            .unwrap();
+
+        assert!(report.rebalances > 1);
+        assert!(report.impermanent_loss < 0.0);
+        assert!(report.swap_costs > 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_ai_strategy_runs_offline_with_mock_model() {
+        let candles = candles(&[0.2; 10]);
+        let strategy = AiStrategy::new(LlmConfig {
+            provider: LlmProvider::Mock,
+            mock_reply: Some(
+                r#"```json
+{"rebalance_required": true, "new_price_range": {"lower_price": 0.19, "upper_price": 0.21}, "analysis": "Flat market", "market_outlook": "Flat", "confidence_score": 0.9}
+```"#
+                    .to_string(),
+            ),
+            ..LlmConfig::default()
+        });
+
+        let report = run_backtest(&strategy, &vault_template(), &candles, &config())
+            .await
+            .unwrap();
+
+        // Same answer on every candle, the range is only minted once
+        assert_eq!(report.rebalances, 1);
+        assert!(report.time_in_range_pct > 80.0);
+    }
+
+    #[tokio::test]
+    async fn test_not_enough_candles() {
+        let candles = candles(&[0.2; 2]);
+
+        let result = run_backtest(&BasicStrategy, &vault_template(), &candles, &config()).await;
+
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_decisions_are_capped() {
+        let candles = candles(&vec![0.2; MAX_BACKTEST_DECISIONS + 3]);
+
+        let result = run_backtest(&BasicStrategy, &vault_template(), &candles, &config()).await;
+        assert!(result.is_err());
+
+        let config = BacktestConfig {
+            decision_interval: 2,
+            ..config()
+        };
+        let result = run_backtest(&BasicStrategy, &vault_template(), &candles, &config).await;
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_range_overlap() {
+        assert_eq!(range_overlap(1.0, 2.0, 0.0, 3.0), 1.0);
+        assert_eq!(range_overlap(1.0, 2.0, 1.5, 3.0), 0.5);
+        assert_eq!(range_overlap(1.0, 2.0, 2.5, 3.0), 0.0);
+        assert_eq!(range_overlap(1.0, 1.0, 0.5, 1.5), 1.0);
+    }
+
+    #[test]
+    fn test_gas_shortfall_is_paid_in_token0() {
+        assert_eq!(pay_in_token1(10.0, 5.0, 2.0, 2.0), (10.0, 3.0));
+        assert_eq!(pay_in_token1(10.0, 1.0, 2.0, 2.0), (9.5, 0.0));
+        // The swap left a token1 debt of 1, it is paid with the gas
+        assert_eq!(pay_in_token1(10.0, -1.0, 2.0, 2.0), (8.5, 0.0));
+    }
+}
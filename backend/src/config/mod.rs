@@ -1,9 +1,14 @@
-use std::fs;
+use std::{collections::HashSet, fs, str::FromStr};
 
+use alloy::primitives::Address;
+use color_eyre::eyre::Result;
 use dotenvy::dotenv;
 use once_cell::sync::Lazy;
 
-use crate::types::TomlConfig;
+use crate::{
+    core::{coingecko::OhlcvTimeframe, rebalance_policy::CronWindow},
+    types::{OhlcvSource, TomlConfig, VaultConfig},
+};
 
 pub const RPC_URL: &str = "https://testnet.hashio.io/api";
 pub const CHAIN_ID: u64 = 296;
@@ -24,6 +29,8 @@ pub struct Config {
     pub mailer_password: String,
     pub is_execute: bool,
     pub coingecko_api_key: String,
+    /// The key is a CoinGecko Pro key, sent to the Pro API instead of the public one
+    pub is_coingecko_pro: bool,
 }
 
 impl Config {
@@ -43,6 +50,10 @@ impl Config {
         let mailer_password = std::env::var("MAILER_PASSWORD").expect("MAILER_PASSWORD is not set");
         let coingecko_api_key =
             std::env::var("COINGEKO_API_KEY").expect("COINGEKO_API_KEY is not set");
+        let is_coingecko_pro = std::env::var("COINGEKO_API_PLAN")
+            .unwrap_or("demo".to_string())
+            .to_lowercase()
+            == "pro";
 
         // Load config from toml file based on the environment (mainnet or testnet)
         let toml_config_file_path = if is_mainnet {
@@ -56,6 +67,8 @@ impl Config {
 
         let toml_config: TomlConfig = toml::from_str(&raw).expect("Failed to parse config.toml");
 
+        validate_toml_config(&toml_config).expect("Invalid config toml file");
+
         Self {
             private_key,
             is_mainnet,
@@ -66,13 +79,338 @@ impl Config {
             mailer_password,
             is_execute,
             coingecko_api_key,
+            is_coingecko_pro,
         }
     }
 }
 
+impl Config {
+    /// Get the config of a vault by its address
+    pub fn vault_config(&self, vault_address: &str) -> Option<&VaultConfig> {
+        self.toml_config
+            .vaults
+            .iter()
+            .find(|vault| vault.address.to_lowercase() == vault_address.to_lowercase())
+    }
+}
+
+/// Check the values of the toml config that serde can not check by itself.
+/// Strategy names are checked against the strategy registry on startup (see `StrategyRegistry::check_config`)
+pub fn validate_toml_config(toml_config: &TomlConfig) -> Result<()> {
+    if toml_config.rpc_urls.is_empty() {
+        return Err(color_eyre::eyre::eyre!(
+            "rpc_urls must contain at least one url"
+        ));
+    }
+
+    for rpc_url in &toml_config.rpc_urls {
+        reqwest::Url::parse(rpc_url)
+            .map_err(|e| color_eyre::eyre::eyre!("Invalid rpc url {:?}: {}", rpc_url, e))?;
+    }
+
+    if toml_config.cross_check_reads && toml_config.rpc_urls.len() < 2 {
+        return Err(color_eyre::eyre::eyre!(
+            "cross_check_reads needs at least two rpc_urls"
+        ));
+    }
+
+    if let Some(multicall_address) = &toml_config.multicall_address {
+        Address::from_str(multicall_address).map_err(|e| {
+            color_eyre::eyre::eyre!("Invalid multicall address {:?}: {}", multicall_address, e)
+        })?;
+    }
+
+    let volatility = &toml_config.volatility;
+
+    if volatility.window < 2 {
+        return Err(color_eyre::eyre::eyre!(
+            "volatility.window must be at least 2 candles"
+        ));
+    }
+
+    if volatility.horizon_candles == 0 {
+        return Err(color_eyre::eyre::eyre!(
+            "volatility.horizon_candles must be greater than 0"
+        ));
+    }
+
+    if !(volatility.sigma_multiple > 0.0 && volatility.sigma_multiple.is_finite()) {
+        return Err(color_eyre::eyre::eyre!(
+            "volatility.sigma_multiple must be greater than 0"
+        ));
+    }
+
+    if !(volatility.atr_multiple >= 0.0 && volatility.atr_multiple.is_finite()) {
+        return Err(color_eyre::eyre::eyre!(
+            "volatility.atr_multiple must be a positive number"
+        ));
+    }
+
+    if volatility.min_width_spacings == 0
+        || volatility.min_width_spacings > volatility.max_width_spacings
+    {
+        return Err(color_eyre::eyre::eyre!(
+            "volatility.min_width_spacings must be between 1 and volatility.max_width_spacings"
+        ));
+    }
+
+    if !(0.0..=1.0).contains(&volatility.trend_skew) {
+        return Err(color_eyre::eyre::eyre!(
+            "volatility.trend_skew must be between 0 and 1"
+        ));
+    }
+
+    if !(volatility.rebalance_width_ratio >= 1.0 && volatility.rebalance_width_ratio.is_finite()) {
+        return Err(color_eyre::eyre::eyre!(
+            "volatility.rebalance_width_ratio must be at least 1"
+        ));
+    }
+
+    let ensemble = &toml_config.ensemble;
+
+    if !ensemble
+        .strategies
+        .contains(&ensemble.deterministic_strategy)
+    {
+        return Err(color_eyre::eyre::eyre!(
+            "ensemble.deterministic_strategy {:?} must be one of ensemble.strategies",
+            ensemble.deterministic_strategy
+        ));
+    }
+
+    if ensemble
+        .strategies
+        .iter()
+        .any(|strategy| strategy == "ensemble")
+    {
+        return Err(color_eyre::eyre::eyre!(
+            "ensemble.strategies can not contain the ensemble strategy"
+        ));
+    }
+
+    if !(0.0..=1.0).contains(&ensemble.min_confidence) {
+        return Err(color_eyre::eyre::eyre!(
+            "ensemble.min_confidence must be between 0 and 1"
+        ));
+    }
+
+    if ensemble.min_width_spacings == 0 || ensemble.min_width_spacings > ensemble.max_width_spacings
+    {
+        return Err(color_eyre::eyre::eyre!(
+            "ensemble.min_width_spacings must be between 1 and ensemble.max_width_spacings"
+        ));
+    }
+
+    let mut seen_addresses = HashSet::new();
+
+    for vault in &toml_config.vaults {
+        Address::from_str(&vault.address).map_err(|e| {
+            color_eyre::eyre::eyre!("Invalid vault address {:?}: {}", vault.address, e)
+        })?;
+
+        if !seen_addresses.insert(vault.address.to_lowercase()) {
+            return Err(color_eyre::eyre::eyre!(
+                "Vault {} is configured more than once",
+                vault.address
+            ));
+        }
+
+        if vault.strategy.trim().is_empty() {
+            return Err(color_eyre::eyre::eyre!(
+                "Vault {} has an empty strategy name",
+                vault.address
+            ));
+        }
+
+        if vault
+            .fallback_strategies
+            .iter()
+            .any(|strategy| strategy.trim().is_empty())
+        {
+            return Err(color_eyre::eyre::eyre!(
+                "Vault {} has an empty fallback strategy name",
+                vault.address
+            ));
+        }
+
+        if vault.monitor_interval_seconds == 0 {
+            return Err(color_eyre::eyre::eyre!(
+                "Vault {} monitor_interval_seconds must be greater than 0",
+                vault.address
+            ));
+        }
+
+        if !(vault.profit_horizon_hours > 0.0 && vault.profit_horizon_hours.is_finite()) {
+            return Err(color_eyre::eyre::eyre!(
+                "Vault {} profit_horizon_hours must be greater than 0",
+                vault.address
+            ));
+        }
+
+        if !(vault.volume_to_token1 >= 0.0 && vault.volume_to_token1.is_finite()) {
+            return Err(color_eyre::eyre::eyre!(
+                "Vault {} volume_to_token1 must be a positive number",
+                vault.address
+            ));
+        }
+
+        if let Some(hbar_price) = vault.hbar_price_token1
+            && !(hbar_price > 0.0 && hbar_price.is_finite())
+        {
+            return Err(color_eyre::eyre::eyre!(
+                "Vault {} hbar_price_token1 must be greater than 0",
+                vault.address
+            ));
+        }
+
+        if vault.ohlcv_interval_seconds == 0 {
+            return Err(color_eyre::eyre::eyre!(
+                "Vault {} ohlcv_interval_seconds must be greater than 0",
+                vault.address
+            ));
+        }
+
+        if vault.ohlcv_source == OhlcvSource::Coingecko
+            && OhlcvTimeframe::from_seconds(vault.ohlcv_interval_seconds).is_none()
+        {
+            return Err(color_eyre::eyre::eyre!(
+                "Vault {} ohlcv_interval_seconds must be 60, 3600 or 86400 with CoinGecko candles",
+                vault.address
+            ));
+        }
+
+        if vault.swap_slippage_bps > 10_000 {
+            return Err(color_eyre::eyre::eyre!(
+                "Vault {} swap_slippage_bps must be at most 10000",
+                vault.address
+            ));
+        }
+
+        if let Some(trigger_band_pct) = vault.policy.trigger_band_pct
+            && !(0.0..50.0).contains(&trigger_band_pct)
+        {
+            return Err(color_eyre::eyre::eyre!(
+                "Vault {} policy.trigger_band_pct must be between 0 and 50",
+                vault.address
+            ));
+        }
+
+        for window in &vault.policy.schedule {
+            CronWindow::parse(window)
+                .map_err(|e| color_eyre::eyre::eyre!("Vault {} policy: {}", vault.address, e))?;
+        }
+    }
+
+    Ok(())
+}
+
 // Define a globally accessible static Config instance
 pub static CONFIG: Lazy<Config> = Lazy::new(Config::load);
 
 // Other constants
 pub const FEE_FACTOR: f64 = 10_000.0;
 pub const MONITOR_VAULT_INTERVAL_SECONDS: u64 = 60 * 1; // 1 hour in seconds
+pub const REBALANCE_GAS_LIMIT: u64 = 15_000_000; // assumed when the rebalance gas can not be estimated
+pub const GAS_LIMIT_MARGIN_BPS: u64 = 2_000; // margin over the estimated gas, Hedera charges at least 80% of the limit
+pub const HBAR_FEE_MARGIN_BPS: u64 = 1_000; // margin over the SaucerSwap mint fee for exchange rate moves
+pub const EXCHANGE_RATE_PRECOMPILE_ADDRESS: &str = "0x0000000000000000000000000000000000000168";
+pub const MAX_REBALANCE_PLANS_PER_VAULT: usize = 100;
+pub const POLICY_REEVALUATE_INTERVAL_SECONDS: u64 = 15 * 60; // default wait before asking the strategies again about a price they did not move for
+pub const MAX_BACKTEST_DECISIONS: usize = 1_000; // strategy decisions of one backtest run
+pub const MAX_AI_JOURNAL_RECORDS_PER_VAULT: usize = 500; // AI decisions kept per vault, in memory and in its journal file
+pub const POOL_SNAPSHOT_BITMAP_WORDS_AROUND: i16 = 2; // tick bitmap words fetched on each side of the current one
+pub const TX_RECEIPT_TIMEOUT_SECONDS: u64 = 30; // wait for a receipt before replacing the transaction
+pub const TX_RECEIPT_POLL_INTERVAL_MS: u64 = 1_000;
+pub const MAX_TX_REPLACEMENTS: u32 = 3;
+pub const TX_REPLACEMENT_GAS_PRICE_BUMP_BPS: u64 = 2_000; // gas price increase of each replacement
+pub const RPC_MAX_RETRIES: u32 = 5;
+pub const RPC_INITIAL_BACKOFF_MS: u64 = 500; // doubled on each retry
+pub const RPC_MAX_BACKOFF_MS: u64 = 10_000;
+pub const RPC_UNHEALTHY_AFTER_FAILURES: u32 = 3; // failures in a row before an endpoint is moved to the back
+pub const RPC_UNHEALTHY_COOLDOWN_SECONDS: u64 = 30; // an unhealthy endpoint is tried again after this delay
+pub const RPC_MAX_BLOCK_LAG: u64 = 5; // blocks two endpoints can be apart when cross checking reads
+pub const CROSS_CHECK_MAX_TICK_DEVIATION: i32 = 10; // ticks the live pool tick can move away from the cross checked one
+pub const TWAP_WINDOW_SECONDS: u32 = 300; // default window of the TWAP the pool tick is checked against
+pub const MAX_TWAP_DEVIATION_TICKS: u32 = 100; // default ticks (~1%) the pool tick can be away from its TWAP
+pub const SWAP_OHLCV_INTERVAL_SECONDS: u64 = 24 * 60 * 60; // default length of the candles built from swaps, daily like CoinGecko
+pub const SWAP_OHLCV_LOOKBACK_BLOCKS: u64 = 7 * 24 * 60 * 30; // a week of ~2s Hedera blocks, scanned when a pool has no local candles
+pub const SWAP_LOGS_BLOCK_RANGE: u64 = 1_000; // blocks per eth_getLogs request, the Hedera relays cap the range
+pub const COINGECKO_API_URL: &str = "https://api.coingecko.com/api/v3";
+pub const COINGECKO_PRO_API_URL: &str = "https://pro-api.coingecko.com/api/v3";
+pub const COINGECKO_NETWORK_ID: &str = "hedera-hashgraph";
+pub const COINGECKO_OHLCV_LIMIT: u32 = 1_000; // max candles per request
+pub const COINGECKO_MAX_RETRIES: u32 = 4; // retries of a rate limited or failed request
+pub const COINGECKO_INITIAL_BACKOFF_MS: u64 = 2_000; // doubled on each retry, unless the API sends a Retry-After
+pub const COINGECKO_MAX_BACKOFF_MS: u64 = 60_000;
+pub const AI_STRATEGY_MAX_RETRIES: u32 = 2; // re-prompts of the AI strategy after an invalid answer
+pub const FEATURE_VOLATILITY_WINDOWS: [usize; 3] = [7, 30, 90]; // candles of each realized volatility window
+pub const FEATURE_ATR_PERIOD: usize = 14; // candles of the average true range
+pub const FEATURE_TREND_WINDOW: usize = 30; // candles the trend line is fitted on
+pub const FEATURE_RANGE_WINDOW: usize = 90; // candles of the close percentiles, volume profile and time in range
+pub const FEATURE_VOLUME_PROFILE_BUCKETS: usize = 10;
+pub const AI_PROMPT_MAX_CANDLES: usize = 60; // the candles of the AI prompt are merged down to this count
+pub const AI_MAX_RANGE_DISTANCE_BPS: u32 = 500; // distance from the current price to an AI range that does not contain it
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn toml_config() -> TomlConfig {
+        let toml_config: TomlConfig = toml::from_str(include_str!("testnet.toml")).unwrap();
+        validate_toml_config(&toml_config).unwrap();
+        toml_config
+    }
+
+    fn assert_rejected(toml_config: &TomlConfig, message: &str) {
+        let error = validate_toml_config(toml_config).unwrap_err().to_string();
+        assert!(
+            error.contains(message),
+            "{:?} does not contain {:?}",
+            error,
+            message
+        );
+    }
+
+    #[test]
+    fn test_rejects_missing_rpc_urls() {
+        let mut toml_config = toml_config();
+        toml_config.rpc_urls.clear();
+        assert_rejected(&toml_config, "rpc_urls must contain at least one url");
+    }
+
+    #[test]
+    fn test_rejects_bad_vault_address() {
+        let mut toml_config = toml_config();
+        toml_config.vaults[0].address = "0x1234".to_string();
+        assert_rejected(&toml_config, "Invalid vault address");
+    }
+
+    #[test]
+    fn test_rejects_min_width_over_max_width() {
+        let mut toml_config = toml_config();
+        toml_config.volatility.min_width_spacings = toml_config.volatility.max_width_spacings + 1;
+        assert_rejected(&toml_config, "volatility.min_width_spacings");
+
+        let mut toml_config = self::toml_config();
+        toml_config.ensemble.min_width_spacings = toml_config.ensemble.max_width_spacings + 1;
+        assert_rejected(&toml_config, "ensemble.min_width_spacings");
+    }
+
+    #[test]
+    fn test_rejects_deterministic_strategy_outside_the_ensemble() {
+        let mut toml_config = toml_config();
+        toml_config.ensemble.deterministic_strategy = "basic".to_string();
+        toml_config.ensemble.strategies = vec!["ai".to_string(), "volatility".to_string()];
+        assert_rejected(&toml_config, "must be one of ensemble.strategies");
+    }
+
+    #[test]
+    fn test_rejects_bad_policy_schedule() {
+        let mut toml_config = toml_config();
+        toml_config.vaults[0].policy.schedule = vec!["* 8-25 * * 1-5".to_string()];
+        assert_rejected(&toml_config, "policy");
+
+        toml_config.vaults[0].policy.schedule = vec!["* * *".to_string()];
+        assert_rejected(&toml_config, "policy");
+    }
+}
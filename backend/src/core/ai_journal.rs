@@ -0,0 +1,332 @@
+/*
+    Journal of the AI strategy calls, to audit why a vault moved. Each call is recorded with its prompt inputs, the raw
+    answers of the model, the parsed decision and the action finally taken on it.
+    Records are appended to `ai_journal/<vault>.jsonl`, a later line with the same id replaces the earlier one, and the
+    files are compacted to the last `MAX_AI_JOURNAL_RECORDS_PER_VAULT` records when loaded at startup.
+*/
+
+use std::{
+    collections::VecDeque,
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use color_eyre::eyre::{Context, Result};
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+use utoipa::ToSchema;
+
+use crate::{
+    config::MAX_AI_JOURNAL_RECORDS_PER_VAULT, helpers::math::features::MarketFeatures,
+    types::AiStrategyResponse,
+};
+
+/// Directory where the journal of each vault is stored
+pub const AI_JOURNAL_DIR: &str = "ai_journal";
+
+/// Pool and position state the AI strategy prompt was built from
+#[derive(Serialize, Deserialize, Debug, Clone, Default, ToSchema)]
+pub struct AiPromptInputs {
+    pub current_price: f64,
+    /// 0 when the vault has no position
+    pub current_lower_price: f64,
+    pub current_upper_price: f64,
+    pub tick_spacing: i32,
+    pub fee: f64,
+    pub candles: usize,
+    pub first_candle_timestamp: Option<i64>,
+    pub last_candle_timestamp: Option<i64>,
+    /// Features of the candles given to the model
+    #[serde(default)]
+    pub features: MarketFeatures,
+}
+
+/// What was done with an AI decision
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AiDecisionAction {
+    /// The decision is not applied yet
+    Pending,
+    /// The model gave no valid answer, there is nothing to apply
+    Failed,
+    /// The model recommends to keep the current range
+    KeepRange,
+    /// The recommended range is the range of the vault
+    AlreadyInRange,
+    /// The ensemble did not follow the decision
+    Rejected {
+        reason: String,
+    },
+    NotProfitable,
+    /// Execution is disabled for the vault, only the plan was recorded
+    DryRun,
+    Executed {
+        tx_hash: String,
+    },
+    /// The rebalance could not be sent or failed
+    Error {
+        error: String,
+    },
+}
+
+/// One AI strategy call
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct AiDecisionRecord {
+    pub id: String,
+    pub vault_address: String,
+    pub created_at: String,
+    /// `provider/model`
+    pub model: String,
+    pub inputs: AiPromptInputs,
+    /// Raw answers of the model, the re-prompted ones included
+    pub responses: Vec<String>,
+    pub decision: Option<AiStrategyResponse>,
+    /// Why no valid decision was parsed
+    pub error: Option<String>,
+    pub latency_ms: u64,
+    pub action: AiDecisionAction,
+}
+
+/// Last AI decisions of each vault, keyed by lowercase vault address
+pub struct AiJournal {
+    dir: PathBuf,
+    records: DashMap<String, VecDeque<AiDecisionRecord>>,
+}
+
+impl AiJournal {
+    /// Load the journal files of `dir`, creating it when missing
+    pub fn load(dir: impl AsRef<Path>) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir).wrap_err_with(|| format!("Failed to create {}", dir.display()))?;
+
+        let records = DashMap::new();
+
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path
+                .extension()
+                .is_none_or(|extension| extension != "jsonl")
+            {
+                continue;
+            }
+
+            let vault_records = load_journal_file(&path)?;
+
+            // Compact the file to the records kept in memory
+            write_journal_file(&path, &vault_records)?;
+
+            if let Some(record) = vault_records.front() {
+                records.insert(record.vault_address.to_lowercase(), vault_records);
+            }
+        }
+
+        info!("Loaded the AI decisions of {} vaults", records.len());
+
+        Ok(Self { dir, records })
+    }
+
+    /// Add a record, only the last `MAX_AI_JOURNAL_RECORDS_PER_VAULT` records of a vault are kept
+    pub fn record(&self, record: AiDecisionRecord) {
+        let vault = record.vault_address.to_lowercase();
+        let mut vault_records = self.records.entry(vault.clone()).or_default();
+
+        self.append(&vault, &record);
+
+        vault_records.push_back(record);
+        while vault_records.len() > MAX_AI_JOURNAL_RECORDS_PER_VAULT {
+            vault_records.pop_front();
+        }
+    }
+
+    /// Set the action taken on a recorded decision
+    pub fn set_action(&self, vault_address: &str, id: &str, action: AiDecisionAction) {
+        let vault = vault_address.to_lowercase();
+        let Some(mut vault_records) = self.records.get_mut(&vault) else {
+            warn!("No AI decision {} recorded for vault {}", id, vault_address);
+            return;
+        };
+
+        let Some(record) = vault_records.iter_mut().find(|record| record.id == id) else {
+            warn!("No AI decision {} recorded for vault {}", id, vault_address);
+            return;
+        };
+
+        record.action = action;
+        let record = record.clone();
+
+        self.append(&vault, &record);
+    }
+
+    /// Records of a vault, or of all vaults, newest first
+    pub fn records(
+        &self,
+        vault_address: Option<&str>,
+        limit: Option<usize>,
+    ) -> Vec<AiDecisionRecord> {
+        let vault = vault_address.map(|address| address.to_lowercase());
+
+        let mut records = self
+            .records
+            .iter()
+            .filter(|entry| vault.as_ref().is_none_or(|vault| entry.key() == vault))
+            .flat_map(|entry| entry.value().iter().cloned().collect::<Vec<_>>())
+            .collect::<Vec<AiDecisionRecord>>();
+
+        records.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+        if let Some(limit) = limit {
+            records.truncate(limit);
+        }
+
+        records
+    }
+
+    /// Write errors are only logged, they must not stop the vault management
+    fn append(&self, vault: &str, record: &AiDecisionRecord) {
+        let path = self.dir.join(format!("{}.jsonl", vault));
+        if let Err(e) = append_journal_line(&path, record) {
+            warn!(
+                "Failed to write AI decision {} to {}: {:?}",
+                record.id,
+                path.display(),
+                e
+            );
+        }
+    }
+}
+
+fn append_journal_line(path: &Path, record: &AiDecisionRecord) -> Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", serde_json::to_string(record)?)?;
+
+    Ok(())
+}
+
+fn write_journal_file(path: &Path, records: &VecDeque<AiDecisionRecord>) -> Result<()> {
+    let mut content = String::new();
+    for record in records {
+        content.push_str(&serde_json::to_string(record)?);
+        content.push('\n');
+    }
+
+    fs::write(path, content).wrap_err_with(|| format!("Failed to write {}", path.display()))
+}
+
+/// Last records of a journal file, a line replaces the earlier ones with the same id
+fn load_journal_file(path: &Path) -> Result<VecDeque<AiDecisionRecord>> {
+    let content =
+        fs::read_to_string(path).wrap_err_with(|| format!("Failed to read {}", path.display()))?;
+
+    let mut records: VecDeque<AiDecisionRecord> = VecDeque::new();
+
+    for (index, line) in content.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        // A line cut by a crash while it was written is dropped
+        let record = match serde_json::from_str::<AiDecisionRecord>(line) {
+            Ok(record) => record,
+            Err(e) => {
+                warn!("Skipping line {} of {}: {}", index + 1, path.display(), e);
+                continue;
+            }
+        };
+
+        match records.iter_mut().find(|existing| existing.id == record.id) {
+            Some(existing) => *existing = record,
+            None => records.push_back(record),
+        }
+    }
+
+    while records.len() > MAX_AI_JOURNAL_RECORDS_PER_VAULT {
+        records.pop_front();
+    }
+
+    Ok(records)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn record(id: &str, vault_address: &str, created_at: &str) -> AiDecisionRecord {
+        AiDecisionRecord {
+            id: id.to_string(),
+            vault_address: vault_address.to_string(),
+            created_at: created_at.to_string(),
+            model: "mock/mock".to_string(),
+            inputs: AiPromptInputs::default(),
+            responses: vec!["{}".to_string()],
+            decision: None,
+            error: None,
+            latency_ms: 10,
+            action: AiDecisionAction::Pending,
+        }
+    }
+
+    #[test]
+    fn test_journal_reloads_last_action_of_each_record() {
+        let dir = std::env::temp_dir().join(format!("ai_journal_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        let journal = AiJournal::load(&dir).unwrap();
+        journal.record(record("a", "0xABC", "2025-01-01T00:00:00Z"));
+        journal.record(record("b", "0xabc", "2025-01-02T00:00:00Z"));
+        journal.record(record("c", "0xdef", "2025-01-03T00:00:00Z"));
+        journal.set_action(
+            "0xabc",
+            "a",
+            AiDecisionAction::Executed {
+                tx_hash: "0x01".to_string(),
+            },
+        );
+
+        let reloaded = AiJournal::load(&dir).unwrap();
+        let records = reloaded.records(Some("0xAbc"), None);
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].id, "b");
+        assert_eq!(records[0].action, AiDecisionAction::Pending);
+        assert_eq!(records[1].id, "a");
+        assert_eq!(
+            records[1].action,
+            AiDecisionAction::Executed {
+                tx_hash: "0x01".to_string()
+            }
+        );
+        assert_eq!(reloaded.records(None, Some(1))[0].id, "c");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_journal_keeps_last_records_of_a_vault() {
+        let dir = std::env::temp_dir().join(format!("ai_journal_max_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        let journal = AiJournal::load(&dir).unwrap();
+
+        for index in 0..MAX_AI_JOURNAL_RECORDS_PER_VAULT + 5 {
+            journal.record(record(
+                &index.to_string(),
+                "0xabc",
+                &format!("2025-01-01T00:00:{:02}Z", index % 60),
+            ));
+        }
+
+        let records = journal.records(Some("0xabc"), None);
+        assert_eq!(records.len(), MAX_AI_JOURNAL_RECORDS_PER_VAULT);
+        assert!(records.iter().all(|record| record.id != "0"));
+
+        // The file is compacted to the kept records on load
+        let reloaded = AiJournal::load(&dir).unwrap();
+        assert_eq!(
+            reloaded.records(Some("0xabc"), None).len(),
+            MAX_AI_JOURNAL_RECORDS_PER_VAULT
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
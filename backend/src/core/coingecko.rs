@@ -1,43 +1,488 @@
-use crate::{config::CONFIG, types::{CoingeckoOhlcvRes, VaultDetails}};
-use color_eyre::eyre::Result;
-use reqwest::header::{ACCEPT, HeaderMap, HeaderValue};
-use serde_json::Value;
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
+use chrono::Utc;
+use color_eyre::eyre::{Context, Result, eyre};
+use dashmap::DashMap;
+use once_cell::sync::Lazy;
+use reqwest::{
+    StatusCode,
+    header::{ACCEPT, RETRY_AFTER},
+};
+use serde::{Deserialize, Serialize};
+use tokio::time::sleep;
+use tracing::{debug, warn};
+
+use crate::{
+    config::{
+        CONFIG, COINGECKO_API_URL, COINGECKO_INITIAL_BACKOFF_MS, COINGECKO_MAX_BACKOFF_MS,
+        COINGECKO_MAX_RETRIES, COINGECKO_NETWORK_ID, COINGECKO_OHLCV_LIMIT, COINGECKO_PRO_API_URL,
+    },
+    core::rpc_retry::RetryPolicy,
+    types::{CoingeckoOhlcvRes, VaultDetails},
+};
+
+/// Directory where the CoinGecko responses are cached between restarts
+pub const COINGECKO_CACHE_DIR: &str = "coingecko_cache";
+
+/// Client shared by the strategies, the volume estimate and the backtests
+pub static COINGECKO_CLIENT: Lazy<CoingeckoClient> = Lazy::new(|| {
+    let base_url = if CONFIG.is_coingecko_pro {
+        COINGECKO_PRO_API_URL
+    } else {
+        COINGECKO_API_URL
+    };
+
+    CoingeckoClient::new(
+        base_url,
+        &CONFIG.coingecko_api_key,
+        CONFIG.is_coingecko_pro,
+        Some(PathBuf::from(COINGECKO_CACHE_DIR)),
+    )
+});
+
+/// Length of the candles of a CoinGecko OHLCV request
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum OhlcvTimeframe {
+    Minute,
+    Hour,
+    Day,
+}
+
+impl OhlcvTimeframe {
+    /// Timeframe of candles of `seconds`, CoinGecko only has minute, hour and day candles
+    pub fn from_seconds(seconds: u64) -> Option<Self> {
+        [
+            OhlcvTimeframe::Minute,
+            OhlcvTimeframe::Hour,
+            OhlcvTimeframe::Day,
+        ]
+        .into_iter()
+        .find(|timeframe| timeframe.seconds() == seconds)
+    }
+
+    pub fn seconds(&self) -> u64 {
+        match self {
+            OhlcvTimeframe::Minute => 60,
+            OhlcvTimeframe::Hour => 60 * 60,
+            OhlcvTimeframe::Day => 24 * 60 * 60,
+        }
+    }
+
+    /// How long a response is reused. Long enough to not refetch 1000 candles on every monitor cycle, short
+    /// enough to see the current candle move.
+    pub fn cache_ttl(&self) -> Duration {
+        match self {
+            OhlcvTimeframe::Minute => Duration::from_secs(60),
+            OhlcvTimeframe::Hour => Duration::from_secs(5 * 60),
+            OhlcvTimeframe::Day => Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+impl fmt::Display for OhlcvTimeframe {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let timeframe = match self {
+            OhlcvTimeframe::Minute => "minute",
+            OhlcvTimeframe::Hour => "hour",
+            OhlcvTimeframe::Day => "day",
+        };
+
+        write!(f, "{}", timeframe)
+    }
+}
+
+/// A response and when it was fetched, stored in memory and on disk
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct CachedOhlcv {
+    fetched_at: i64,
+    response: CoingeckoOhlcvRes,
+}
+
+impl CachedOhlcv {
+    fn is_fresh(&self, timeframe: OhlcvTimeframe) -> bool {
+        let age_seconds = Utc::now().timestamp() - self.fetched_at;
+
+        age_seconds >= 0 && (age_seconds as u64) < timeframe.cache_ttl().as_secs()
+    }
+}
+
+/// CoinGecko onchain API client.
+///
+/// OHLCV responses are cached by pool, token and timeframe, in memory and in `cache_dir` when set. Rate limited (429)
+/// and failed requests are retried with a backoff, and the last cached response is used when they keep failing.
+pub struct CoingeckoClient {
+    http: reqwest::Client,
+    base_url: String,
+    api_key: String,
+    is_pro: bool,
+    cache_dir: Option<PathBuf>,
+    cache: DashMap<String, CachedOhlcv>,
+    retry_policy: RetryPolicy,
+}
+
+impl CoingeckoClient {
+    pub fn new(base_url: &str, api_key: &str, is_pro: bool, cache_dir: Option<PathBuf>) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            api_key: api_key.to_string(),
+            is_pro,
+            cache_dir,
+            cache: DashMap::new(),
+            retry_policy: RetryPolicy {
+                max_retries: COINGECKO_MAX_RETRIES,
+                initial_backoff: Duration::from_millis(COINGECKO_INITIAL_BACKOFF_MS),
+                max_backoff: Duration::from_millis(COINGECKO_MAX_BACKOFF_MS),
+            },
+        }
+    }
+
+    /// Candles of `pool_address` with prices of `token_address` in the other pool token
+    pub async fn get_pool_ohlcv(
+        &self,
+        pool_address: &str,
+        token_address: &str,
+        timeframe: OhlcvTimeframe,
+    ) -> Result<CoingeckoOhlcvRes> {
+        let pool_address = pool_address.to_lowercase();
+        let token_address = token_address.to_lowercase();
+        let cache_key = ohlcv_cache_key(&pool_address, &token_address, timeframe);
+
+        let cached = self
+            .cache
+            .get(&cache_key)
+            .map(|cached| cached.clone())
+            .or_else(|| self.read_cache_file(&cache_key));
+
+        if let Some(cached) = &cached
+            && cached.is_fresh(timeframe)
+        {
+            debug!("Using cached {} candles of pool {}", timeframe, pool_address);
+            return Ok(cached.response.clone());
+        }
+
+        let url = format!(
+            "{}/onchain/networks/{}/pools/{}/ohlcv/{}?limit={}&currency=token&token={}&include_empty_intervals=false",
+            self.base_url,
+            COINGECKO_NETWORK_ID,
+            pool_address,
+            timeframe,
+            COINGECKO_OHLCV_LIMIT,
+            token_address
+        );
+
+        let response = match self.get_with_backoff(&url).await {
+            Ok(response) => response,
+            Err(e) => match cached {
+                Some(cached) => {
+                    warn!(
+                        "CoinGecko request for pool {} failed, using the candles fetched at {}: {:?}",
+                        pool_address, cached.fetched_at, e
+                    );
+                    return Ok(cached.response);
+                }
+                None => return Err(e),
+            },
+        };
+
+        let cached = CachedOhlcv {
+            fetched_at: Utc::now().timestamp(),
+            response,
+        };
+
+        if let Err(e) = self.write_cache_file(&cache_key, &cached) {
+            warn!("Failed to write the CoinGecko cache of {}: {:?}", cache_key, e);
+        }
+        self.cache.insert(cache_key, cached.clone());
+
+        Ok(cached.response)
+    }
+
+    async fn get_with_backoff(&self, url: &str) -> Result<CoingeckoOhlcvRes> {
+        let key_header = if self.is_pro {
+            "x-cg-pro-api-key"
+        } else {
+            "x-cg-demo-api-key"
+        };
+
+        let mut retry = 0;
+
+        loop {
+            let response = self
+                .http
+                .get(url)
+                .header(ACCEPT, "application/json")
+                .header(key_header, &self.api_key)
+                .send()
+                .await;
+
+            let (error, retry_after) = match response {
+                Ok(response) if response.status().is_success() => {
+                    return response
+                        .json()
+                        .await
+                        .with_context(|| format!("Invalid CoinGecko response for {}", url));
+                }
+                Ok(response)
+                    if response.status() == StatusCode::TOO_MANY_REQUESTS
+                        || response.status().is_server_error() =>
+                {
+                    let retry_after = response
+                        .headers()
+                        .get(RETRY_AFTER)
+                        .and_then(|value| value.to_str().ok())
+                        .and_then(|value| value.parse::<u64>().ok())
+                        .map(Duration::from_secs);
+
+                    (eyre!("CoinGecko returned {}", response.status()), retry_after)
+                }
+                Ok(response) => {
+                    let status = response.status();
+                    let body = response.text().await.unwrap_or_default();
+
+                    return Err(eyre!("CoinGecko returned {}: {}", status, body));
+                }
+                Err(e) => (eyre!("CoinGecko request failed: {}", e), None),
+            };
+
+            if retry >= self.retry_policy.max_retries {
+                return Err(error);
+            }
+
+            let backoff = retry_after
+                .unwrap_or_else(|| self.retry_policy.backoff(retry))
+                .min(self.retry_policy.max_backoff);
+
+            warn!(
+                "{}, retry {}/{} in {:?}",
+                error,
+                retry + 1,
+                self.retry_policy.max_retries,
+                backoff
+            );
+
+            sleep(backoff).await;
+            retry += 1;
+        }
+    }
+
+    fn cache_file_path(&self, cache_key: &str) -> Option<PathBuf> {
+        self.cache_dir
+            .as_ref()
+            .map(|cache_dir| cache_dir.join(format!("{}.json", cache_key)))
+    }
+
+    fn read_cache_file(&self, cache_key: &str) -> Option<CachedOhlcv> {
+        let path = self.cache_file_path(cache_key)?;
+        let raw = fs::read_to_string(&path).ok()?;
+
+        match serde_json::from_str(&raw) {
+            Ok(cached) => Some(cached),
+            Err(e) => {
+                warn!("Ignoring invalid CoinGecko cache file {}: {}", path.display(), e);
+                None
+            }
+        }
+    }
+
+    fn write_cache_file(&self, cache_key: &str, cached: &CachedOhlcv) -> Result<()> {
+        let Some(path) = self.cache_file_path(cache_key) else {
+            return Ok(());
+        };
+
+        fs::create_dir_all(path.parent().unwrap_or(Path::new(".")))?;
+        fs::write(&path, serde_json::to_string(cached)?)?;
+
+        Ok(())
+    }
+}
+
+/// Key of the candles in the cache and name of their cache file, the prices depend on the quoted token
+fn ohlcv_cache_key(pool_address: &str, token_address: &str, timeframe: OhlcvTimeframe) -> String {
+    format!("{}_{}_{}", pool_address, token_address, timeframe)
+}
+
+/// Daily candles of the vault pool, prices of token0 in token1.
+/// CoinGecko lists the newest candle first, they are returned sorted oldest first like every other candle source.
 pub async fn get_pool_ohlcv_data(
     pool_address: &str,
     vault_details: &VaultDetails,
 ) -> Result<CoingeckoOhlcvRes> {
-    let network_id = "hedera-hashgraph";
-    // let network_id = "sei-evm";
-    let pool_address = pool_address.to_lowercase();
-    let token_0_address = vault_details.pool.token0.address.as_str();
+    let mut res = COINGECKO_CLIENT
+        .get_pool_ohlcv(
+            pool_address,
+            &vault_details.pool.token0.address,
+            OhlcvTimeframe::Day,
+        )
+        .await?;
+
+    res.data
+        .attributes
+        .ohlcv_list
+        .sort_by_key(|candle| candle.timestamp());
+
+    Ok(res)
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    };
+
+    use actix_web::{App, HttpResponse, HttpServer, web};
+
+    use super::*;
+
+    const POOL: &str = "0xc5b707348da504e9be1bd4e21525459830e7b11d";
+    const TOKEN: &str = "0x0000000000000000000000000000000000163b5a";
+    const OTHER_TOKEN: &str = "0x000000000000000000000000000000000006f89a";
+
+    /// Local stand-in for the CoinGecko API: rate limits the first request, then answers with one candle.
+    /// Returns its url and the number of requests received.
+    fn start_stand_in() -> (String, Arc<AtomicUsize>) {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let server_requests = requests.clone();
+
+        let server = HttpServer::new(move || {
+            let requests = server_requests.clone();
+
+            App::new().route(
+                "/api/v3/onchain/networks/{network}/pools/{pool}/ohlcv/{timeframe}",
+                web::get().to(move |req: actix_web::HttpRequest| {
+                    let requests = requests.clone();
+
+                    async move {
+                        if requests.fetch_add(1, Ordering::SeqCst) == 0 {
+                            return HttpResponse::TooManyRequests()
+                                .insert_header(("Retry-After", "0"))
+                                .finish();
+                        }
+
+                        if req.headers().get("x-cg-pro-api-key").is_none() {
+                            return HttpResponse::Unauthorized().finish();
+                        }
+
+                        HttpResponse::Ok().json(serde_json::json!({
+                            "data": {
+                                "id": req.match_info().get("timeframe").unwrap_or_default(),
+                                "attributes": { "ohlcv_list": [[1_700_000_000, 1.0, 1.2, 0.9, 1.1, 500.0]] }
+                            }
+                        }))
+                    }
+                }),
+            )
+        })
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+
+        let url = format!("http://{}/api/v3", server.addrs()[0]);
+        actix_web::rt::spawn(server.run());
+
+        (url, requests)
+    }
+
+    fn cache_dir(name: &str) -> PathBuf {
+        let cache_dir = std::env::temp_dir().join(format!(
+            "coingecko_cache_{}_{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&cache_dir);
+
+        cache_dir
+    }
+
+    #[actix_web::test]
+    async fn test_rate_limited_request_is_retried_and_cached() {
+        let (url, requests) = start_stand_in();
+        let cache_dir = cache_dir("retry");
+
+        let client = CoingeckoClient::new(&url, "key", true, Some(cache_dir.clone()));
 
-    let url = format!(
-        "https://api.coingecko.com/api/v3/onchain/networks/{}/pools/{}/ohlcv/day?limit=1000&currency=token&token={}&include_empty_intervals=false",
-        network_id, pool_address, token_0_address
-    );
+        let res = client
+            .get_pool_ohlcv(POOL, TOKEN, OhlcvTimeframe::Hour)
+            .await
+            .unwrap();
+        assert_eq!(res.data.id, "hour");
+        assert_eq!(res.data.attributes.ohlcv_list[0].volume(), 500.0);
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
 
-    let coingecko_api_key = &CONFIG.coingecko_api_key;
+        // Served from memory, then from disk by a new client
+        client
+            .get_pool_ohlcv(POOL, TOKEN, OhlcvTimeframe::Hour)
+            .await
+            .unwrap();
+        CoingeckoClient::new(&url, "key", true, Some(cache_dir.clone()))
+            .get_pool_ohlcv(POOL, TOKEN, OhlcvTimeframe::Hour)
+            .await
+            .unwrap();
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
 
-    // Set up headers
-    let mut headers = HeaderMap::new();
+        // Other timeframes are cached separately
+        let res = client
+            .get_pool_ohlcv(POOL, TOKEN, OhlcvTimeframe::Day)
+            .await
+            .unwrap();
+        assert_eq!(res.data.id, "day");
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
 
-    headers.insert(ACCEPT, HeaderValue::from_static("application/json"));
-    headers.insert(
-        "x-cg-demo-api-key",
-        HeaderValue::from_str(&coingecko_api_key)?,
-    );
+        // And so are the prices of the other token of the pool
+        client
+            .get_pool_ohlcv(POOL, OTHER_TOKEN, OhlcvTimeframe::Hour)
+            .await
+            .unwrap();
+        assert_eq!(requests.load(Ordering::SeqCst), 4);
+        assert!(
+            cache_dir
+                .join(format!(
+                    "{}.json",
+                    ohlcv_cache_key(POOL, OTHER_TOKEN, OhlcvTimeframe::Hour)
+                ))
+                .exists()
+        );
 
-    // Make request
-    let client = reqwest::Client::new();
+        let _ = fs::remove_dir_all(&cache_dir);
+    }
 
-    let response = client.get(url).headers(headers).send().await?;
+    #[actix_web::test]
+    async fn test_stale_cache_is_used_when_requests_fail() {
+        let (url, _) = start_stand_in();
+        let cache_dir = cache_dir("stale");
 
-    let ohlcv_data_res: Value = response.json().await?;
+        // Demo keys are refused by the stand-in
+        let client = CoingeckoClient::new(&url, "key", false, Some(cache_dir.clone()));
+        assert!(
+            client
+                .get_pool_ohlcv(POOL, TOKEN, OhlcvTimeframe::Minute)
+                .await
+                .is_err()
+        );
 
-    // println!("ohlcv_data_res: {:?}", ohlcv_data_res);
+        client.cache.insert(
+            ohlcv_cache_key(POOL, TOKEN, OhlcvTimeframe::Minute),
+            CachedOhlcv {
+                fetched_at: 0,
+                response: serde_json::from_value(serde_json::json!({
+                    "data": { "id": "stale", "attributes": { "ohlcv_list": [] } }
+                }))
+                .unwrap(),
+            },
+        );
 
-    let ohlcv_data: CoingeckoOhlcvRes = serde_json::from_value(ohlcv_data_res)?;
+        let res = client
+            .get_pool_ohlcv(POOL, TOKEN, OhlcvTimeframe::Minute)
+            .await
+            .unwrap();
+        assert_eq!(res.data.id, "stale");
 
-    Ok(ohlcv_data)
+        let _ = fs::remove_dir_all(&cache_dir);
+    }
 }
@@ -5,7 +5,7 @@ use serde::{Deserialize, Serialize};
 
 use color_eyre::eyre::Result;
 
-use crate::state::START_TIMESTAMP;
+use crate::{helpers::amount::TokenAmount, state::START_TIMESTAMP};
 
 #[derive(Serialize)]
 pub struct RebalanceLogEntry {
@@ -13,21 +13,25 @@ pub struct RebalanceLogEntry {
     pub vault_address: String,
     pub transaction_hash: String,
     pub transaction_status: String,
-    pub tvl0: f64,
-    pub tvl1: f64,
-    pub fees0_bef: f64,
-    pub fees1_bef: f64,
+    pub tvl0: TokenAmount,
+    pub tvl1: TokenAmount,
+    pub fees0_bef: TokenAmount,
+    pub fees1_bef: TokenAmount,
     pub current_tick: i32,
     pub lower_tick_bef: i32,
     pub upper_tick_bef: i32,
     pub lower_tick_aft: i32,
     pub upper_tick_aft: i32,
-    pub amount0_bef: f64,
-    pub amount1_bef: f64,
+    pub amount0_bef: TokenAmount,
+    pub amount1_bef: TokenAmount,
     pub liquidity_bef: u128,
-    pub swap_amount_out: f64,
-    pub swap_max_amount_in: f64,
+    pub swap_amount_out: TokenAmount,
+    pub swap_max_amount_in: TokenAmount,
     pub is_swap_0_to_1: bool,
+    pub gas_limit: u64,
+    pub hbar_value_sent: f64,
+    pub hbar_mint_fee: f64,
+    pub vault_hbar_balance_after: f64,
 }
 
 pub fn log_rebalance_result_to_csv(entry: RebalanceLogEntry) -> Result<()> {
@@ -2,57 +2,77 @@ use std::{
     fs,
     process::{Command, Stdio},
     str::FromStr,
+    sync::Arc,
     time::Duration,
 };
 
-use alloy::{providers::ProviderBuilder, signers::local::PrivateKeySigner};
+use alloy::{
+    providers::ProviderBuilder, rpc::client::ClientBuilder as RpcClientBuilder,
+    signers::local::PrivateKeySigner,
+};
 
 use color_eyre::eyre::Result;
 use mcp_core::{client::ClientBuilder, transport::ClientSseTransportBuilder};
 use reqwest::Client;
 use rig::{
-    agent::Agent,
-    client::ProviderClient,
+    agent::{Agent, AgentBuilder},
     completion::Prompt,
-    providers::{self, gemini::completion::CompletionModel, gemini::completion::GEMINI_2_0_FLASH},
 };
 use tokio::time::sleep;
 use tracing::info;
 
 use crate::{
     config::CONFIG,
-    core,
+    core::{
+        self,
+        llm::LlmModel,
+        rpc_pool::{FailoverService, RpcPool},
+        rpc_retry::{RetryPolicy, RpcRetryLayer, retry_with_backoff},
+    },
     types::{EvmProvider, WebAppState},
 };
 
-pub async fn init_evm_provider() -> Result<EvmProvider> {
+pub async fn init_evm_provider(rpc_pool: Arc<RpcPool>) -> Result<EvmProvider> {
     let private_key = CONFIG.private_key.as_str();
     let chain_id = CONFIG.toml_config.chain_id;
-    let rpc_url = CONFIG.toml_config.rpc_url.as_str();
 
     let evm_signer = PrivateKeySigner::from_str(private_key)?;
 
-    // Init provider with the specified rpc url in config
+    // Every request goes to the best endpoint of the pool and is retried on transient errors
+    let rpc_client = RpcClientBuilder::default()
+        .layer(RpcRetryLayer::new(RetryPolicy::default()))
+        .transport(FailoverService::new(rpc_pool), false);
+
+    // Init provider with the rpc urls in config
     let evm_provider = ProviderBuilder::new()
         .with_chain_id(chain_id)
         .wallet(evm_signer)
-        .connect(rpc_url)
-        .await?;
+        .connect_client(rpc_client);
 
     Ok(evm_provider)
 }
 
 pub async fn init_all_vaults(app_state: &WebAppState) -> Result<()> {
     let provider = &app_state.evm_provider;
-    let all_vaults_addresses = CONFIG.toml_config.vaults.clone();
+    let all_vaults_configs = &CONFIG.toml_config.vaults;
     let all_vaults = &app_state.all_vaults;
 
-    // TODO: add retry mechanism for each vault fetch
-    for vault_address in all_vaults_addresses {
+    // Make sure every configured strategy is registered before starting any vault loop
+    app_state.strategies.check_config(&CONFIG.toml_config)?;
+
+    for vault_config in all_vaults_configs {
+        let vault_address = vault_config.address.clone();
+
         // Fetch vault details and store them into the app state
         info!("Fetching vault details for address: {:?}...", vault_address);
 
-        let vault_details = core::vault::get_vault_details(provider, &vault_address).await?;
+        // The provider retries each request, the whole fetch is retried too in case the RPC stays down longer
+        let vault_details = retry_with_backoff(
+            &format!("Fetching vault details for {}", vault_address),
+            &RetryPolicy::default(),
+            || core::vault::get_vault_details(provider, &vault_address),
+        )
+        .await?;
 
         all_vaults.insert(vault_address.clone(), vault_details);
 
@@ -67,7 +87,7 @@ pub async fn init_all_vaults(app_state: &WebAppState) -> Result<()> {
     Ok(())
 }
 
-pub async fn init_ai_agent() -> Result<Agent<CompletionModel>> {
+pub async fn init_ai_agent() -> Result<Agent<LlmModel>> {
     // Build the mcp server first
     let mut build_mcp_child = Command::new("cargo")
         .arg("build")
@@ -148,9 +168,9 @@ pub async fn init_ai_agent() -> Result<Agent<CompletionModel>> {
         .await
         .map_err(|e| color_eyre::eyre::eyre!(e))?;
 
-    let completion_model = providers::gemini::Client::from_env();
+    let completion_model = core::llm::completion_model(&CONFIG.toml_config.llm)?;
 
-    let mut agent_builder = completion_model.agent(GEMINI_2_0_FLASH);
+    let mut agent_builder = AgentBuilder::new(completion_model);
 
     // Add MCP tools to the agent
     agent_builder = tools_list_res
@@ -0,0 +1,131 @@
+/*
+    Language models behind the AI strategy and the chat agent. The provider and model come from the `[llm]` table
+    of the config, every provider is wrapped in the same rig model handle so the agents don't depend on it.
+*/
+
+use std::sync::{
+    Arc,
+    atomic::{AtomicUsize, Ordering},
+};
+
+use color_eyre::eyre::Result;
+use rig::{
+    OneOrMany,
+    client::{CompletionClient, ProviderClient, completion::CompletionModelHandle},
+    completion::{
+        AssistantContent, CompletionError, CompletionModel, CompletionRequest, CompletionResponse,
+        Usage,
+    },
+    providers::{gemini, openai},
+    streaming::StreamingCompletionResponse,
+};
+
+use crate::types::{LlmConfig, LlmProvider};
+
+/// Model of any provider
+pub type LlmModel = CompletionModelHandle<'static>;
+
+/// Model of the configured provider
+pub fn completion_model(config: &LlmConfig) -> Result<LlmModel> {
+    let inner: Arc<dyn rig::completion::CompletionModelDyn> = match config.provider {
+        LlmProvider::Gemini => Arc::new(gemini::Client::from_env().completion_model(&config.model)),
+        LlmProvider::OpenaiCompatible => {
+            let api_key = std::env::var(&config.api_key_env).unwrap_or_default();
+
+            let mut client_builder = openai::Client::builder(&api_key);
+            if let Some(base_url) = &config.base_url {
+                client_builder = client_builder.base_url(base_url);
+            }
+
+            // Local servers implement the chat completions endpoint, not the responses one
+            Arc::new(
+                client_builder
+                    .build()?
+                    .completion_model(&config.model)
+                    .completions_api(),
+            )
+        }
+        LlmProvider::Mock => Arc::new(MockModel::new(vec![
+            config
+                .mock_reply
+                .clone()
+                .unwrap_or_else(|| MOCK_STRATEGY_REPLY.to_string()),
+        ])),
+    };
+
+    Ok(CompletionModelHandle { inner })
+}
+
+/// Default answer of the mock model: no rebalance, in the format the AI strategy asks for
+const MOCK_STRATEGY_REPLY: &str = r#"{"rebalance_required": false, "new_price_range": {"lower_price": 0.0, "upper_price": 0.0}, "analysis": "Mock model, keeping the current range.", "market_outlook": "Unknown", "confidence_score": 0.0}"#;
+
+/// Model answering `replies` in order, then the last one to every other prompt, without any network call
+#[derive(Clone)]
+pub struct MockModel {
+    replies: Vec<String>,
+    calls: Arc<AtomicUsize>,
+}
+
+impl MockModel {
+    pub fn new(replies: Vec<String>) -> Self {
+        Self {
+            replies,
+            calls: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+}
+
+impl CompletionModel for MockModel {
+    type Response = ();
+    type StreamingResponse = ();
+
+    async fn completion(
+        &self,
+        _request: CompletionRequest,
+    ) -> Result<CompletionResponse<()>, CompletionError> {
+        let call = self.calls.fetch_add(1, Ordering::SeqCst);
+        let reply = self
+            .replies
+            .get(call)
+            .or(self.replies.last())
+            .cloned()
+            .unwrap_or_default();
+
+        Ok(CompletionResponse {
+            choice: OneOrMany::one(AssistantContent::text(reply)),
+            usage: Usage::new(),
+            raw_response: (),
+        })
+    }
+
+    async fn stream(
+        &self,
+        _request: CompletionRequest,
+    ) -> Result<StreamingCompletionResponse<()>, CompletionError> {
+        Err(CompletionError::ProviderError(
+            "The mock model does not stream".to_string(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rig::{agent::AgentBuilder, completion::Prompt};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_mock_model_answers_without_network() {
+        let config = LlmConfig {
+            provider: LlmProvider::Mock,
+            mock_reply: Some("hello".to_string()),
+            ..LlmConfig::default()
+        };
+
+        let agent = AgentBuilder::new(completion_model(&config).unwrap())
+            .preamble("Say hello")
+            .build();
+
+        assert_eq!(agent.prompt("Hi").await.unwrap(), "hello");
+    }
+}
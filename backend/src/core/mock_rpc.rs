@@ -0,0 +1,126 @@
+use std::{
+    str::FromStr,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+use alloy::{
+    providers::ProviderBuilder,
+    rpc::{
+        client::RpcClient,
+        json_rpc::{
+            ErrorPayload, RequestPacket, Response, ResponsePacket, ResponsePayload,
+            SerializedRequest,
+        },
+    },
+    signers::local::PrivateKeySigner,
+    transports::{TransportError, TransportFut},
+};
+use serde_json::Value;
+use tower::Service;
+
+use crate::types::EvmProvider;
+
+pub const MOCK_CHAIN_ID: u64 = 296;
+
+type Handler = dyn Fn(&str, &Value) -> Result<Value, ErrorPayload> + Send + Sync;
+
+/// Transport answering each JSON-RPC request with a closure of the test, for the code that talks to a node.
+/// The method of every request is recorded, the requests of a batch one after the other.
+#[derive(Clone)]
+pub struct MockRpc {
+    handler: Arc<Handler>,
+    methods: Arc<Mutex<Vec<String>>>,
+}
+
+impl MockRpc {
+    /// - handler: Called with the method and the params of each request, returns its result or error
+    pub fn new(
+        handler: impl Fn(&str, &Value) -> Result<Value, ErrorPayload> + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            handler: Arc::new(handler),
+            methods: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Provider of the operator wallet built like `core::init::init_evm_provider`, on this transport
+    pub fn provider(&self) -> EvmProvider {
+        let signer = PrivateKeySigner::from_str(
+            "0x59c6995e998f97a5a0044966f0945389dc9e86dae88c7a8412f4603b6b78690d",
+        )
+        .unwrap();
+
+        ProviderBuilder::new()
+            .with_chain_id(MOCK_CHAIN_ID)
+            .wallet(signer)
+            .connect_client(RpcClient::new(self.clone(), true))
+    }
+
+    /// Count of the requests received so far with this method
+    pub fn count(&self, method: &str) -> usize {
+        self.methods
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|request_method| *request_method == method)
+            .count()
+    }
+
+    fn respond(&self, request: &SerializedRequest) -> Response {
+        let params = request
+            .params()
+            .map(|params| serde_json::from_str(params.get()).unwrap())
+            .unwrap_or(Value::Null);
+
+        self.methods
+            .lock()
+            .unwrap()
+            .push(request.method().to_string());
+
+        let payload = match (self.handler)(request.method(), &params) {
+            Ok(result) => {
+                ResponsePayload::Success(serde_json::value::to_raw_value(&result).unwrap())
+            }
+            Err(error) => ResponsePayload::Failure(error),
+        };
+
+        Response {
+            id: request.id().clone(),
+            payload,
+        }
+    }
+}
+
+impl Service<RequestPacket> for MockRpc {
+    type Response = ResponsePacket;
+    type Error = TransportError;
+    type Future = TransportFut<'static>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: RequestPacket) -> Self::Future {
+        let response = match &request {
+            RequestPacket::Single(request) => ResponsePacket::Single(self.respond(request)),
+            RequestPacket::Batch(requests) => ResponsePacket::Batch(
+                requests
+                    .iter()
+                    .map(|request| self.respond(request))
+                    .collect(),
+            ),
+        };
+
+        Box::pin(async move { Ok(response) })
+    }
+}
+
+/// JSON-RPC error of a node, e.g. `rpc_error(-32000, "nonce too low")`
+pub fn rpc_error(code: i64, message: &str) -> ErrorPayload {
+    ErrorPayload {
+        code,
+        message: message.to_string().into(),
+        data: None,
+    }
+}
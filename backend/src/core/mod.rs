@@ -1,5 +1,20 @@
+pub mod ai_journal;
 pub mod email;
 pub mod init;
+pub mod llm;
+#[cfg(test)]
+pub mod mock_rpc;
+pub mod multicall;
+pub mod oracle;
+pub mod plan;
+pub mod pool;
+pub mod profitability;
+pub mod rebalance_policy;
+pub mod rpc_pool;
+pub mod rpc_retry;
+pub mod swap_ohlcv;
+pub mod tx_costs;
+pub mod tx_sender;
 pub mod vault;
 pub mod vault_spawn;
 pub mod coingecko;
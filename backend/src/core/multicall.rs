@@ -0,0 +1,284 @@
+use std::{marker::PhantomData, str::FromStr};
+
+use alloy::{
+    eips::BlockId,
+    primitives::{Address, Bytes},
+    providers::{Provider, bindings::IMulticall3},
+    rpc::{client::BatchRequest, types::TransactionRequest},
+    sol_types::SolCall,
+};
+use color_eyre::eyre::{Result, eyre};
+use tracing::{trace, warn};
+
+use crate::{
+    config::CONFIG,
+    core::rpc_retry::{RpcErrorKind, classify_error},
+};
+
+/// Index of a call in a [`ReadBatch`], decodes its result with the return type of the call
+pub struct CallHandle<C> {
+    index: usize,
+    _call: PhantomData<C>,
+}
+
+/// View calls read together from a single block.
+///
+/// The calls are sent in one `aggregate3` call of the configured Multicall3 contract. When no multicall
+/// address is configured or the multicall fails (e.g. not deployed on the network), they are sent as one
+/// JSON-RPC batch of `eth_call`s pinned to the same block.
+#[derive(Default)]
+pub struct ReadBatch {
+    calls: Vec<(Address, Bytes)>,
+}
+
+/// Raw return data of the calls of a [`ReadBatch`], in the order they were added
+pub struct ReadResults {
+    return_data: Vec<Bytes>,
+}
+
+impl ReadBatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add<C: SolCall>(&mut self, target: Address, call: C) -> CallHandle<C> {
+        self.calls.push((target, call.abi_encode().into()));
+
+        CallHandle {
+            index: self.calls.len() - 1,
+            _call: PhantomData,
+        }
+    }
+
+    pub async fn execute<P>(self, provider: &P, block: BlockId) -> Result<ReadResults>
+    where
+        P: Provider,
+    {
+        self.execute_with(
+            provider,
+            block,
+            CONFIG.toml_config.multicall_address.as_deref(),
+        )
+        .await
+    }
+
+    /// - multicall_address: Multicall3 contract the calls are sent to, `None` sends them as a JSON-RPC batch
+    async fn execute_with<P>(
+        self,
+        provider: &P,
+        block: BlockId,
+        multicall_address: Option<&str>,
+    ) -> Result<ReadResults>
+    where
+        P: Provider,
+    {
+        trace!("Reading {} calls at block {:?}", self.calls.len(), block);
+
+        let return_data = match multicall_address {
+            Some(multicall_address) => {
+                match self
+                    .execute_multicall(provider, block, Address::from_str(multicall_address)?)
+                    .await
+                {
+                    Ok(return_data) => return_data,
+                    Err(e) if classify_error(&e) == RpcErrorKind::Transient => return Err(e),
+                    Err(e) => {
+                        warn!(
+                            "Multicall at {} failed, reading the calls with a JSON-RPC batch: {:?}",
+                            multicall_address, e
+                        );
+                        self.execute_json_rpc_batch(provider, block).await?
+                    }
+                }
+            }
+            None => self.execute_json_rpc_batch(provider, block).await?,
+        };
+
+        Ok(ReadResults { return_data })
+    }
+
+    async fn execute_multicall<P>(
+        &self,
+        provider: &P,
+        block: BlockId,
+        multicall_address: Address,
+    ) -> Result<Vec<Bytes>>
+    where
+        P: Provider,
+    {
+        let calls = self
+            .calls
+            .iter()
+            .map(|(target, call_data)| IMulticall3::Call3 {
+                target: *target,
+                allowFailure: false,
+                callData: call_data.clone(),
+            })
+            .collect();
+
+        let tx = TransactionRequest::default()
+            .to(multicall_address)
+            .input(IMulticall3::aggregate3Call { calls }.abi_encode().into());
+
+        let output = provider.call(tx).block(block).await?;
+        let results = IMulticall3::aggregate3Call::abi_decode_returns(&output)?;
+
+        if results.len() != self.calls.len() {
+            return Err(eyre!(
+                "Multicall returned {} results for {} calls",
+                results.len(),
+                self.calls.len()
+            ));
+        }
+
+        Ok(results
+            .into_iter()
+            .map(|result| result.returnData)
+            .collect())
+    }
+
+    async fn execute_json_rpc_batch<P>(&self, provider: &P, block: BlockId) -> Result<Vec<Bytes>>
+    where
+        P: Provider,
+    {
+        let mut batch = BatchRequest::new(provider.client());
+
+        let waiters = self
+            .calls
+            .iter()
+            .map(|(target, call_data)| {
+                let tx = TransactionRequest::default()
+                    .to(*target)
+                    .input(call_data.clone().into());
+
+                batch.add_call::<_, Bytes>("eth_call", &(tx, block))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        batch.send().await?;
+
+        let mut return_data = Vec::with_capacity(waiters.len());
+        for waiter in waiters {
+            return_data.push(waiter.await?);
+        }
+
+        Ok(return_data)
+    }
+}
+
+impl ReadResults {
+    pub fn get<C: SolCall>(&self, handle: &CallHandle<C>) -> Result<C::Return> {
+        let return_data = self
+            .return_data
+            .get(handle.index)
+            .ok_or_else(|| eyre!("No result for call {} of the batch", handle.index))?;
+
+        Ok(C::abi_decode_returns(return_data)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use alloy::{primitives::U256, sol};
+    use serde_json::json;
+
+    use super::*;
+    use crate::core::mock_rpc::{MOCK_CHAIN_ID, MockRpc, rpc_error};
+
+    sol! {
+        function balanceOf(address account) view returns (uint256);
+        function symbol() view returns (string memory);
+    }
+
+    #[test]
+    fn test_results_are_decoded_with_the_call_type() {
+        let account = Address::repeat_byte(1);
+
+        let mut batch = ReadBatch::new();
+        let balance = batch.add(Address::ZERO, balanceOfCall { account });
+        let symbol = batch.add(Address::ZERO, symbolCall {});
+
+        assert_eq!(batch.calls.len(), 2);
+        assert_eq!(batch.calls[0].1, balanceOfCall { account }.abi_encode());
+
+        let results = ReadResults {
+            return_data: vec![
+                balanceOfCall::abi_encode_returns(&U256::from(42)).into(),
+                symbolCall::abi_encode_returns(&"WHBAR".to_string()).into(),
+            ],
+        };
+
+        assert_eq!(results.get(&balance).unwrap(), U256::from(42));
+        assert_eq!(results.get(&symbol).unwrap(), "WHBAR");
+    }
+
+    /// Node without the multicall contract: its calls revert, or time out when `is_multicall_down`.
+    /// The balance of an account at any other contract is the last byte of the contract address.
+    fn mock_node(multicall_address: Address, is_multicall_down: bool) -> MockRpc {
+        MockRpc::new(move |method, params| match method {
+            "eth_call" => {
+                let to: Address = serde_json::from_value(params[0]["to"].clone()).unwrap();
+
+                match (to == multicall_address, is_multicall_down) {
+                    (true, false) => Err(rpc_error(3, "execution reverted")),
+                    (true, true) => Err(rpc_error(-32000, "Request timeout")),
+                    (false, _) => Ok(json!(Bytes::from(balanceOfCall::abi_encode_returns(
+                        &U256::from(to.0[19])
+                    )))),
+                }
+            }
+            "eth_chainId" => Ok(json!(format!("{:#x}", MOCK_CHAIN_ID))),
+            _ => Err(rpc_error(-32601, "method not found")),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_failed_multicall_falls_back_to_a_json_rpc_batch() {
+        let multicall_address = Address::repeat_byte(0xca);
+        let rpc = mock_node(multicall_address, false);
+        let account = Address::repeat_byte(1);
+
+        let mut batch = ReadBatch::new();
+        let balance0 = batch.add(Address::with_last_byte(7), balanceOfCall { account });
+        let balance1 = batch.add(Address::with_last_byte(9), balanceOfCall { account });
+
+        let results = batch
+            .execute_with(
+                &rpc.provider(),
+                BlockId::number(100),
+                Some(&multicall_address.to_string()),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(results.get(&balance0).unwrap(), U256::from(7));
+        assert_eq!(results.get(&balance1).unwrap(), U256::from(9));
+        // The multicall, then one batch with both calls
+        assert_eq!(rpc.count("eth_call"), 3);
+    }
+
+    #[tokio::test]
+    async fn test_transient_multicall_error_is_not_read_again_in_a_batch() {
+        let multicall_address = Address::repeat_byte(0xca);
+        let rpc = mock_node(multicall_address, true);
+
+        let mut batch = ReadBatch::new();
+        batch.add(
+            Address::with_last_byte(7),
+            balanceOfCall {
+                account: Address::repeat_byte(1),
+            },
+        );
+
+        let result = batch
+            .execute_with(
+                &rpc.provider(),
+                BlockId::number(100),
+                Some(&multicall_address.to_string()),
+            )
+            .await;
+
+        assert!(result.is_err());
+        assert_eq!(rpc.count("eth_call"), 1);
+    }
+}
@@ -0,0 +1,201 @@
+use std::str::FromStr;
+
+use alloy::{eips::BlockId, primitives::Address, providers::Provider};
+use color_eyre::eyre::{Result, eyre};
+use serde::Serialize;
+use tracing::{debug, warn};
+use utoipa::ToSchema;
+
+use crate::{
+    core::{multicall::ReadBatch, vault::UniswapV3Pool},
+    helpers::{
+        amount::TokenAmount,
+        math::oracle::{consult, get_quote_at_tick},
+    },
+    types::{Pool, VaultConfig, VaultDetails},
+};
+
+/// Pool tick and its time-weighted average read at the same block
+#[derive(Serialize, Debug, Clone, ToSchema)]
+pub struct TwapReading {
+    pub spot_tick: i32,
+    pub twap_tick: i32,
+    pub window_seconds: u32,
+    /// Harmonic mean of the pool liquidity over the window
+    pub harmonic_mean_liquidity: u128,
+    /// Token1 received for one token0 at the TWAP tick
+    #[schema(value_type = String)]
+    pub twap_quote1: TokenAmount,
+}
+
+impl TwapReading {
+    pub fn deviation_ticks(&self) -> u32 {
+        self.spot_tick.abs_diff(self.twap_tick)
+    }
+}
+
+/// Read the pool tick and its TWAP over the last `window_seconds` from the pool oracle.
+/// Errors when the pool observations do not go back that far.
+pub async fn read_twap<P>(provider: &P, pool: &Pool, window_seconds: u32) -> Result<TwapReading>
+where
+    P: Provider,
+{
+    let pool_address = Address::from_str(&pool.address)?;
+
+    let mut batch = ReadBatch::new();
+    let slot0 = batch.add(pool_address, UniswapV3Pool::slot0Call {});
+    let observations = batch.add(
+        pool_address,
+        UniswapV3Pool::observeCall {
+            secondsAgos: vec![window_seconds, 0],
+        },
+    );
+
+    let results = batch.execute(provider, BlockId::latest()).await?;
+
+    let spot_tick = results.get(&slot0)?.tick.as_i32();
+    let observations = results.get(&observations)?;
+
+    let (tick_cumulatives, seconds_per_liquidity) = match (
+        observations.tickCumulatives.as_slice(),
+        observations.secondsPerLiquidityCumulativeX128s.as_slice(),
+    ) {
+        (
+            [tick_cumulative_0, tick_cumulative_1],
+            [seconds_per_liquidity_0, seconds_per_liquidity_1],
+        ) => (
+            [tick_cumulative_0.as_i64(), tick_cumulative_1.as_i64()],
+            [*seconds_per_liquidity_0, *seconds_per_liquidity_1],
+        ),
+        _ => {
+            return Err(eyre!(
+                "Pool {} returned unexpected observations",
+                pool_address
+            ));
+        }
+    };
+
+    let (twap_tick, harmonic_mean_liquidity) =
+        consult(window_seconds, tick_cumulatives, seconds_per_liquidity)?;
+
+    let twap_quote1 = get_quote_at_tick(
+        twap_tick,
+        10u128
+            .checked_pow(pool.token0.decimals as u32)
+            .ok_or_else(|| eyre!("Too many decimals for a quote: {}", pool.token0.decimals))?,
+        Address::from_str(&pool.token0.address)?,
+        Address::from_str(&pool.token1.address)?,
+    )?;
+
+    Ok(TwapReading {
+        spot_tick,
+        twap_tick,
+        window_seconds,
+        harmonic_mean_liquidity,
+        twap_quote1: TokenAmount::new(twap_quote1, pool.token1.decimals),
+    })
+}
+
+/// Make sure the pool price the rebalance acts on was not moved in the last blocks, e.g. by a flash loan.
+/// Errors when the pool tick is further than `max_twap_deviation_ticks` from its TWAP. Returns `None` when
+/// the check is disabled for the vault.
+pub async fn check_spot_against_twap<P>(
+    provider: &P,
+    vault_details: &VaultDetails,
+    vault_config: &VaultConfig,
+) -> Result<Option<TwapReading>>
+where
+    P: Provider,
+{
+    if vault_config.twap_window_seconds == 0 {
+        return Ok(None);
+    }
+
+    let reading = read_twap(
+        provider,
+        &vault_details.pool,
+        vault_config.twap_window_seconds,
+    )
+    .await?;
+
+    debug!(
+        "Pool {} tick {}, {}s TWAP tick {} ({} {} per {})",
+        vault_details.pool.address,
+        reading.spot_tick,
+        reading.window_seconds,
+        reading.twap_tick,
+        reading.twap_quote1,
+        vault_details.pool.token1.symbol,
+        vault_details.pool.token0.symbol
+    );
+
+    check_twap_deviation(&reading, vault_details, vault_config)?;
+
+    Ok(Some(reading))
+}
+
+/// Errors when the pool tick of the reading is further than `max_twap_deviation_ticks` from its TWAP
+fn check_twap_deviation(
+    reading: &TwapReading,
+    vault_details: &VaultDetails,
+    vault_config: &VaultConfig,
+) -> Result<()> {
+    if reading.deviation_ticks() > vault_config.max_twap_deviation_ticks {
+        warn!(
+            "Pool {} tick {} is {} ticks away from its {}s TWAP tick {}",
+            vault_details.pool.address,
+            reading.spot_tick,
+            reading.deviation_ticks(),
+            reading.window_seconds,
+            reading.twap_tick
+        );
+
+        return Err(eyre!(
+            "Refusing to rebalance vault {}: pool tick {} deviates {} ticks from its {}s TWAP tick {}, the max is {}",
+            vault_details.address,
+            reading.spot_tick,
+            reading.deviation_ticks(),
+            reading.window_seconds,
+            reading.twap_tick,
+            vault_config.max_twap_deviation_ticks
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use alloy::primitives::U256;
+
+    use super::*;
+    use crate::types::{TomlConfig, test_vault};
+
+    fn reading(spot_tick: i32, twap_tick: i32) -> TwapReading {
+        TwapReading {
+            spot_tick,
+            twap_tick,
+            window_seconds: 300,
+            harmonic_mean_liquidity: 1_000_000,
+            twap_quote1: TokenAmount::new(U256::from(1_000_000), 6),
+        }
+    }
+
+    #[test]
+    fn test_spot_tick_too_far_from_twap_is_refused() {
+        let toml_config: TomlConfig =
+            toml::from_str(include_str!("../config/testnet.toml")).unwrap();
+        let vault_config = VaultConfig {
+            max_twap_deviation_ticks: 100,
+            ..toml_config.vaults[0].clone()
+        };
+        let vault = test_vault(0, 60, 1.0);
+
+        assert!(check_twap_deviation(&reading(100, 0), &vault, &vault_config).is_ok());
+        assert!(check_twap_deviation(&reading(-100, 0), &vault, &vault_config).is_ok());
+
+        let error = check_twap_deviation(&reading(101, 0), &vault, &vault_config).unwrap_err();
+        assert!(error.to_string().contains("deviates 101 ticks"));
+        assert!(check_twap_deviation(&reading(-50, 51), &vault, &vault_config).is_err());
+    }
+}
@@ -0,0 +1,277 @@
+use std::{collections::VecDeque, str::FromStr};
+
+use alloy::{
+    primitives::{Address, U256, aliases::I24},
+    providers::{Provider, WalletProvider},
+};
+use color_eyre::eyre::Result;
+use serde::Serialize;
+use tracing::info;
+use utoipa::ToSchema;
+
+use crate::{
+    config::{MAX_REBALANCE_PLANS_PER_VAULT, REBALANCE_GAS_LIMIT},
+    core::{
+        oracle::TwapReading,
+        profitability::RebalanceValueEstimate,
+        rebalance_policy::PolicyVerdict,
+        tx_costs::{HbarValue, with_gas_margin},
+        vault::ManiXAIVault,
+    },
+    helpers::{amount::TokenAmount, math::swap_solver::SwapSolution},
+    strategies::{StrategyDecision, fallback::StrategyFallback},
+    types::{PrepareSwapArgs, TickRange, VaultDetails},
+};
+
+/// Everything a rebalance would do, recorded before it is sent so operators can review the strategy decisions
+#[derive(Serialize, Debug, Clone, ToSchema)]
+pub struct RebalancePlan {
+    pub vault_address: String,
+    pub created_at: String,
+    pub strategy: String,
+    pub rationale: String,
+    /// Strategies that failed or were rejected by the ensemble before `strategy` decided
+    pub fallbacks: Vec<StrategyFallback>,
+    pub current_tick: i32,
+    pub old_lower_tick: i32,
+    pub old_upper_tick: i32,
+    pub new_lower_tick: i32,
+    pub new_upper_tick: i32,
+    pub is_swap_0_to_1: bool,
+    pub token_in: String,
+    pub token_out: String,
+    #[schema(value_type = String)]
+    pub swap_amount_in: TokenAmount,
+    #[schema(value_type = String)]
+    pub swap_max_amount_in: TokenAmount,
+    #[schema(value_type = String)]
+    pub swap_amount_out: TokenAmount,
+    pub price_impact_pct: f64,
+    /// Liquidity and amounts of the position minted after the swap
+    pub expected_liquidity: u128,
+    #[schema(value_type = String)]
+    pub expected_amount0: TokenAmount,
+    #[schema(value_type = String)]
+    pub expected_amount1: TokenAmount,
+    pub estimate: RebalanceValueEstimate,
+    /// Pool tick checked against its TWAP, `None` when the check is disabled for the vault
+    pub twap: Option<TwapReading>,
+    /// Verdict of the rebalance policy that let the strategies run
+    pub policy: Option<PolicyVerdict>,
+    /// `eth_call` and gas estimation of the `rebalance` call
+    pub call_simulation: RebalanceCallSimulation,
+    pub hbar_value: HbarValue,
+    /// False once the `rebalance` call is sent. Plans of vaults with execution disabled are only simulated
+    pub is_dry_run: bool,
+    pub tx_hash: Option<String>,
+    /// HBAR left unused in the vault once the call is executed
+    pub vault_hbar_balance_after: Option<f64>,
+}
+
+/// Outcome of running the `rebalance` call with `eth_call`
+#[derive(Serialize, Debug, Clone, ToSchema)]
+pub struct RebalanceCallSimulation {
+    pub reverted: bool,
+    pub error: Option<String>,
+    pub gas_estimate: Option<u64>,
+    /// Gas limit to send, the estimate plus margin or `REBALANCE_GAS_LIMIT` when the call can not be estimated
+    pub gas_limit: u64,
+}
+
+impl RebalancePlan {
+    pub fn new(
+        vault_details: &VaultDetails,
+        decision: &StrategyDecision,
+        swap_arg: &PrepareSwapArgs,
+        solution: &SwapSolution,
+        estimate: &RebalanceValueEstimate,
+        call_simulation: &RebalanceCallSimulation,
+        hbar_value: &HbarValue,
+    ) -> Result<Self> {
+        let tick_range = &decision.tick_range;
+
+        let (expected_liquidity, expected_amount0, expected_amount1) =
+            solution.expected_position(tick_range.lower_tick, tick_range.upper_tick)?;
+
+        Ok(Self {
+            vault_address: vault_details.address.clone(),
+            created_at: chrono::Utc::now().to_rfc3339(),
+            strategy: decision.strategy.clone(),
+            rationale: decision.rationale.clone(),
+            fallbacks: decision.fallbacks.clone(),
+            current_tick: vault_details.pool.current_tick,
+            old_lower_tick: vault_details.lower_tick,
+            old_upper_tick: vault_details.upper_tick,
+            new_lower_tick: tick_range.lower_tick,
+            new_upper_tick: tick_range.upper_tick,
+            is_swap_0_to_1: swap_arg.is_swap_0_to_1,
+            token_in: swap_arg.token_in.symbol.clone(),
+            token_out: swap_arg.token_out.symbol.clone(),
+            swap_amount_in: TokenAmount::new(solution.amount_in, swap_arg.token_in.decimals),
+            swap_max_amount_in: swap_arg.max_amount_in,
+            swap_amount_out: swap_arg.exact_amount_out,
+            price_impact_pct: solution.price_impact_pct(),
+            expected_liquidity,
+            expected_amount0: TokenAmount::new(
+                expected_amount0,
+                vault_details.pool.token0.decimals,
+            ),
+            expected_amount1: TokenAmount::new(
+                expected_amount1,
+                vault_details.pool.token1.decimals,
+            ),
+            estimate: estimate.clone(),
+            twap: None,
+            policy: None,
+            call_simulation: call_simulation.clone(),
+            hbar_value: hbar_value.clone(),
+            is_dry_run: true,
+            tx_hash: None,
+            vault_hbar_balance_after: None,
+        })
+    }
+}
+
+/// Run the `rebalance` call with `eth_call` from the operator wallet and estimate its gas, without sending anything
+pub async fn simulate_rebalance_call<P>(
+    provider: &P,
+    vault_address: &str,
+    tick_range: &TickRange,
+    swap_arg: &PrepareSwapArgs,
+    value_to_send: U256,
+) -> Result<RebalanceCallSimulation>
+where
+    P: Provider + WalletProvider,
+{
+    let vault_contract = ManiXAIVault::new(Address::from_str(vault_address)?, provider);
+
+    let call = vault_contract
+        .rebalance(
+            I24::from_str(tick_range.lower_tick.to_string().as_str())?,
+            I24::from_str(tick_range.upper_tick.to_string().as_str())?,
+            swap_arg.exact_amount_out.raw,
+            swap_arg.max_amount_in.raw,
+            swap_arg.is_swap_0_to_1,
+        )
+        .value(value_to_send)
+        .from(provider.default_signer_address());
+
+    let simulation = match call.call().await {
+        Ok(_) => {
+            let gas_estimate = call.estimate_gas().await.ok();
+
+            RebalanceCallSimulation {
+                reverted: false,
+                error: None,
+                gas_estimate,
+                gas_limit: gas_estimate
+                    .map(with_gas_margin)
+                    .unwrap_or(REBALANCE_GAS_LIMIT),
+            }
+        }
+        Err(err) => RebalanceCallSimulation {
+            reverted: true,
+            error: Some(err.to_string()),
+            gas_estimate: None,
+            gas_limit: REBALANCE_GAS_LIMIT,
+        },
+    };
+
+    info!(
+        "Simulated rebalance of vault {}: {:?}",
+        vault_address, simulation
+    );
+
+    Ok(simulation)
+}
+
+/// Keep the plan with the plans of its vault, only the last `MAX_REBALANCE_PLANS_PER_VAULT` of them are kept
+pub fn store_rebalance_plan(
+    rebalance_plans: &dashmap::DashMap<String, VecDeque<RebalancePlan>>,
+    plan: RebalancePlan,
+) {
+    let mut plans = rebalance_plans
+        .entry(plan.vault_address.to_lowercase())
+        .or_default();
+
+    plans.push_back(plan);
+
+    while plans.len() > MAX_REBALANCE_PLANS_PER_VAULT {
+        plans.pop_front();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn plan(vault_address: &str, current_tick: i32) -> RebalancePlan {
+        RebalancePlan {
+            vault_address: vault_address.to_string(),
+            created_at: chrono::Utc::now().to_rfc3339(),
+            strategy: "basic".to_string(),
+            rationale: String::new(),
+            fallbacks: vec![],
+            current_tick,
+            old_lower_tick: 0,
+            old_upper_tick: 0,
+            new_lower_tick: -60,
+            new_upper_tick: 60,
+            is_swap_0_to_1: true,
+            token_in: "A".to_string(),
+            token_out: "B".to_string(),
+            swap_amount_in: TokenAmount::new(U256::ZERO, 8),
+            swap_max_amount_in: TokenAmount::new(U256::ZERO, 8),
+            swap_amount_out: TokenAmount::new(U256::ZERO, 6),
+            price_impact_pct: 0.0,
+            expected_liquidity: 0,
+            expected_amount0: TokenAmount::new(U256::ZERO, 8),
+            expected_amount1: TokenAmount::new(U256::ZERO, 6),
+            estimate: RebalanceValueEstimate {
+                horizon_hours: 24.0,
+                daily_volume_token1: 0.0,
+                expected_fees_new_range: 0.0,
+                expected_fees_current_range: 0.0,
+                swap_fee_cost: 0.0,
+                price_impact_cost: 0.0,
+                gas_cost: 0.0,
+                net_expected_value: 0.0,
+            },
+            twap: None,
+            policy: None,
+            call_simulation: RebalanceCallSimulation {
+                reverted: false,
+                error: None,
+                gas_estimate: None,
+                gas_limit: REBALANCE_GAS_LIMIT,
+            },
+            hbar_value: HbarValue {
+                mint_fee: 0.0,
+                vault_balance: 0.0,
+                value_to_send: 0.0,
+                value_to_send_weibars: U256::ZERO,
+            },
+            is_dry_run: true,
+            tx_hash: None,
+            vault_hbar_balance_after: None,
+        }
+    }
+
+    #[test]
+    fn test_only_the_last_plans_are_kept_per_lowercase_vault() {
+        let rebalance_plans = dashmap::DashMap::new();
+
+        for current_tick in 0..MAX_REBALANCE_PLANS_PER_VAULT as i32 + 5 {
+            store_rebalance_plan(&rebalance_plans, plan("0xAbCd", current_tick));
+        }
+        store_rebalance_plan(&rebalance_plans, plan("0xabcd", -1));
+
+        assert_eq!(rebalance_plans.len(), 1);
+
+        let plans = rebalance_plans.get("0xabcd").unwrap();
+        assert_eq!(plans.len(), MAX_REBALANCE_PLANS_PER_VAULT);
+        // The oldest plans are dropped first
+        assert_eq!(plans.front().unwrap().current_tick, 6);
+        assert_eq!(plans.back().unwrap().current_tick, -1);
+    }
+}
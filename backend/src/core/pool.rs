@@ -0,0 +1,77 @@
+use std::{collections::HashMap, str::FromStr};
+
+use alloy::{
+    primitives::{Address, U256, aliases::I24},
+    providers::Provider,
+};
+use color_eyre::eyre::Result;
+use tracing::trace;
+
+use crate::{
+    core::vault::UniswapV3Pool,
+    helpers::math::uniswap_v3::{swap_simulator::PoolSnapshot, tick_bitmap::position},
+};
+
+/// Fetch the pool state needed to simulate swaps: slot0, active liquidity, the tick bitmap words
+/// `words_around` on each side of the current tick and the liquidity net of every initialized tick in them.
+pub async fn fetch_pool_snapshot<P>(
+    provider: &P,
+    pool_address: &str,
+    words_around: i16,
+) -> Result<PoolSnapshot>
+where
+    P: Provider,
+{
+    trace!("Fetching pool snapshot for {}...", pool_address);
+
+    let pool_contract = UniswapV3Pool::new(Address::from_str(pool_address)?, provider);
+
+    let slot0 = pool_contract.slot0().call().await?;
+    let sqrt_price_x96 = U256::from(slot0.sqrtPriceX96);
+    let tick = slot0.tick.as_i32();
+    let liquidity = pool_contract.liquidity().call().await?;
+    let fee_pips = pool_contract.fee().call().await?.to::<u32>();
+    let tick_spacing = pool_contract.tickSpacing().call().await?.as_i32();
+
+    let mut compressed = tick / tick_spacing;
+    if tick < 0 && tick % tick_spacing != 0 {
+        compressed -= 1;
+    }
+    let (current_word, _) = position(compressed);
+
+    let first_word = current_word.saturating_sub(words_around);
+    let last_word = current_word.saturating_add(words_around);
+
+    let mut tick_bitmap = HashMap::new();
+    let mut liquidity_net = HashMap::new();
+
+    for word_pos in first_word..=last_word {
+        let word = pool_contract.tickBitmap(word_pos).call().await?;
+        tick_bitmap.insert(word_pos, word);
+
+        for bit_pos in 0..256 {
+            if !word.bit(bit_pos) {
+                continue;
+            }
+
+            let initialized_tick = ((word_pos as i32) * 256 + bit_pos as i32) * tick_spacing;
+
+            let tick_info = pool_contract
+                .ticks(I24::from_str(initialized_tick.to_string().as_str())?)
+                .call()
+                .await?;
+
+            liquidity_net.insert(initialized_tick, tick_info.liquidityNet);
+        }
+    }
+
+    Ok(PoolSnapshot {
+        sqrt_price_x96,
+        tick,
+        liquidity,
+        fee_pips,
+        tick_spacing,
+        tick_bitmap,
+        liquidity_net,
+    })
+}
@@ -0,0 +1,283 @@
+use alloy::primitives::utils::format_units;
+use color_eyre::eyre::Result;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::{
+    helpers::math::swap_solver::SwapSolution,
+    types::{OhlcvEntry, OhlcvSource, TickRange, VaultConfig, VaultDetails},
+};
+
+/// Number of days of candles averaged to get the expected pool volume
+const VOLUME_LOOKBACK_DAYS: i64 = 7;
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+/// Everything the expected value of a rebalance depends on, in token1 units
+#[derive(Debug, Clone)]
+pub struct RebalanceValueInputs {
+    pub daily_volume_token1: f64,
+    /// Pool fee as a fraction (0.003 for a 0.3% pool)
+    pub fee: f64,
+    pub horizon_hours: f64,
+    /// Share of the active liquidity the new position would hold, zero if the price ends outside of the new range
+    pub new_position_share: f64,
+    /// Share of the active liquidity the current position holds, zero if it is out of range
+    pub current_position_share: f64,
+    pub swap_fee_token1: f64,
+    pub price_impact_token1: f64,
+    pub gas_cost_token1: f64,
+}
+
+/// Pool liquidity and call cost of a planned rebalance, read on chain
+#[derive(Debug, Clone)]
+pub struct RebalanceOnChainInputs {
+    /// Active liquidity of the pool before the vault position is burned
+    pub pool_liquidity: u128,
+    /// Active liquidity of the pool once the vault position is burned
+    pub pool_liquidity_without_vault: u128,
+    /// Gas and SaucerSwap fees paid by the `rebalance` call (see `core::tx_costs`)
+    pub tx_cost_hbar: f64,
+}
+
+/// Expected value of a rebalance over the horizon, in token1 units
+#[derive(Serialize, Debug, Clone, ToSchema)]
+pub struct RebalanceValueEstimate {
+    pub horizon_hours: f64,
+    pub daily_volume_token1: f64,
+    pub expected_fees_new_range: f64,
+    /// Fees the current position would keep earning if left alone
+    pub expected_fees_current_range: f64,
+    pub swap_fee_cost: f64,
+    pub price_impact_cost: f64,
+    pub gas_cost: f64,
+    pub net_expected_value: f64,
+}
+
+impl RebalanceValueEstimate {
+    pub fn is_profitable(&self) -> bool {
+        self.net_expected_value > 0.0
+    }
+}
+
+/// Net expected value of moving to the new range: the extra fees it should earn over the horizon
+/// minus the swap fee, price impact and gas paid to get there.
+pub fn estimate_rebalance_value(inputs: &RebalanceValueInputs) -> RebalanceValueEstimate {
+    let horizon_fees = inputs.daily_volume_token1 * inputs.fee * inputs.horizon_hours / 24.0;
+
+    let expected_fees_new_range = horizon_fees * inputs.new_position_share;
+    let expected_fees_current_range = horizon_fees * inputs.current_position_share;
+
+    let net_expected_value = expected_fees_new_range
+        - expected_fees_current_range
+        - inputs.swap_fee_token1
+        - inputs.price_impact_token1
+        - inputs.gas_cost_token1;
+
+    RebalanceValueEstimate {
+        horizon_hours: inputs.horizon_hours,
+        daily_volume_token1: inputs.daily_volume_token1,
+        expected_fees_new_range,
+        expected_fees_current_range,
+        swap_fee_cost: inputs.swap_fee_token1,
+        price_impact_cost: inputs.price_impact_token1,
+        gas_cost: inputs.gas_cost_token1,
+        net_expected_value,
+    }
+}
+
+/// Gather the volume, liquidity shares and swap costs of a planned rebalance and estimate its value.
+///
+/// - ohlcv: Candles of the pool from the vault `ohlcv_source`, oldest first
+/// - on_chain: Pool liquidity with and without the vault position, and cost of the `rebalance` call
+pub fn estimate_vault_rebalance(
+    vault_details: &VaultDetails,
+    vault_config: &VaultConfig,
+    ohlcv: &[OhlcvEntry],
+    solution: &SwapSolution,
+    tick_range: &TickRange,
+    on_chain: &RebalanceOnChainInputs,
+) -> Result<RebalanceValueEstimate> {
+    let pool = &vault_details.pool;
+    let token0_decimals = pool.token0.decimals;
+    let token1_decimals = pool.token1.decimals;
+
+    // 1. Expected volume from the candles of the last days, the volumes of the candles built from swaps are already in token1
+    let volume_to_token1 = match vault_config.ohlcv_source {
+        OhlcvSource::Coingecko => vault_config.volume_to_token1,
+        OhlcvSource::SwapLogs => 1.0,
+    };
+    let daily_volume_token1 =
+        average_daily_volume(ohlcv, chrono::Utc::now().timestamp()) * volume_to_token1;
+
+    // 2. Share of the active liquidity of the new and current positions
+    let new_position_share = if solution.tick_after >= tick_range.lower_tick
+        && solution.tick_after < tick_range.upper_tick
+    {
+        let (new_liquidity, _, _) =
+            solution.expected_position(tick_range.lower_tick, tick_range.upper_tick)?;
+
+        liquidity_share(
+            new_liquidity,
+            on_chain.pool_liquidity_without_vault + new_liquidity,
+        )
+    } else {
+        0.0
+    };
+
+    let is_current_in_range = vault_details.is_active
+        && pool.current_tick >= vault_details.lower_tick
+        && pool.current_tick < vault_details.upper_tick;
+
+    let current_position_share = if is_current_in_range {
+        liquidity_share(vault_details.position.liquidity, on_chain.pool_liquidity)
+    } else {
+        0.0
+    };
+
+    // 3. Swap fee and price impact, valued at the pre swap price
+    let (swap_fee_token1, price_impact_token1) = match &solution.simulation {
+        Some(simulation) => {
+            let (decimals_in, decimals_out) = if solution.zero_for_one {
+                (token0_decimals, token1_decimals)
+            } else {
+                (token1_decimals, token0_decimals)
+            };
+
+            let amount_in: f64 = format_units(simulation.amount_in, decimals_in)?.parse()?;
+            let fee_amount: f64 = format_units(simulation.fee_amount, decimals_in)?.parse()?;
+            let amount_out: f64 = format_units(simulation.amount_out, decimals_out)?.parse()?;
+
+            if solution.zero_for_one {
+                (
+                    fee_amount * pool.price1,
+                    ((amount_in - fee_amount) * pool.price1 - amount_out).max(0.0),
+                )
+            } else {
+                (
+                    fee_amount,
+                    ((amount_in - fee_amount) - amount_out * pool.price1).max(0.0),
+                )
+            }
+        }
+        None => (0.0, 0.0),
+    };
+
+    // 4. Gas and fees of the call, valued in token1
+    let hbar_price_token1 = if pool.token1.is_native_wrapper {
+        1.0
+    } else if pool.token0.is_native_wrapper {
+        pool.price1
+    } else {
+        vault_config.hbar_price_token1.ok_or_else(|| {
+            color_eyre::eyre::eyre!(
+                "Vault {} pool has no WHBAR side, set hbar_price_token1 to value the rebalance gas",
+                vault_details.address
+            )
+        })?
+    };
+
+    Ok(estimate_rebalance_value(&RebalanceValueInputs {
+        daily_volume_token1,
+        fee: pool.fee / 100.0,
+        horizon_hours: vault_config.profit_horizon_hours,
+        new_position_share,
+        current_position_share,
+        swap_fee_token1,
+        price_impact_token1,
+        gas_cost_token1: on_chain.tx_cost_hbar * hbar_price_token1,
+    }))
+}
+
+/// Average volume per day over the last `VOLUME_LOOKBACK_DAYS` days before `now`.
+/// The candle sources skip the intervals without swaps, so the missing candles count as no volume.
+fn average_daily_volume(candles: &[OhlcvEntry], now: i64) -> f64 {
+    let lookback_start = now - VOLUME_LOOKBACK_DAYS * SECONDS_PER_DAY;
+
+    candles
+        .iter()
+        .filter(|c| c.timestamp() > lookback_start && c.timestamp() <= now)
+        .map(|c| c.volume())
+        .sum::<f64>()
+        / VOLUME_LOOKBACK_DAYS as f64
+}
+
+fn liquidity_share(liquidity: u128, total_liquidity: u128) -> f64 {
+    if total_liquidity == 0 {
+        0.0
+    } else {
+        liquidity as f64 / total_liquidity as f64
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn inputs() -> RebalanceValueInputs {
+        RebalanceValueInputs {
+            daily_volume_token1: 100_000.0,
+            fee: 0.003,
+            horizon_hours: 24.0,
+            new_position_share: 0.1,
+            current_position_share: 0.0,
+            swap_fee_token1: 1.0,
+            price_impact_token1: 0.5,
+            gas_cost_token1: 2.0,
+        }
+    }
+
+    #[test]
+    fn test_out_of_range_position_is_worth_moving() {
+        let estimate = estimate_rebalance_value(&inputs());
+
+        // 100k volume * 0.3% * 10% share
+        assert!((estimate.expected_fees_new_range - 30.0).abs() < 1e-9);
+        assert!((estimate.net_expected_value - 26.5).abs() < 1e-9);
+        assert!(estimate.is_profitable());
+    }
+
+    #[test]
+    fn test_costs_above_extra_fees() {
+        let estimate = estimate_rebalance_value(&RebalanceValueInputs {
+            current_position_share: 0.09,
+            ..inputs()
+        });
+
+        // only 3 of extra fees against 3.5 of costs
+        assert!((estimate.net_expected_value + 0.5).abs() < 1e-9);
+        assert!(!estimate.is_profitable());
+    }
+
+    #[test]
+    fn test_daily_volume_of_hourly_candles() {
+        let candles: Vec<OhlcvEntry> = (0..200)
+            .map(|hour| {
+                OhlcvEntry(
+                    hour * 3_600,
+                    1.0,
+                    1.0,
+                    1.0,
+                    1.0,
+                    if hour < 32 { 1_000.0 } else { 10.0 },
+                )
+            })
+            .collect();
+
+        // only the last 168 hours count
+        assert!((average_daily_volume(&candles, 199 * 3_600) - 240.0).abs() < 1e-9);
+        assert_eq!(average_daily_volume(&[], 0), 0.0);
+    }
+
+    #[test]
+    fn test_daily_volume_of_gapped_candles() {
+        let now = 100 * SECONDS_PER_DAY;
+        // a sparse pool: two hourly candles this week and older ones months ago
+        let candles: Vec<OhlcvEntry> = [10, 20, 30, now - 3 * 3_600, now - 3_600]
+            .into_iter()
+            .map(|timestamp| OhlcvEntry(timestamp, 1.0, 1.0, 1.0, 1.0, 700.0))
+            .collect();
+
+        assert!((average_daily_volume(&candles, now) - 200.0).abs() < 1e-9);
+    }
+}
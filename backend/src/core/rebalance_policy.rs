@@ -0,0 +1,512 @@
+/*
+    Rules deciding whether the strategies of a vault are run at all on a loop iteration, checked in this order:
+    - schedule: rebalances only happen in the cron-style windows of the vault, when it has some
+    - daily cap: at most `max_rebalances_per_day` executed rebalances over the last 24 hours
+    - cooldown: at least `min_rebalance_interval_seconds` since the last executed rebalance
+    - no position: a vault without position always runs its strategies to mint one
+    - trigger band: with `trigger_band_pct` or `out_of_band_seconds` set, an in range position is left alone until the
+      price leaves the inner band of its range, and stays out of it for `out_of_band_seconds`. When the strategies
+      then do not rebalance, they are only asked again after `reevaluate_interval_seconds`
+    Without any rule configured the strategies run on every iteration. The state of the rules is kept in memory.
+*/
+
+use std::collections::VecDeque;
+
+use chrono::{DateTime, Datelike, Timelike, Utc};
+use color_eyre::eyre::{Result, eyre};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::types::{RebalancePolicyConfig, VaultDetails};
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+/// Rule that allowed or blocked a loop iteration
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PolicyRule {
+    OutsideSchedule,
+    DailyCap,
+    Cooldown,
+    NoPosition,
+    InBand,
+    OutOfBandTooShort,
+    /// The strategies did not rebalance on the price out of band a short time ago
+    DeclinedRecently,
+    OutOfBand,
+    /// No trigger rule is configured, the strategies decide on every iteration
+    EveryInterval,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct PolicyVerdict {
+    pub allowed: bool,
+    pub rule: PolicyRule,
+    pub detail: String,
+    pub evaluated_at: String,
+}
+
+/// What the rules of a vault remember between iterations
+#[derive(Serialize, Debug, Clone, Default, ToSchema)]
+pub struct PolicyState {
+    /// Unix time the price left the trigger band, `None` while it is inside
+    pub out_of_band_since: Option<i64>,
+    /// Unix times of the executed rebalances of the last 24 hours
+    #[schema(value_type = Vec<i64>)]
+    pub rebalances: VecDeque<i64>,
+    /// Unix time the strategies last ran on the price out of band without rebalancing
+    pub declined_at: Option<i64>,
+    pub last_verdict: Option<PolicyVerdict>,
+}
+
+/// Rules of a vault and their state, as served by the API
+#[derive(Serialize, Debug, Clone, ToSchema)]
+pub struct RebalancePolicyStatus {
+    pub vault_address: String,
+    pub config: RebalancePolicyConfig,
+    pub state: PolicyState,
+}
+
+impl PolicyState {
+    /// Count an executed rebalance, the new position starts in its band
+    pub fn record_rebalance(&mut self, now: DateTime<Utc>) {
+        self.rebalances.push_back(now.timestamp());
+        self.out_of_band_since = None;
+        self.declined_at = None;
+    }
+
+    /// Remember the strategies ran without rebalancing, so they are not asked again on every iteration
+    pub fn record_declined(&mut self, now: DateTime<Utc>) {
+        self.declined_at = Some(now.timestamp());
+    }
+}
+
+/// Run the rules of `config` on the vault and remember the verdict
+pub fn evaluate(
+    config: &RebalancePolicyConfig,
+    state: &mut PolicyState,
+    vault: &VaultDetails,
+    now: DateTime<Utc>,
+) -> Result<PolicyVerdict> {
+    let timestamp = now.timestamp();
+
+    // The band is tracked on every iteration so the time out of band is known once the other rules pass
+    let is_trigger_band_enabled =
+        config.trigger_band_pct.is_some() || config.out_of_band_seconds > 0;
+    let is_out_of_band = vault.is_active && is_out_of_band(config, vault);
+
+    if is_out_of_band {
+        state.out_of_band_since.get_or_insert(timestamp);
+    } else {
+        state.out_of_band_since = None;
+        state.declined_at = None;
+    }
+
+    while state
+        .rebalances
+        .front()
+        .is_some_and(|rebalance| timestamp - rebalance >= SECONDS_PER_DAY)
+    {
+        state.rebalances.pop_front();
+    }
+
+    let (allowed, rule, detail) =
+        if !config.schedule.is_empty() && !is_in_schedule(&config.schedule, now)? {
+            (
+                false,
+                PolicyRule::OutsideSchedule,
+                format!("{} is outside of the windows {:?}", now, config.schedule),
+            )
+        } else if config.max_rebalances_per_day > 0
+            && state.rebalances.len() >= config.max_rebalances_per_day as usize
+        {
+            (
+                false,
+                PolicyRule::DailyCap,
+                format!(
+                    "{} rebalances over the last 24 hours, at most {} are allowed",
+                    state.rebalances.len(),
+                    config.max_rebalances_per_day
+                ),
+            )
+        } else if let Some(last_rebalance) = state.rebalances.back().filter(|last_rebalance| {
+            timestamp - **last_rebalance < config.min_rebalance_interval_seconds as i64
+        }) {
+            (
+                false,
+                PolicyRule::Cooldown,
+                format!(
+                    "last rebalance {}s ago, the cooldown is {}s",
+                    timestamp - last_rebalance,
+                    config.min_rebalance_interval_seconds
+                ),
+            )
+        } else if !vault.is_active {
+            (
+                true,
+                PolicyRule::NoPosition,
+                "the vault has no position".to_string(),
+            )
+        } else if !is_trigger_band_enabled {
+            (
+                true,
+                PolicyRule::EveryInterval,
+                "no trigger rule is configured".to_string(),
+            )
+        } else if let Some(out_of_band_since) = state.out_of_band_since {
+            let out_of_band_seconds = timestamp - out_of_band_since;
+
+            let declined_seconds = state
+                .declined_at
+                .map(|declined_at| timestamp - declined_at)
+                .filter(|seconds| *seconds < config.reevaluate_interval_seconds as i64);
+
+            if out_of_band_seconds < config.out_of_band_seconds as i64 {
+                (
+                    false,
+                    PolicyRule::OutOfBandTooShort,
+                    format!(
+                        "tick {} out of the trigger band for {}s, {}s are needed",
+                        vault.pool.current_tick, out_of_band_seconds, config.out_of_band_seconds
+                    ),
+                )
+            } else if let Some(declined_seconds) = declined_seconds {
+                (
+                    false,
+                    PolicyRule::DeclinedRecently,
+                    format!(
+                        "the strategies did not rebalance {}s ago, they are asked again after {}s",
+                        declined_seconds, config.reevaluate_interval_seconds
+                    ),
+                )
+            } else {
+                (
+                    true,
+                    PolicyRule::OutOfBand,
+                    format!(
+                        "tick {} out of the trigger band for {}s",
+                        vault.pool.current_tick, out_of_band_seconds
+                    ),
+                )
+            }
+        } else {
+            (
+                false,
+                PolicyRule::InBand,
+                format!(
+                    "tick {} inside the trigger band of the range {} - {}",
+                    vault.pool.current_tick, vault.lower_tick, vault.upper_tick
+                ),
+            )
+        };
+
+    let verdict = PolicyVerdict {
+        allowed,
+        rule,
+        detail,
+        evaluated_at: now.to_rfc3339(),
+    };
+    state.last_verdict = Some(verdict.clone());
+
+    Ok(verdict)
+}
+
+/// Whether the current tick is out of the range shrunk by `trigger_band_pct` of its width on each side
+fn is_out_of_band(config: &RebalancePolicyConfig, vault: &VaultDetails) -> bool {
+    let width = (vault.upper_tick - vault.lower_tick) as f64;
+    let margin = width * config.trigger_band_pct.unwrap_or_default() / 100.0;
+
+    let current_tick = vault.pool.current_tick as f64;
+
+    current_tick < vault.lower_tick as f64 + margin
+        || current_tick >= vault.upper_tick as f64 - margin
+}
+
+fn is_in_schedule(schedule: &[String], now: DateTime<Utc>) -> Result<bool> {
+    for window in schedule {
+        if CronWindow::parse(window)?.contains(now) {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+/// Minutes matched by a `minute hour day-of-month month day-of-week` expression, in UTC.
+/// Fields take `*`, values, ranges `a-b`, lists `a,b` and steps `*/n` or `a-b/n`. Sunday is 0 (or 7).
+/// Unlike cron, a minute has to match every field, day of month and day of week included.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CronWindow {
+    minutes: u64,
+    hours: u64,
+    days_of_month: u64,
+    months: u64,
+    days_of_week: u64,
+}
+
+impl CronWindow {
+    pub fn parse(expression: &str) -> Result<Self> {
+        let fields = expression.split_whitespace().collect::<Vec<&str>>();
+
+        let [minutes, hours, days_of_month, months, days_of_week] = fields[..] else {
+            return Err(eyre!(
+                "Invalid schedule {:?}: expected 5 fields, got {}",
+                expression,
+                fields.len()
+            ));
+        };
+
+        let parse = |field, min, max| {
+            parse_cron_field(field, min, max)
+                .map_err(|e| eyre!("Invalid schedule {:?}: {}", expression, e))
+        };
+
+        let mut days_of_week = parse(days_of_week, 0, 7)?;
+        // 7 is another name of Sunday
+        if days_of_week & (1 << 7) != 0 {
+            days_of_week |= 1;
+        }
+
+        Ok(Self {
+            minutes: parse(minutes, 0, 59)?,
+            hours: parse(hours, 0, 23)?,
+            days_of_month: parse(days_of_month, 1, 31)?,
+            months: parse(months, 1, 12)?,
+            days_of_week,
+        })
+    }
+
+    pub fn contains(&self, time: DateTime<Utc>) -> bool {
+        let is_set = |mask: u64, value: u32| mask & (1 << value) != 0;
+
+        is_set(self.minutes, time.minute())
+            && is_set(self.hours, time.hour())
+            && is_set(self.days_of_month, time.day())
+            && is_set(self.months, time.month())
+            && is_set(self.days_of_week, time.weekday().num_days_from_sunday())
+    }
+}
+
+/// Bit `n` of the mask is set when the field matches the value `n`
+fn parse_cron_field(field: &str, min: u32, max: u32) -> Result<u64> {
+    let mut mask = 0u64;
+
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, Some(step.parse::<u32>()?)),
+            None => (part, None),
+        };
+
+        if step == Some(0) {
+            return Err(eyre!("step of {:?} must be greater than 0", part));
+        }
+
+        let (start, end) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((start, end)) => (start.parse::<u32>()?, end.parse::<u32>()?),
+                // A start with a step runs up to the end of the field, `5/15` is 5,20,35,50
+                None => {
+                    let value = range.parse::<u32>()?;
+                    (value, if step.is_some() { max } else { value })
+                }
+            },
+        };
+
+        if start < min || end > max || start > end {
+            return Err(eyre!(
+                "{:?} is out of the {}-{} range of the field",
+                part,
+                min,
+                max
+            ));
+        }
+
+        for value in (start..=end).step_by(step.unwrap_or(1) as usize) {
+            mask |= 1 << value;
+        }
+    }
+
+    Ok(mask)
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::types::test_vault;
+
+    /// Vault on the range 0 - 100
+    fn vault(current_tick: i32, is_active: bool) -> VaultDetails {
+        VaultDetails {
+            lower_tick: 0,
+            upper_tick: 100,
+            is_active,
+            ..test_vault(current_tick, 10, 1.0)
+        }
+    }
+
+    fn at(seconds: i64) -> DateTime<Utc> {
+        // Monday 2025-01-06 00:00 UTC
+        Utc.timestamp_opt(1_736_121_600 + seconds, 0).unwrap()
+    }
+
+    #[test]
+    fn test_trigger_band_needs_time_out_of_band() {
+        let config = RebalancePolicyConfig {
+            trigger_band_pct: Some(10.0),
+            out_of_band_seconds: 600,
+            ..RebalancePolicyConfig::default()
+        };
+        let mut state = PolicyState::default();
+
+        // Inside the 10 - 90 inner band
+        let verdict = evaluate(&config, &mut state, &vault(50, true), at(0)).unwrap();
+        assert_eq!((verdict.allowed, verdict.rule), (false, PolicyRule::InBand));
+
+        // In range but out of the inner band, not for long enough yet
+        let verdict = evaluate(&config, &mut state, &vault(95, true), at(60)).unwrap();
+        assert_eq!(verdict.rule, PolicyRule::OutOfBandTooShort);
+
+        let verdict = evaluate(&config, &mut state, &vault(120, true), at(660)).unwrap();
+        assert_eq!(
+            (verdict.allowed, verdict.rule),
+            (true, PolicyRule::OutOfBand)
+        );
+
+        // Back in the band resets the timer
+        evaluate(&config, &mut state, &vault(50, true), at(700)).unwrap();
+        let verdict = evaluate(&config, &mut state, &vault(120, true), at(720)).unwrap();
+        assert_eq!(verdict.rule, PolicyRule::OutOfBandTooShort);
+
+        // A vault without position always decides, no rule at all keeps the old behavior
+        let verdict = evaluate(&config, &mut state, &vault(0, false), at(720)).unwrap();
+        assert_eq!(verdict.rule, PolicyRule::NoPosition);
+
+        let verdict = evaluate(
+            &RebalancePolicyConfig::default(),
+            &mut PolicyState::default(),
+            &vault(50, true),
+            at(0),
+        )
+        .unwrap();
+        assert_eq!(
+            (verdict.allowed, verdict.rule),
+            (true, PolicyRule::EveryInterval)
+        );
+    }
+
+    #[test]
+    fn test_declined_out_of_band_waits_before_reevaluation() {
+        let config = RebalancePolicyConfig {
+            trigger_band_pct: Some(10.0),
+            out_of_band_seconds: 600,
+            reevaluate_interval_seconds: 900,
+            ..RebalancePolicyConfig::default()
+        };
+        let mut state = PolicyState::default();
+
+        evaluate(&config, &mut state, &vault(120, true), at(0)).unwrap();
+        let verdict = evaluate(&config, &mut state, &vault(120, true), at(600)).unwrap();
+        assert_eq!(
+            (verdict.allowed, verdict.rule),
+            (true, PolicyRule::OutOfBand)
+        );
+
+        // The strategies kept the position, they are not asked again on the next iterations
+        state.record_declined(at(600));
+        let verdict = evaluate(&config, &mut state, &vault(120, true), at(660)).unwrap();
+        assert_eq!(
+            (verdict.allowed, verdict.rule),
+            (false, PolicyRule::DeclinedRecently)
+        );
+
+        let verdict = evaluate(&config, &mut state, &vault(120, true), at(1_500)).unwrap();
+        assert_eq!(
+            (verdict.allowed, verdict.rule),
+            (true, PolicyRule::OutOfBand)
+        );
+
+        // Back in the band forgets the declined run
+        state.record_declined(at(1_500));
+        evaluate(&config, &mut state, &vault(50, true), at(1_560)).unwrap();
+        assert_eq!(state.declined_at, None);
+    }
+
+    #[test]
+    fn test_cooldown_and_daily_cap() {
+        let config = RebalancePolicyConfig {
+            min_rebalance_interval_seconds: 3_600,
+            max_rebalances_per_day: 2,
+            ..RebalancePolicyConfig::default()
+        };
+        let mut state = PolicyState::default();
+
+        state.record_rebalance(at(0));
+        let verdict = evaluate(&config, &mut state, &vault(200, true), at(1_800)).unwrap();
+        assert_eq!(
+            (verdict.allowed, verdict.rule),
+            (false, PolicyRule::Cooldown)
+        );
+
+        let verdict = evaluate(&config, &mut state, &vault(200, true), at(3_600)).unwrap();
+        assert!(verdict.allowed);
+
+        state.record_rebalance(at(3_600));
+        let verdict = evaluate(&config, &mut state, &vault(200, true), at(20_000)).unwrap();
+        assert_eq!(verdict.rule, PolicyRule::DailyCap);
+
+        // The first rebalance is more than a day old
+        let verdict =
+            evaluate(&config, &mut state, &vault(200, true), at(SECONDS_PER_DAY)).unwrap();
+        assert!(verdict.allowed);
+    }
+
+    #[test]
+    fn test_cron_step_from_a_start_value() {
+        let expected = [5, 20, 35, 50].iter().fold(0u64, |mask, v| mask | 1 << v);
+        assert_eq!(parse_cron_field("5/15", 0, 59).unwrap(), expected);
+        assert_eq!(parse_cron_field("5-59/15", 0, 59).unwrap(), expected);
+        assert_eq!(parse_cron_field("5", 0, 59).unwrap(), 1 << 5);
+
+        let window = CronWindow::parse("5/15 * * * *").unwrap();
+        assert!(window.contains(at(20 * 60)));
+        assert!(!window.contains(at(15 * 60)));
+    }
+
+    #[test]
+    fn test_schedule_windows() {
+        // Working hours on weekdays
+        let window = CronWindow::parse("* 8-17 * * 1-5").unwrap();
+        assert!(window.contains(at(9 * 3_600)));
+        assert!(!window.contains(at(18 * 3_600)));
+        // Saturday
+        assert!(!window.contains(at(5 * SECONDS_PER_DAY + 9 * 3_600)));
+
+        let window = CronWindow::parse("*/15 0 * * 7").unwrap();
+        // Sunday 2025-01-05 00:30
+        assert!(window.contains(at(-SECONDS_PER_DAY + 30 * 60)));
+        assert!(!window.contains(at(-SECONDS_PER_DAY + 31 * 60)));
+
+        assert!(CronWindow::parse("* * * *").is_err());
+        assert!(CronWindow::parse("60 * * * *").is_err());
+        assert!(CronWindow::parse("*/0 * * * *").is_err());
+
+        let config = RebalancePolicyConfig {
+            schedule: vec!["* 8-17 * * 1-5".to_string()],
+            ..RebalancePolicyConfig::default()
+        };
+        let verdict = evaluate(
+            &config,
+            &mut PolicyState::default(),
+            &vault(0, false),
+            at(0),
+        )
+        .unwrap();
+        assert_eq!(
+            (verdict.allowed, verdict.rule),
+            (false, PolicyRule::OutsideSchedule)
+        );
+    }
+}
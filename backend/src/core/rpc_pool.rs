@@ -0,0 +1,296 @@
+use std::{
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use alloy::{
+    providers::RootProvider,
+    rpc::json_rpc::{RequestPacket, ResponsePacket},
+    transports::{
+        TransportError, TransportFut,
+        http::{
+            Http,
+            reqwest::{Client, Url},
+        },
+    },
+};
+use color_eyre::eyre::{Result, eyre};
+use serde::Serialize;
+use tower::Service;
+use tracing::warn;
+use utoipa::ToSchema;
+
+use crate::{
+    config::{RPC_UNHEALTHY_AFTER_FAILURES, RPC_UNHEALTHY_COOLDOWN_SECONDS},
+    core::rpc_retry::{RpcErrorKind, classify_error_payload, classify_transport_error},
+};
+
+/// Health of an RPC endpoint as seen by the requests sent to it
+#[derive(Serialize, Debug, Clone, ToSchema)]
+pub struct RpcEndpointHealth {
+    pub url: String,
+    pub is_healthy: bool,
+    pub consecutive_failures: u32,
+    pub total_requests: u64,
+    pub total_failures: u64,
+    pub last_latency_ms: Option<u64>,
+    pub last_error: Option<String>,
+}
+
+struct EndpointState {
+    health: RpcEndpointHealth,
+    /// Set when the endpoint becomes unhealthy, it is tried again once the cooldown is over
+    unhealthy_since: Option<Instant>,
+}
+
+pub struct RpcEndpoint {
+    pub url: Url,
+    transport: Http<Client>,
+    /// Read only provider on this endpoint alone, used to cross check reads
+    pub provider: RootProvider,
+    state: Mutex<EndpointState>,
+}
+
+impl RpcEndpoint {
+    fn new(url: Url) -> Self {
+        Self {
+            transport: Http::new(url.clone()),
+            provider: RootProvider::new_http(url.clone()),
+            state: Mutex::new(EndpointState {
+                health: RpcEndpointHealth {
+                    url: url.to_string(),
+                    is_healthy: true,
+                    consecutive_failures: 0,
+                    total_requests: 0,
+                    total_failures: 0,
+                    last_latency_ms: None,
+                    last_error: None,
+                },
+                unhealthy_since: None,
+            }),
+            url,
+        }
+    }
+
+    pub fn health(&self) -> RpcEndpointHealth {
+        self.state.lock().unwrap().health.clone()
+    }
+
+    /// Healthy, or unhealthy for longer than the cooldown and worth trying again
+    fn is_available(&self) -> bool {
+        match self.state.lock().unwrap().unhealthy_since {
+            Some(since) => since.elapsed() >= Duration::from_secs(RPC_UNHEALTHY_COOLDOWN_SECONDS),
+            None => true,
+        }
+    }
+
+    fn record_success(&self, latency: Duration) {
+        let mut state = self.state.lock().unwrap();
+
+        state.health.total_requests += 1;
+        state.health.consecutive_failures = 0;
+        state.health.last_latency_ms = Some(latency.as_millis() as u64);
+        state.health.is_healthy = true;
+        state.unhealthy_since = None;
+    }
+
+    fn record_failure(&self, error: String) {
+        let mut state = self.state.lock().unwrap();
+
+        state.health.total_requests += 1;
+        state.health.total_failures += 1;
+        state.health.consecutive_failures += 1;
+        state.health.last_error = Some(error);
+
+        if state.health.consecutive_failures >= RPC_UNHEALTHY_AFTER_FAILURES {
+            if state.health.is_healthy {
+                warn!(
+                    "RPC endpoint {} is unhealthy after {} failures in a row",
+                    self.url, state.health.consecutive_failures
+                );
+            }
+
+            state.health.is_healthy = false;
+            // restart the cooldown, the retry after the previous one failed too
+            state.unhealthy_since = Some(Instant::now());
+        }
+    }
+}
+
+/// RPC endpoints of the config, ranked by health
+pub struct RpcPool {
+    endpoints: Vec<Arc<RpcEndpoint>>,
+}
+
+impl RpcPool {
+    pub fn new(rpc_urls: &[String]) -> Result<Self> {
+        if rpc_urls.is_empty() {
+            return Err(eyre!("At least one RPC url is needed"));
+        }
+
+        let endpoints = rpc_urls
+            .iter()
+            .map(|url| Ok(Arc::new(RpcEndpoint::new(url.parse()?))))
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self { endpoints })
+    }
+
+    pub fn health(&self) -> Vec<RpcEndpointHealth> {
+        self.endpoints
+            .iter()
+            .map(|endpoint| endpoint.health())
+            .collect()
+    }
+
+    /// Endpoints in the order they should be tried: available ones in config order, then the ones
+    /// still cooling down, as a last resort
+    pub fn ranked_endpoints(&self) -> Vec<Arc<RpcEndpoint>> {
+        let (mut available, cooling_down): (Vec<_>, Vec<_>) = self
+            .endpoints
+            .iter()
+            .cloned()
+            .partition(|endpoint| endpoint.is_available());
+
+        available.extend(cooling_down);
+
+        available
+    }
+
+    /// The `count` best available endpoints, errors if there are not enough of them
+    pub fn available_endpoints(&self, count: usize) -> Result<Vec<Arc<RpcEndpoint>>> {
+        let endpoints: Vec<_> = self
+            .endpoints
+            .iter()
+            .filter(|endpoint| endpoint.is_available())
+            .take(count)
+            .cloned()
+            .collect();
+
+        if endpoints.len() < count {
+            return Err(eyre!(
+                "Only {} healthy RPC endpoints, {} needed",
+                endpoints.len(),
+                count
+            ));
+        }
+
+        Ok(endpoints)
+    }
+}
+
+/// Transport sending each request to the best endpoint of the pool, and to the next ones when it fails
+/// with a transient error. Reverts and other errors are returned as is, another node would give the same answer.
+#[derive(Clone)]
+pub struct FailoverService {
+    pool: Arc<RpcPool>,
+}
+
+impl FailoverService {
+    pub fn new(pool: Arc<RpcPool>) -> Self {
+        Self { pool }
+    }
+}
+
+impl Service<RequestPacket> for FailoverService {
+    type Response = ResponsePacket;
+    type Error = TransportError;
+    type Future = TransportFut<'static>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        // The http transports are always ready
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: RequestPacket) -> Self::Future {
+        let pool = self.pool.clone();
+
+        Box::pin(async move {
+            let mut last_result = None;
+
+            for endpoint in pool.ranked_endpoints() {
+                let started_at = Instant::now();
+                let result = endpoint.transport.clone().call(request.clone()).await;
+
+                // JSON-RPC errors come back inside a successful http response
+                let failure = match &result {
+                    Ok(response) => match response.as_error() {
+                        Some(payload)
+                            if classify_error_payload(payload) == RpcErrorKind::Transient =>
+                        {
+                            Some(payload.to_string())
+                        }
+                        _ => None,
+                    },
+                    Err(error) if classify_transport_error(error) == RpcErrorKind::Transient => {
+                        Some(error.to_string())
+                    }
+                    Err(_) => None,
+                };
+
+                match failure {
+                    None => {
+                        endpoint.record_success(started_at.elapsed());
+                        return result;
+                    }
+                    Some(error) => {
+                        warn!(
+                            "RPC endpoint {} failed, trying the next one: {}",
+                            endpoint.url, error
+                        );
+                        endpoint.record_failure(error);
+                        last_result = Some(result);
+                    }
+                }
+            }
+
+            last_result.unwrap_or_else(|| {
+                Err(TransportError::local_usage_str(
+                    "No RPC endpoint configured",
+                ))
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn pool() -> RpcPool {
+        RpcPool::new(&[
+            "https://first.example.com/api".to_string(),
+            "https://second.example.com/api".to_string(),
+        ])
+        .unwrap()
+    }
+
+    #[test]
+    fn test_unhealthy_endpoint_is_tried_last() {
+        let pool = pool();
+        let first = pool.ranked_endpoints()[0].clone();
+
+        for _ in 1..RPC_UNHEALTHY_AFTER_FAILURES {
+            first.record_failure("timeout".to_string());
+        }
+        assert!(first.health().is_healthy);
+        assert_eq!(pool.ranked_endpoints()[0].url, first.url);
+
+        first.record_failure("timeout".to_string());
+        assert!(!first.health().is_healthy);
+        assert_eq!(pool.ranked_endpoints()[1].url, first.url);
+        assert!(pool.available_endpoints(2).is_err());
+
+        first.record_success(Duration::from_millis(20));
+        assert!(first.health().is_healthy);
+        assert_eq!(pool.ranked_endpoints()[0].url, first.url);
+        assert_eq!(pool.available_endpoints(2).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_empty_pool_is_rejected() {
+        assert!(RpcPool::new(&[]).is_err());
+        assert!(RpcPool::new(&["not a url".to_string()]).is_err());
+    }
+}
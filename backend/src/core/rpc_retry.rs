@@ -0,0 +1,357 @@
+use std::{
+    future::Future,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use alloy::{
+    rpc::json_rpc::{ErrorPayload, RequestPacket, ResponsePacket},
+    transports::{TransportError, TransportErrorKind, TransportFut},
+};
+use color_eyre::eyre::{Report, Result};
+use tokio::time::sleep;
+use tower::{Layer, Service};
+use tracing::warn;
+
+use crate::config::{RPC_INITIAL_BACKOFF_MS, RPC_MAX_BACKOFF_MS, RPC_MAX_RETRIES};
+
+/// Transactions are not resent by the transport, the tx sender replaces them itself (see `core::tx_sender`)
+const NON_RETRIED_METHODS: [&str; 2] = ["eth_sendRawTransaction", "eth_sendTransaction"];
+
+/// How an RPC failure should be handled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RpcErrorKind {
+    /// Timeouts, rate limits and server errors, the same request may succeed later
+    Transient,
+    /// The call reverted, retrying gives the same result
+    Revert,
+    /// Anything else, e.g. a malformed request or response
+    Fatal,
+}
+
+/// Exponential backoff: `initial_backoff`, doubled on each retry up to `max_backoff`
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: RPC_MAX_RETRIES,
+            initial_backoff: Duration::from_millis(RPC_INITIAL_BACKOFF_MS),
+            max_backoff: Duration::from_millis(RPC_MAX_BACKOFF_MS),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Time to wait before the retry number `retry` (starting at 0)
+    pub fn backoff(&self, retry: u32) -> Duration {
+        self.initial_backoff
+            .saturating_mul(2u32.saturating_pow(retry))
+            .min(self.max_backoff)
+    }
+}
+
+/// Sort a transport error into transient, revert or fatal
+pub fn classify_transport_error(error: &TransportError) -> RpcErrorKind {
+    match error {
+        TransportError::ErrorResp(payload) => classify_error_payload(payload),
+        TransportError::Transport(kind) => match kind {
+            TransportErrorKind::HttpError(http_error) => {
+                if http_error.status == 429 || http_error.status >= 500 {
+                    RpcErrorKind::Transient
+                } else {
+                    RpcErrorKind::Fatal
+                }
+            }
+            // Connection failures and timeouts of the http client
+            TransportErrorKind::Custom(_)
+            | TransportErrorKind::BackendGone
+            | TransportErrorKind::MissingBatchResponse(_) => RpcErrorKind::Transient,
+            _ => RpcErrorKind::Fatal,
+        },
+        TransportError::NullResp => RpcErrorKind::Transient,
+        _ => RpcErrorKind::Fatal,
+    }
+}
+
+/// Sort an error returned by the alloy calls (provider, contract or transport errors) into
+/// transient, revert or fatal
+pub fn classify_error(error: &Report) -> RpcErrorKind {
+    for cause in error.chain() {
+        if let Some(error) = cause.downcast_ref::<TransportError>() {
+            return classify_transport_error(error);
+        }
+
+        if let Some(alloy::contract::Error::TransportError(error)) =
+            cause.downcast_ref::<alloy::contract::Error>()
+        {
+            return classify_transport_error(error);
+        }
+    }
+
+    RpcErrorKind::Fatal
+}
+
+pub fn classify_error_payload(payload: &ErrorPayload) -> RpcErrorKind {
+    let message = payload.message.to_lowercase();
+
+    // 3 is the standard code of `execution reverted`, the hedera relay also uses `CONTRACT_REVERT_EXECUTED`
+    if payload.code == 3 || message.contains("revert") {
+        return RpcErrorKind::Revert;
+    }
+
+    if payload.is_retry_err()
+        || message.contains("timeout")
+        || message.contains("timed out")
+        || message.contains("rate limit")
+        || message.contains("too many requests")
+        || message.contains("unavailable")
+    {
+        return RpcErrorKind::Transient;
+    }
+
+    RpcErrorKind::Fatal
+}
+
+/// Retry `operation` with the backoff of the policy as long as it fails with a transient error
+pub async fn retry_with_backoff<T, F, Fut>(
+    label: &str,
+    policy: &RetryPolicy,
+    mut operation: F,
+) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let mut retry = 0;
+
+    loop {
+        match operation().await {
+            Ok(value) => return Ok(value),
+            Err(e)
+                if retry < policy.max_retries && classify_error(&e) == RpcErrorKind::Transient =>
+            {
+                let backoff = policy.backoff(retry);
+
+                warn!(
+                    "{} failed with a transient error, retry {}/{} in {:?}: {}",
+                    label,
+                    retry + 1,
+                    policy.max_retries,
+                    backoff,
+                    e
+                );
+
+                sleep(backoff).await;
+                retry += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Transport layer retrying every RPC request of the provider on transient errors
+#[derive(Debug, Clone, Default)]
+pub struct RpcRetryLayer {
+    policy: RetryPolicy,
+}
+
+impl RpcRetryLayer {
+    pub fn new(policy: RetryPolicy) -> Self {
+        Self { policy }
+    }
+}
+
+impl<S> Layer<S> for RpcRetryLayer {
+    type Service = RpcRetryService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RpcRetryService {
+            inner,
+            policy: self.policy.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RpcRetryService<S> {
+    inner: S,
+    policy: RetryPolicy,
+}
+
+impl<S> Service<RequestPacket> for RpcRetryService<S>
+where
+    S: Service<
+            RequestPacket,
+            Response = ResponsePacket,
+            Error = TransportError,
+            Future = TransportFut<'static>,
+        > + Clone
+        + Send
+        + Sync
+        + 'static,
+{
+    type Response = ResponsePacket;
+    type Error = TransportError;
+    type Future = TransportFut<'static>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: RequestPacket) -> Self::Future {
+        // Take the service that was polled ready, leave a clone in its place
+        let inner = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, inner);
+        let policy = self.policy.clone();
+
+        Box::pin(async move {
+            let is_retried = request
+                .method_names()
+                .all(|method| !NON_RETRIED_METHODS.contains(&method));
+
+            let mut retry = 0;
+
+            loop {
+                let result = inner.call(request.clone()).await;
+
+                // JSON-RPC errors come back inside a successful http response
+                let (kind, error) = match &result {
+                    Ok(response) => match response.as_error() {
+                        Some(payload) => (classify_error_payload(payload), payload.to_string()),
+                        None => return result,
+                    },
+                    Err(error) => (classify_transport_error(error), error.to_string()),
+                };
+
+                if !is_retried || kind != RpcErrorKind::Transient || retry >= policy.max_retries {
+                    return result;
+                }
+
+                let backoff = policy.backoff(retry);
+
+                warn!(
+                    "RPC request {:?} failed with a transient error, retry {}/{} in {:?}: {}",
+                    request.method_names().collect::<Vec<_>>(),
+                    retry + 1,
+                    policy.max_retries,
+                    backoff,
+                    error
+                );
+
+                sleep(backoff).await;
+                retry += 1;
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use alloy::transports::HttpError;
+
+    use super::*;
+
+    fn error_resp(code: i64, message: &str) -> TransportError {
+        TransportError::ErrorResp(ErrorPayload {
+            code,
+            message: message.to_string().into(),
+            data: None,
+        })
+    }
+
+    fn http_error(status: u16) -> TransportError {
+        TransportError::Transport(TransportErrorKind::HttpError(HttpError {
+            status,
+            body: String::new(),
+        }))
+    }
+
+    #[test]
+    fn test_classify_transport_errors() {
+        assert_eq!(
+            classify_transport_error(&http_error(429)),
+            RpcErrorKind::Transient
+        );
+        assert_eq!(
+            classify_transport_error(&http_error(502)),
+            RpcErrorKind::Transient
+        );
+        assert_eq!(
+            classify_transport_error(&http_error(400)),
+            RpcErrorKind::Fatal
+        );
+        assert_eq!(
+            classify_transport_error(&error_resp(3, "execution reverted: STF")),
+            RpcErrorKind::Revert
+        );
+        assert_eq!(
+            classify_transport_error(&error_resp(-32008, "CONTRACT_REVERT_EXECUTED")),
+            RpcErrorKind::Revert
+        );
+        assert_eq!(
+            classify_transport_error(&error_resp(-32020, "Request timeout. Please try again.")),
+            RpcErrorKind::Transient
+        );
+        assert_eq!(
+            classify_transport_error(&error_resp(-32602, "Invalid parameter 0")),
+            RpcErrorKind::Fatal
+        );
+        assert_eq!(
+            classify_transport_error(&TransportErrorKind::custom_str("connection reset")),
+            RpcErrorKind::Transient
+        );
+    }
+
+    #[test]
+    fn test_backoff_is_exponential_and_capped() {
+        let policy = RetryPolicy {
+            max_retries: 10,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(1),
+        };
+
+        assert_eq!(policy.backoff(0), Duration::from_millis(100));
+        assert_eq!(policy.backoff(2), Duration::from_millis(400));
+        assert_eq!(policy.backoff(4), Duration::from_secs(1));
+        assert_eq!(policy.backoff(40), Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn test_retry_only_transient_errors() {
+        let policy = RetryPolicy {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(1),
+        };
+
+        let mut calls = 0;
+        let result = retry_with_backoff("transient", &policy, || {
+            calls += 1;
+            let attempt = calls;
+            async move {
+                if attempt < 3 {
+                    Err(Report::new(http_error(503)))
+                } else {
+                    Ok(attempt)
+                }
+            }
+        })
+        .await;
+        assert_eq!(result.unwrap(), 3);
+
+        let mut calls = 0;
+        let result: Result<()> = retry_with_backoff("revert", &policy, || {
+            calls += 1;
+            async { Err(Report::new(error_resp(3, "execution reverted"))) }
+        })
+        .await;
+        assert!(result.is_err());
+        assert_eq!(calls, 1);
+    }
+}
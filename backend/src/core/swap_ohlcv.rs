@@ -0,0 +1,323 @@
+/*
+    Candles built from the pool `Swap` logs, for the networks and pools CoinGecko does not index (e.g. testnet).
+    The candles of each pool are stored in `ohlcv_data/` with the last scanned block, so each update only scans
+    the blocks mined since the previous one.
+*/
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use alloy::{
+    eips::BlockNumberOrTag,
+    primitives::{Address, U256},
+    providers::Provider,
+    rpc::types::Filter,
+    sol_types::SolEvent,
+};
+use color_eyre::eyre::{Context, Result, eyre};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use tracing::{debug, info, warn};
+
+use crate::{
+    config::{SWAP_LOGS_BLOCK_RANGE, SWAP_OHLCV_LOOKBACK_BLOCKS},
+    core::vault::UniswapV3Pool,
+    helpers::{amount::TokenAmount, math::sqrt_price_x96_to_price},
+    types::{OhlcvEntry, Pool},
+};
+
+/// Directory where the candles built from the pool swaps are stored
+pub const OHLCV_DATA_DIR: &str = "ohlcv_data";
+
+/// Vaults on the same pool share the candles file
+static OHLCV_FILES_LOCK: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+
+/// Price and volume of a swap, price1 and volume in token1 units
+#[derive(Debug, Clone, PartialEq)]
+pub struct SwapPoint {
+    pub timestamp: i64,
+    pub price1: f64,
+    pub volume1: f64,
+}
+
+/// Content of a candles file
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SwapOhlcvFile {
+    pub pool_address: String,
+    pub interval_seconds: u64,
+    /// Swaps up to this block are in the candles
+    pub last_scanned_block: u64,
+    /// Oldest first, intervals without swaps are skipped
+    pub candles: Vec<OhlcvEntry>,
+}
+
+/// Scan the pool swaps mined since the last update, add them to the stored candles and return all of them.
+/// Without stored candles the scan starts at `start_block`, or `SWAP_OHLCV_LOOKBACK_BLOCKS` back.
+pub async fn update_swap_ohlcv<P>(
+    provider: &P,
+    pool: &Pool,
+    interval_seconds: u64,
+    start_block: Option<u64>,
+) -> Result<Vec<OhlcvEntry>>
+where
+    P: Provider,
+{
+    let _guard = OHLCV_FILES_LOCK.lock().await;
+
+    let path = ohlcv_file_path(&pool.address, interval_seconds);
+    let latest_block = provider.get_block_number().await?;
+
+    let mut ohlcv_file = match load_ohlcv_file(&path)? {
+        Some(ohlcv_file)
+            if ohlcv_file.pool_address != pool.address.to_lowercase()
+                || ohlcv_file.interval_seconds != interval_seconds =>
+        {
+            return Err(eyre!(
+                "Ohlcv file {} holds {}s candles of pool {}",
+                path.display(),
+                ohlcv_file.interval_seconds,
+                ohlcv_file.pool_address
+            ));
+        }
+        Some(ohlcv_file) => ohlcv_file,
+        None => {
+            let first_block = start_block
+                .unwrap_or_else(|| latest_block.saturating_sub(SWAP_OHLCV_LOOKBACK_BLOCKS));
+
+            info!(
+                "No local candles for pool {}, scanning its swaps from block {}",
+                pool.address, first_block
+            );
+
+            SwapOhlcvFile {
+                pool_address: pool.address.to_lowercase(),
+                interval_seconds,
+                last_scanned_block: first_block.saturating_sub(1),
+                candles: vec![],
+            }
+        }
+    };
+
+    if ohlcv_file.last_scanned_block >= latest_block {
+        return Ok(ohlcv_file.candles);
+    }
+
+    let swaps = fetch_swaps(
+        provider,
+        pool,
+        ohlcv_file.last_scanned_block + 1,
+        latest_block,
+    )
+    .await?;
+
+    debug!(
+        "Pool {} has {} swaps in blocks {}..={}",
+        pool.address,
+        swaps.len(),
+        ohlcv_file.last_scanned_block + 1,
+        latest_block
+    );
+
+    aggregate_swaps(&mut ohlcv_file.candles, &swaps, interval_seconds);
+    ohlcv_file.last_scanned_block = latest_block;
+
+    fs::create_dir_all(OHLCV_DATA_DIR)?;
+    fs::write(&path, serde_json::to_string(&ohlcv_file)?)
+        .with_context(|| format!("Failed to write ohlcv file {}", path.display()))?;
+
+    Ok(ohlcv_file.candles)
+}
+
+/// Swaps of the pool in `from_block..=to_block`, oldest first. The logs are read in ranges of
+/// `SWAP_LOGS_BLOCK_RANGE` blocks.
+pub async fn fetch_swaps<P>(
+    provider: &P,
+    pool: &Pool,
+    from_block: u64,
+    to_block: u64,
+) -> Result<Vec<SwapPoint>>
+where
+    P: Provider,
+{
+    let pool_address = Address::from_str(&pool.address)?;
+
+    let mut swaps = vec![];
+    // The relays do not always return the block timestamp with the logs
+    let mut block_timestamps: HashMap<u64, i64> = HashMap::new();
+
+    let mut range_start = from_block;
+    while range_start <= to_block {
+        let range_end = (range_start + SWAP_LOGS_BLOCK_RANGE - 1).min(to_block);
+
+        let filter = Filter::new()
+            .address(pool_address)
+            .event_signature(UniswapV3Pool::Swap::SIGNATURE_HASH)
+            .from_block(range_start)
+            .to_block(range_end);
+
+        for log in provider.get_logs(&filter).await? {
+            let block_number = log
+                .block_number
+                .ok_or_else(|| eyre!("Swap log of pool {} without block number", pool_address))?;
+
+            let timestamp = match (log.block_timestamp, block_timestamps.get(&block_number)) {
+                (Some(timestamp), _) => timestamp as i64,
+                (None, Some(timestamp)) => *timestamp,
+                (None, None) => {
+                    let block = provider
+                        .get_block_by_number(BlockNumberOrTag::Number(block_number))
+                        .await?
+                        .ok_or_else(|| eyre!("Block {} not found", block_number))?;
+
+                    block.header.timestamp as i64
+                }
+            };
+            block_timestamps.insert(block_number, timestamp);
+
+            let swap = log.log_decode::<UniswapV3Pool::Swap>()?.inner.data;
+
+            swaps.push(SwapPoint {
+                timestamp,
+                price1: sqrt_price_x96_to_price(
+                    U256::from(swap.sqrtPriceX96),
+                    pool.token0.decimals,
+                    pool.token1.decimals,
+                ),
+                volume1: TokenAmount::new(swap.amount1.unsigned_abs(), pool.token1.decimals)
+                    .to_f64(),
+            });
+        }
+
+        range_start = range_end + 1;
+    }
+
+    Ok(swaps)
+}
+
+/// Add swaps to candles of `interval_seconds`. The swaps must be oldest first and not older than the last candle,
+/// the last candle is extended when they fall in its interval.
+pub fn aggregate_swaps(candles: &mut Vec<OhlcvEntry>, swaps: &[SwapPoint], interval_seconds: u64) {
+    let interval_seconds = interval_seconds as i64;
+
+    for swap in swaps {
+        let candle_start = swap.timestamp - swap.timestamp.rem_euclid(interval_seconds);
+
+        match candles.last_mut() {
+            Some(candle) if candle.timestamp() == candle_start => {
+                candle.2 = candle.high().max(swap.price1);
+                candle.3 = candle.low().min(swap.price1);
+                candle.4 = swap.price1;
+                candle.5 += swap.volume1;
+            }
+            Some(candle) if candle.timestamp() > candle_start => {
+                warn!(
+                    "Swap at {} is older than the last candle {}, skipping it",
+                    swap.timestamp,
+                    candle.timestamp()
+                );
+            }
+            _ => candles.push(OhlcvEntry(
+                candle_start,
+                swap.price1,
+                swap.price1,
+                swap.price1,
+                swap.price1,
+                swap.volume1,
+            )),
+        }
+    }
+}
+
+fn ohlcv_file_path(pool_address: &str, interval_seconds: u64) -> PathBuf {
+    PathBuf::from(OHLCV_DATA_DIR).join(format!(
+        "{}_{}s.json",
+        pool_address.to_lowercase(),
+        interval_seconds
+    ))
+}
+
+fn load_ohlcv_file(path: &Path) -> Result<Option<SwapOhlcvFile>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let raw = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read ohlcv file {}", path.display()))?;
+
+    let ohlcv_file = serde_json::from_str(&raw)
+        .with_context(|| format!("Failed to parse ohlcv file {}", path.display()))?;
+
+    Ok(Some(ohlcv_file))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn swap(timestamp: i64, price1: f64, volume1: f64) -> SwapPoint {
+        SwapPoint {
+            timestamp,
+            price1,
+            volume1,
+        }
+    }
+
+    #[test]
+    fn test_swaps_are_aggregated_into_candles() {
+        let mut candles = vec![];
+
+        aggregate_swaps(
+            &mut candles,
+            &[
+                swap(3_600, 1.0, 10.0),
+                swap(4_000, 1.2, 5.0),
+                swap(5_000, 0.9, 1.0),
+                swap(7_300, 1.1, 2.0),
+                // nothing in the third hour
+                swap(14_400, 1.3, 4.0),
+            ],
+            3_600,
+        );
+
+        assert_eq!(candles.len(), 3);
+
+        let OhlcvEntry(timestamp, open, high, low, close, volume) = candles[0].clone();
+        assert_eq!(
+            (timestamp, open, high, low, close, volume),
+            (3_600, 1.0, 1.2, 0.9, 0.9, 16.0)
+        );
+
+        assert_eq!(candles[1].timestamp(), 7_200);
+        assert_eq!(candles[1].close(), 1.1);
+        assert_eq!(candles[2].timestamp(), 14_400);
+        assert_eq!(candles[2].volume(), 4.0);
+    }
+
+    #[test]
+    fn test_later_scans_extend_the_last_candle() {
+        let mut candles = vec![];
+
+        aggregate_swaps(&mut candles, &[swap(86_400, 2.0, 1.0)], 86_400);
+        aggregate_swaps(
+            &mut candles,
+            &[
+                // older than the last candle, already scanned
+                swap(100, 9.0, 9.0),
+                swap(90_000, 2.5, 3.0),
+                swap(172_800, 2.2, 1.0),
+            ],
+            86_400,
+        );
+
+        assert_eq!(candles.len(), 2);
+        assert_eq!(candles[0].high(), 2.5);
+        assert_eq!(candles[0].close(), 2.5);
+        assert_eq!(candles[0].volume(), 4.0);
+        assert_eq!(candles[1].timestamp(), 172_800);
+    }
+}
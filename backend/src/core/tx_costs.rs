@@ -0,0 +1,224 @@
+use std::str::FromStr;
+
+use alloy::{
+    primitives::{Address, U256, utils::format_units},
+    providers::Provider,
+    sol,
+};
+use color_eyre::eyre::Result;
+use serde::Serialize;
+use tracing::debug;
+use utoipa::ToSchema;
+
+use crate::{
+    config::{EXCHANGE_RATE_PRECOMPILE_ADDRESS, GAS_LIMIT_MARGIN_BPS, HBAR_FEE_MARGIN_BPS},
+    core::vault::UniswapV3Pool,
+};
+
+sol! {
+    // Hedera exchange rate system contract, see `contracts/src/interfaces/IExchangeRate.sol`
+    #[sol(rpc)]
+    contract IExchangeRate {
+        function tinycentsToTinybars(uint256 tinycents) external returns (uint256);
+
+        function tinybarsToTinycents(uint256 tinybars) external returns (uint256);
+    }
+
+    #[sol(rpc)]
+    contract SaucerSwapFactory {
+        function mintFee() external view returns (uint256);
+    }
+}
+
+/// The JSON-RPC relay handles HBAR with 18 decimals (weibars), the network with 8 (tinybars)
+const WEIBARS_PER_TINYBAR: u64 = 10_000_000_000;
+
+/// HBAR a vault call has to carry to pay the SaucerSwap mint fee
+#[derive(Serialize, Debug, Clone, ToSchema)]
+pub struct HbarValue {
+    /// Mint fee of the pool factory converted at the network exchange rate, margin included (HBAR)
+    pub mint_fee: f64,
+    /// HBAR already held by the vault, left over from previous calls
+    pub vault_balance: f64,
+    /// HBAR to send with the call, the part of the fee the vault balance does not cover (HBAR)
+    pub value_to_send: f64,
+    #[serde(skip)]
+    pub value_to_send_weibars: U256,
+}
+
+impl HbarValue {
+    /// Split the mint fee between the HBAR of the vault and the value to send, amounts in weibars
+    pub fn new(mint_fee: U256, vault_balance: U256) -> Result<Self> {
+        let value_to_send_weibars = mint_fee.saturating_sub(vault_balance);
+
+        Ok(Self {
+            mint_fee: format_units(mint_fee, 18)?.parse()?,
+            vault_balance: format_units(vault_balance, 18)?.parse()?,
+            value_to_send: format_units(value_to_send_weibars, 18)?.parse()?,
+            value_to_send_weibars,
+        })
+    }
+}
+
+/// Gas limit to send with a call: the estimate plus `GAS_LIMIT_MARGIN_BPS`
+pub fn with_gas_margin(gas_estimate: u64) -> u64 {
+    gas_estimate + gas_estimate * GAS_LIMIT_MARGIN_BPS / 10_000
+}
+
+/// SaucerSwap mint fee of the pool factory in weibars. The factory stores it in tinycents (1e-10 USD),
+/// it is converted with the exchange rate precompile, the same way the vault does on chain.
+pub async fn get_mint_fee_weibars<P>(provider: &P, pool_address: &str) -> Result<U256>
+where
+    P: Provider,
+{
+    let pool_contract = UniswapV3Pool::new(Address::from_str(pool_address)?, provider);
+    let factory_address = pool_contract.factory().call().await?;
+
+    let mint_fee_tinycents = SaucerSwapFactory::new(factory_address, provider)
+        .mintFee()
+        .call()
+        .await?;
+
+    if mint_fee_tinycents.is_zero() {
+        return Ok(U256::ZERO);
+    }
+
+    let exchange_rate = IExchangeRate::new(
+        Address::from_str(EXCHANGE_RATE_PRECOMPILE_ADDRESS)?,
+        provider,
+    );
+    let mint_fee_tinybars = exchange_rate
+        .tinycentsToTinybars(mint_fee_tinycents)
+        .call()
+        .await?;
+
+    Ok(mint_fee_tinybars_to_weibars(mint_fee_tinybars))
+}
+
+/// Mint fee to send in weibars, from the tinybars of the exchange rate conversion
+fn mint_fee_tinybars_to_weibars(mint_fee_tinybars: U256) -> U256 {
+    // Same slop as the vault for the conversion rounding, plus a margin for rate moves until execution
+    let mint_fee_tinybars = mint_fee_tinybars + U256::from(1);
+    let mint_fee_tinybars = mint_fee_tinybars
+        + mint_fee_tinybars * U256::from(HBAR_FEE_MARGIN_BPS) / U256::from(10_000);
+
+    mint_fee_tinybars * U256::from(WEIBARS_PER_TINYBAR)
+}
+
+/// HBAR to send with a vault call that mints liquidity. The HBAR refunded to the vault by previous calls is
+/// used first, so the operator wallet only tops up what is missing.
+pub async fn get_hbar_value_to_send<P>(
+    provider: &P,
+    vault_address: &str,
+    pool_address: &str,
+) -> Result<HbarValue>
+where
+    P: Provider,
+{
+    let mint_fee = get_mint_fee_weibars(provider, pool_address).await?;
+    let vault_balance = provider
+        .get_balance(Address::from_str(vault_address)?)
+        .await?;
+
+    let hbar_value = HbarValue::new(mint_fee, vault_balance)?;
+
+    debug!("HBAR value for vault {}: {:?}", vault_address, hbar_value);
+
+    Ok(hbar_value)
+}
+
+/// HBAR paid by a call: the whole gas limit at the current gas price, plus the mint fee it carries.
+/// Hedera charges at least 80% of the gas limit, so the limit is a close upper bound of the gas paid.
+pub async fn get_tx_cost_hbar<P>(
+    provider: &P,
+    gas_limit: u64,
+    hbar_value: &HbarValue,
+) -> Result<f64>
+where
+    P: Provider,
+{
+    // Gas prices are in weibars (1e-18 HBAR)
+    let gas_price = provider.get_gas_price().await?;
+    let gas_cost: f64 = format_units(U256::from(gas_price) * U256::from(gas_limit), 18)?.parse()?;
+
+    Ok(gas_cost + hbar_value.mint_fee)
+}
+
+/// HBAR left in the vault after a call, this is the unused part of the values sent so far
+pub async fn get_vault_hbar_balance<P>(provider: &P, vault_address: &str) -> Result<f64>
+where
+    P: Provider,
+{
+    let balance = provider
+        .get_balance(Address::from_str(vault_address)?)
+        .await?;
+
+    Ok(format_units(balance, 18)?.parse()?)
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+    use crate::core::mock_rpc::{MockRpc, rpc_error};
+
+    const HBAR: u64 = 100_000_000; // tinybars
+
+    #[test]
+    fn test_mint_fee_is_converted_to_weibars_with_margin() {
+        let mint_fee = mint_fee_tinybars_to_weibars(U256::from(HBAR));
+
+        // 1 HBAR plus the rounding tinybar, plus 10%
+        let mint_fee_tinybars = (HBAR + 1) + (HBAR + 1) * HBAR_FEE_MARGIN_BPS / 10_000;
+        assert_eq!(
+            mint_fee,
+            U256::from(mint_fee_tinybars) * U256::from(WEIBARS_PER_TINYBAR)
+        );
+        assert_eq!(mint_fee, U256::from(1_100_000_010_000_000_000u64));
+    }
+
+    #[test]
+    fn test_vault_balance_pays_the_mint_fee_first() {
+        let mint_fee = U256::from(1_100_000_010_000_000_000u64);
+
+        let hbar_value = HbarValue::new(mint_fee, U256::from(500_000_000_000_000_000u64)).unwrap();
+        assert_eq!(hbar_value.mint_fee, 1.10000001);
+        assert_eq!(hbar_value.vault_balance, 0.5);
+        assert_eq!(
+            hbar_value.value_to_send_weibars,
+            U256::from(600_000_010_000_000_000u64)
+        );
+        assert_eq!(hbar_value.value_to_send, 0.60000001);
+
+        let hbar_value = HbarValue::new(mint_fee, U256::from(2) * mint_fee).unwrap();
+        assert_eq!(hbar_value.value_to_send_weibars, U256::ZERO);
+        assert_eq!(hbar_value.value_to_send, 0.0);
+    }
+
+    #[test]
+    fn test_gas_margin() {
+        assert_eq!(
+            with_gas_margin(1_000_000),
+            1_000_000 + 1_000_000 * GAS_LIMIT_MARGIN_BPS / 10_000
+        );
+        assert_eq!(with_gas_margin(0), 0);
+    }
+
+    #[tokio::test]
+    async fn test_tx_cost_adds_the_gas_limit_at_the_gas_price_to_the_mint_fee() {
+        // 710 gwei, the usual Hedera gas price in weibars
+        let rpc = MockRpc::new(|method, _| match method {
+            "eth_gasPrice" => Ok(json!("0xa54f4c3c00")),
+            _ => Err(rpc_error(-32601, "method not found")),
+        });
+        let hbar_value =
+            HbarValue::new(U256::from(1_100_000_010_000_000_000u64), U256::ZERO).unwrap();
+
+        let tx_cost = get_tx_cost_hbar(&rpc.provider(), 1_000_000, &hbar_value)
+            .await
+            .unwrap();
+
+        assert!((tx_cost - (0.71 + 1.10000001)).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,405 @@
+use std::time::Duration;
+
+use alloy::{
+    network::TransactionBuilder,
+    primitives::TxHash,
+    providers::{Provider, WalletProvider},
+    rpc::types::{TransactionReceipt, TransactionRequest},
+};
+use color_eyre::eyre::{Result, eyre};
+use tokio::{
+    sync::{mpsc, oneshot},
+    time::{Instant, sleep},
+};
+use tracing::{error, info, warn};
+
+use crate::{
+    config::{
+        MAX_TX_REPLACEMENTS, TX_RECEIPT_POLL_INTERVAL_MS, TX_RECEIPT_TIMEOUT_SECONDS,
+        TX_REPLACEMENT_GAS_PRICE_BUMP_BPS,
+    },
+    types::EvmProvider,
+};
+
+/// A transaction waiting in the queue, with the channel its receipt is returned on
+struct TxJob {
+    label: String,
+    tx: TransactionRequest,
+    respond_to: oneshot::Sender<Result<TransactionReceipt>>,
+}
+
+/// Single entry point to send transactions from the operator wallet.
+///
+/// Transactions are queued and sent one at a time by a background task that owns the wallet nonce, so the
+/// vault loops and the admin endpoints never sign two transactions with the same nonce. Each transaction is
+/// confirmed before the next one is sent, and replaced with a higher gas price when it is not mined in time.
+#[derive(Clone)]
+pub struct TxSender {
+    queue: mpsc::UnboundedSender<TxJob>,
+}
+
+impl TxSender {
+    /// Start the sender task, it runs as long as a `TxSender` handle is alive
+    pub fn new(provider: EvmProvider) -> Self {
+        let (queue, jobs) = mpsc::unbounded_channel();
+
+        tokio::spawn(run_tx_queue(provider, jobs));
+
+        Self { queue }
+    }
+
+    /// Queue the transaction and wait for its receipt. Errors if the transaction can not be sent or
+    /// is not mined after all its replacements, a reverted transaction returns its receipt with a false status.
+    ///
+    /// - label: Name of the transaction in the logs, e.g. "rebalance 0x..."
+    /// - tx: Transaction to send, the nonce and gas price are set by the sender
+    pub async fn send(
+        &self,
+        label: impl Into<String>,
+        tx: TransactionRequest,
+    ) -> Result<TransactionReceipt> {
+        let (respond_to, receipt) = oneshot::channel();
+
+        self.queue
+            .send(TxJob {
+                label: label.into(),
+                tx,
+                respond_to,
+            })
+            .map_err(|_| eyre!("Transaction sender is stopped"))?;
+
+        receipt
+            .await
+            .map_err(|_| eyre!("Transaction sender dropped the transaction"))?
+    }
+}
+
+async fn run_tx_queue(provider: EvmProvider, mut jobs: mpsc::UnboundedReceiver<TxJob>) {
+    // Next nonce of the operator wallet, read again from the network after a failure
+    let mut next_nonce: Option<u64> = None;
+
+    while let Some(job) = jobs.recv().await {
+        let result = send_and_confirm(&provider, &mut next_nonce, &job.label, job.tx).await;
+
+        if let Err(e) = &result {
+            error!("Transaction {} failed: {:?}", job.label, e);
+            next_nonce = None;
+        }
+
+        // the caller may have given up waiting, the receipt is logged anyway
+        let _ = job.respond_to.send(result);
+    }
+}
+
+async fn send_and_confirm(
+    provider: &EvmProvider,
+    next_nonce: &mut Option<u64>,
+    label: &str,
+    tx: TransactionRequest,
+) -> Result<TransactionReceipt> {
+    let from = provider.default_signer_address();
+
+    let nonce = match *next_nonce {
+        Some(nonce) => nonce,
+        None => provider.get_transaction_count(from).pending().await?,
+    };
+
+    let mut gas_price = provider.get_gas_price().await?;
+    let mut tx = tx.with_from(from).with_nonce(nonce);
+    let mut tx_hashes: Vec<TxHash> = Vec::new();
+
+    for attempt in 0..=MAX_TX_REPLACEMENTS {
+        tx = tx.with_gas_price(gas_price);
+
+        match provider.send_transaction(tx.clone()).await {
+            Ok(pending) => {
+                let tx_hash = *pending.tx_hash();
+
+                info!(
+                    "Sent transaction {} with nonce {} (attempt {}): {}",
+                    label, nonce, attempt, tx_hash
+                );
+
+                tx_hashes.push(tx_hash);
+            }
+            // A previous attempt got mined while we were about to replace it
+            Err(e) if !tx_hashes.is_empty() && is_nonce_used_error(&e.to_string()) => {
+                warn!(
+                    "Replacement of transaction {} not needed, nonce {} is used: {}",
+                    label, nonce, e
+                );
+            }
+            Err(e) => return Err(e.into()),
+        }
+
+        if let Some(receipt) = wait_for_receipt(provider, &tx_hashes).await? {
+            info!(
+                "Transaction {} confirmed in block {:?} with status {}: {}",
+                label,
+                receipt.block_number,
+                receipt.status(),
+                receipt.transaction_hash
+            );
+
+            *next_nonce = Some(nonce + 1);
+
+            return Ok(receipt);
+        }
+
+        gas_price += gas_price * TX_REPLACEMENT_GAS_PRICE_BUMP_BPS as u128 / 10_000;
+
+        warn!(
+            "Transaction {} with nonce {} not mined after {}s, replacing it with gas price {}",
+            label, nonce, TX_RECEIPT_TIMEOUT_SECONDS, gas_price
+        );
+    }
+
+    Err(eyre!(
+        "Transaction {} with nonce {} not mined after {} replacements: {:?}",
+        label,
+        nonce,
+        MAX_TX_REPLACEMENTS,
+        tx_hashes
+    ))
+}
+
+/// Poll the receipts of every version of the transaction until one is mined or the timeout is reached
+async fn wait_for_receipt(
+    provider: &EvmProvider,
+    tx_hashes: &[TxHash],
+) -> Result<Option<TransactionReceipt>> {
+    let deadline = Instant::now() + Duration::from_secs(TX_RECEIPT_TIMEOUT_SECONDS);
+
+    loop {
+        for tx_hash in tx_hashes {
+            if let Some(receipt) = provider.get_transaction_receipt(*tx_hash).await? {
+                return Ok(Some(receipt));
+            }
+        }
+
+        if Instant::now() >= deadline {
+            return Ok(None);
+        }
+
+        sleep(Duration::from_millis(TX_RECEIPT_POLL_INTERVAL_MS)).await;
+    }
+}
+
+fn is_nonce_used_error(error: &str) -> bool {
+    let error = error.to_lowercase();
+
+    error.contains("nonce too low")
+        || error.contains("nonce has already been used")
+        || error.contains("already known")
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::HashSet,
+        sync::{Arc, Mutex},
+    };
+
+    use alloy::{
+        consensus::{Transaction, TxEnvelope},
+        eips::Decodable2718,
+        primitives::{Address, Bytes, U256},
+    };
+    use serde_json::{Value, json};
+
+    use super::*;
+    use crate::core::mock_rpc::{MOCK_CHAIN_ID, MockRpc, rpc_error};
+
+    /// Node state behind the mock transport
+    #[derive(Default)]
+    struct Node {
+        /// Transaction count of the wallet
+        nonce: u64,
+        gas_price: u128,
+        /// A transaction is mined on this attempt for its nonce, 1 mines the first one sent
+        mined_on_attempt: usize,
+        /// Transactions to this address are rejected by the node
+        rejected_to: Option<Address>,
+        sent: Vec<TxEnvelope>,
+        mined: HashSet<TxHash>,
+    }
+
+    fn mock_node(node: Arc<Mutex<Node>>) -> MockRpc {
+        MockRpc::new(move |method, params| {
+            let mut guard = node.lock().unwrap();
+            let node = &mut *guard;
+
+            match method {
+                "eth_chainId" => Ok(json!(format!("{:#x}", MOCK_CHAIN_ID))),
+                "eth_getTransactionCount" => Ok(json!(format!("{:#x}", node.nonce))),
+                "eth_gasPrice" => Ok(json!(format!("{:#x}", node.gas_price))),
+                "eth_sendRawTransaction" => {
+                    let raw: Bytes = serde_json::from_value(params[0].clone()).unwrap();
+                    let tx = TxEnvelope::decode_2718(&mut raw.as_ref()).unwrap();
+
+                    if tx.to().is_some() && tx.to() == node.rejected_to {
+                        return Err(rpc_error(
+                            -32000,
+                            "insufficient funds for gas * price + value",
+                        ));
+                    }
+
+                    let tx_hash = *tx.tx_hash();
+                    let attempt = node
+                        .sent
+                        .iter()
+                        .filter(|sent| Transaction::nonce(*sent) == tx.nonce())
+                        .count()
+                        + 1;
+
+                    if attempt >= node.mined_on_attempt {
+                        node.mined.insert(tx_hash);
+                        node.nonce = tx.nonce() + 1;
+                    }
+                    node.sent.push(tx);
+
+                    Ok(json!(tx_hash))
+                }
+                "eth_getTransactionReceipt" => {
+                    let tx_hash: TxHash = serde_json::from_value(params[0].clone()).unwrap();
+
+                    Ok(match node.mined.contains(&tx_hash) {
+                        true => receipt(tx_hash),
+                        false => Value::Null,
+                    })
+                }
+                _ => Err(rpc_error(-32601, "method not found")),
+            }
+        })
+    }
+
+    fn receipt(tx_hash: TxHash) -> Value {
+        json!({
+            "type": "0x0",
+            "status": "0x1",
+            "transactionHash": tx_hash,
+            "transactionIndex": "0x0",
+            "blockHash": TxHash::with_last_byte(1),
+            "blockNumber": "0x10",
+            "from": Address::ZERO,
+            "to": Address::ZERO,
+            "cumulativeGasUsed": "0x5208",
+            "gasUsed": "0x5208",
+            "effectiveGasPrice": "0x64",
+            "contractAddress": null,
+            "logs": [],
+            "logsBloom": format!("0x{}", "00".repeat(256)),
+        })
+    }
+
+    fn transfer(to: u8) -> TransactionRequest {
+        TransactionRequest::default()
+            .with_to(Address::with_last_byte(to))
+            .with_value(U256::from(1))
+            .with_gas_limit(21_000)
+    }
+
+    /// Recipient, nonce and gas price of the raw transactions sent to the node
+    fn sent(node: &Arc<Mutex<Node>>) -> Vec<(Option<Address>, u64, Option<u128>)> {
+        node.lock()
+            .unwrap()
+            .sent
+            .iter()
+            .map(|tx| (tx.to(), tx.nonce(), tx.gas_price()))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_queue_sends_in_order_with_tracked_nonces() {
+        let node = Arc::new(Mutex::new(Node {
+            nonce: 7,
+            gas_price: 100,
+            mined_on_attempt: 1,
+            ..Node::default()
+        }));
+        let rpc = mock_node(node.clone());
+        let tx_sender = TxSender::new(rpc.provider());
+
+        let (first, second, third) = tokio::join!(
+            tx_sender.send("first", transfer(1)),
+            tx_sender.send("second", transfer(2)),
+            tx_sender.send("third", transfer(3)),
+        );
+        assert!(first.unwrap().status() && second.unwrap().status() && third.unwrap().status());
+
+        assert_eq!(
+            sent(&node),
+            vec![
+                (Some(Address::with_last_byte(1)), 7, Some(100)),
+                (Some(Address::with_last_byte(2)), 8, Some(100)),
+                (Some(Address::with_last_byte(3)), 9, Some(100)),
+            ]
+        );
+        // The nonce is read once, then tracked by the sender
+        assert_eq!(rpc.count("eth_getTransactionCount"), 1);
+    }
+
+    #[tokio::test]
+    async fn test_nonce_is_read_again_after_a_failure() {
+        let node = Arc::new(Mutex::new(Node {
+            nonce: 7,
+            gas_price: 100,
+            mined_on_attempt: 1,
+            rejected_to: Some(Address::with_last_byte(2)),
+            ..Node::default()
+        }));
+        let rpc = mock_node(node.clone());
+        let tx_sender = TxSender::new(rpc.provider());
+
+        tx_sender.send("first", transfer(1)).await.unwrap();
+        assert!(tx_sender.send("second", transfer(2)).await.is_err());
+        tx_sender.send("third", transfer(3)).await.unwrap();
+
+        assert_eq!(rpc.count("eth_getTransactionCount"), 2);
+        assert_eq!(
+            sent(&node),
+            vec![
+                (Some(Address::with_last_byte(1)), 7, Some(100)),
+                (Some(Address::with_last_byte(3)), 8, Some(100)),
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_unmined_transaction_is_replaced_with_a_higher_gas_price() {
+        let node = Arc::new(Mutex::new(Node {
+            nonce: 7,
+            gas_price: 100,
+            mined_on_attempt: 2,
+            ..Node::default()
+        }));
+        let rpc = mock_node(node.clone());
+        let tx_sender = TxSender::new(rpc.provider());
+
+        let receipt = tx_sender.send("stuck", transfer(1)).await.unwrap();
+
+        let bumped_gas_price = 100 + 100 * TX_REPLACEMENT_GAS_PRICE_BUMP_BPS as u128 / 10_000;
+        assert_eq!(
+            sent(&node),
+            vec![
+                (Some(Address::with_last_byte(1)), 7, Some(100)),
+                (Some(Address::with_last_byte(1)), 7, Some(bumped_gas_price)),
+            ]
+        );
+        assert_eq!(
+            receipt.transaction_hash,
+            *node.lock().unwrap().sent[1].tx_hash()
+        );
+    }
+
+    #[test]
+    fn test_nonce_used_errors() {
+        assert!(is_nonce_used_error(
+            "server returned an error response: error code -32001: Nonce too low. Provided nonce: 5, current nonce: 6"
+        ));
+        assert!(is_nonce_used_error("already known"));
+        assert!(!is_nonce_used_error(
+            "insufficient funds for gas * price + value"
+        ));
+    }
+}
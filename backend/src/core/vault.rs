@@ -1,17 +1,19 @@
 use std::str::FromStr;
 
 use alloy::{
+    eips::BlockId,
     primitives::{Address, U256, keccak256, utils::format_units},
     providers::{Provider, WalletProvider},
     sol,
 };
 use alloy_sol_types::SolValue;
-use color_eyre::eyre::Result;
-use tracing::{info, trace};
+use color_eyre::eyre::{Result, eyre};
+use tracing::{debug, info, trace};
 
 use crate::{
-    config::{CONFIG, FEE_FACTOR},
-    helpers,
+    config::{CONFIG, CROSS_CHECK_MAX_TICK_DEVIATION, FEE_FACTOR, RPC_MAX_BLOCK_LAG},
+    core::{multicall::ReadBatch, rpc_pool::RpcPool},
+    helpers::{self, amount::TokenAmount},
     types::{Pool, Position, Token, VaultDetails, VaultTVL, VaultTokenBalances},
 };
 
@@ -40,6 +42,8 @@ sol! {
     // The `rpc` attribute enables contract interaction via the provider.
     #[sol(rpc)]
     contract UniswapV3Pool {
+        function factory() external view returns (address);
+
         function token0() external view returns (address);
 
         function token1() external view returns (address);
@@ -53,6 +57,10 @@ sol! {
 
         function slot0() external view returns (uint160 sqrtPriceX96, int24 tick, uint16 observationIndex, uint16 observationCardinality, uint16 observationCardinalityNext, uint8 feeProtocol, bool unlocked);
 
+        function observe(uint32[] calldata secondsAgos) external view returns (int56[] memory tickCumulatives, uint160[] memory secondsPerLiquidityCumulativeX128s);
+
+        event Swap(address indexed sender, address indexed recipient, int256 amount0, int256 amount1, uint160 sqrtPriceX96, uint128 liquidity, int24 tick);
+
 
         function positions(bytes32 key) external view returns (
             uint128 liquidity,
@@ -61,6 +69,19 @@ sol! {
             uint128 tokensOwed0,
             uint128 tokensOwed1
         );
+
+        function ticks(int24 tick) external view returns (
+            uint128 liquidityGross,
+            int128 liquidityNet,
+            uint256 feeGrowthOutside0X128,
+            uint256 feeGrowthOutside1X128,
+            int56 tickCumulativeOutside,
+            uint160 secondsPerLiquidityOutsideX128,
+            uint32 secondsOutside,
+            bool initialized
+        );
+
+        function tickBitmap(int16 wordPosition) external view returns (uint256);
     }
 }
 
@@ -68,50 +89,88 @@ pub async fn get_vault_details<P>(provider: &P, vault_address: &str) -> Result<V
 where
     P: Provider + WalletProvider,
 {
-    let vault = ManiXAIVault::new(Address::from_str(vault_address)?, provider);
+    let vault = Address::from_str(vault_address)?;
 
     let hbar_evm_address = &CONFIG.toml_config.hbar_evm_address;
 
-    let name = vault.name().call().await?;
-    let symbol = vault.symbol().call().await?;
-    let decimals = vault.decimals().call().await?;
-    let total_supply = vault.totalSupply().call().await?;
-    let pool_address = vault.pool().call().await?;
-    let token0_address = vault.token0().call().await?;
-    let token1_address = vault.token1().call().await?;
-    let mut fee: f64 = vault.fee().call().await?.into();
+    // Every read is pinned to the same block, the details are a consistent snapshot
+    let block = BlockId::number(provider.get_block_number().await?);
+
+    // Fetch the vault state
+    let mut batch = ReadBatch::new();
+    let name = batch.add(vault, ManiXAIVault::nameCall {});
+    let symbol = batch.add(vault, ManiXAIVault::symbolCall {});
+    let decimals = batch.add(vault, ManiXAIVault::decimalsCall {});
+    let total_supply = batch.add(vault, ManiXAIVault::totalSupplyCall {});
+    let pool_address = batch.add(vault, ManiXAIVault::poolCall {});
+    let token0_address = batch.add(vault, ManiXAIVault::token0Call {});
+    let token1_address = batch.add(vault, ManiXAIVault::token1Call {});
+    let fee = batch.add(vault, ManiXAIVault::feeCall {});
+    let tick_spacing = batch.add(vault, ManiXAIVault::tickSpacingCall {});
+    let lower_tick_org = batch.add(vault, ManiXAIVault::lowerTickCall {});
+    let upper_tick_org = batch.add(vault, ManiXAIVault::upperTickCall {});
+    let is_active = batch.add(vault, ManiXAIVault::isActiveCall {});
+    let is_vault_tokens_associated = batch.add(vault, ManiXAIVault::isVaultTokensAssociatedCall {});
+
+    let vault_results = batch.execute(provider, block).await?;
+
+    let name = vault_results.get(&name)?;
+    let symbol = vault_results.get(&symbol)?;
+    let decimals = vault_results.get(&decimals)?;
+    let total_supply = vault_results.get(&total_supply)?;
+    let pool_address = vault_results.get(&pool_address)?;
+    let token0_address = vault_results.get(&token0_address)?;
+    let token1_address = vault_results.get(&token1_address)?;
+    let mut fee: f64 = vault_results.get(&fee)?.into();
     fee = fee / FEE_FACTOR;
-    let tick_spacing = vault.tickSpacing().call().await?.as_i32();
-    let lower_tick_org = vault.lowerTick().call().await?;
+    let tick_spacing = vault_results.get(&tick_spacing)?.as_i32();
+    let lower_tick_org = vault_results.get(&lower_tick_org)?;
     let lower_tick = lower_tick_org.as_i32();
-    let upper_tick_org = vault.upperTick().call().await?;
+    let upper_tick_org = vault_results.get(&upper_tick_org)?;
     let upper_tick = upper_tick_org.as_i32();
-    let is_active = vault.isActive().call().await?;
-    let is_vault_tokens_associated = vault.isVaultTokensAssociated().call().await?;
-
-    let pool_contract = UniswapV3Pool::new(pool_address, provider);
+    let is_active = vault_results.get(&is_active)?;
+    let is_vault_tokens_associated = vault_results.get(&is_vault_tokens_associated)?;
+
+    // Fetch the pool price, the tokens details, the vault balances and position
+    let position_key = keccak256((vault, lower_tick_org, upper_tick_org).abi_encode_packed());
+
+    let mut batch = ReadBatch::new();
+    let slot0 = batch.add(pool_address, UniswapV3Pool::slot0Call {});
+    let token0_name = batch.add(token0_address, ERC20::nameCall {});
+    let token0_symbol = batch.add(token0_address, ERC20::symbolCall {});
+    let token0_decimals = batch.add(token0_address, ERC20::decimalsCall {});
+    let token1_name = batch.add(token1_address, ERC20::nameCall {});
+    let token1_symbol = batch.add(token1_address, ERC20::symbolCall {});
+    let token1_decimals = batch.add(token1_address, ERC20::decimalsCall {});
+    let balance0 = batch.add(token0_address, ERC20::balanceOfCall { account: vault });
+    let balance1 = batch.add(token1_address, ERC20::balanceOfCall { account: vault });
+    let position_details = is_active.then(|| {
+        batch.add(
+            pool_address,
+            UniswapV3Pool::positionsCall { key: position_key },
+        )
+    });
+
+    let pool_results = batch.execute(provider, block).await?;
 
     // Ftehcing sqrt and tick of the pool
-    let slot0 = pool_contract.slot0().call().await?;
-    let sqrt_price_x96 = U256::from_str(slot0.sqrtPriceX96.to_string().as_str())?;
+    let slot0 = pool_results.get(&slot0)?;
+    let sqrt_price_x96 = U256::from(slot0.sqrtPriceX96);
     let current_tick = slot0.tick.as_i32();
 
     let total_supply: f64 = format_units(total_supply, decimals)?.parse()?;
 
     // Ftehc token0 and token1 details
-    let token0 = ERC20::new(token0_address, provider);
-    let token1 = ERC20::new(token1_address, provider);
-
     let token0_address = token0_address.to_string();
-    let token0_name = token0.name().call().await?;
-    let token0_symbol = token0.symbol().call().await?;
-    let token0_decimals = token0.decimals().call().await?;
+    let token0_name = pool_results.get(&token0_name)?;
+    let token0_symbol = pool_results.get(&token0_symbol)?;
+    let token0_decimals = pool_results.get(&token0_decimals)?;
     let is_token0_native_wrapper = token0_address.to_lowercase() == hbar_evm_address.to_lowercase();
 
     let token1_address = token1_address.to_string();
-    let token1_name = token1.name().call().await?;
-    let token1_symbol = token1.symbol().call().await?;
-    let token1_decimals = token1.decimals().call().await?;
+    let token1_name = pool_results.get(&token1_name)?;
+    let token1_symbol = pool_results.get(&token1_symbol)?;
+    let token1_decimals = pool_results.get(&token1_decimals)?;
     let is_token1_native_wrapper = token1_address.to_lowercase() == hbar_evm_address.to_lowercase();
 
     // Calculate the pool price1 and price0 by using the current tick
@@ -121,17 +180,8 @@ where
     let position: Position;
 
     // Fetch the vault current position
-    if is_active {
-        let value = (
-            Address::from_str(vault_address)?,
-            lower_tick_org,
-            upper_tick_org,
-        );
-        let res_value = value.abi_encode_packed();
-
-        let position_key = keccak256(res_value);
-
-        let position_details = pool_contract.positions(position_key).call().await?;
+    if let Some(position_details) = position_details {
+        let position_details = pool_results.get(&position_details)?;
 
         let liquidity = position_details.liquidity;
         let tokens_owed_0 = position_details.tokensOwed0;
@@ -150,44 +200,23 @@ where
                 liquidity,
             )?;
 
-        let formatted_amount0: f64 = format_units(amount0, token0_decimals)?.parse()?;
-        let formatted_amount1: f64 = format_units(amount1, token1_decimals)?.parse()?;
-        let formatted_tokens_owed_0: f64 = format_units(tokens_owed_0, token0_decimals)?.parse()?;
-        let formatted_tokens_owed_1: f64 = format_units(tokens_owed_1, token1_decimals)?.parse()?;
-
         position = Position {
             tick_lower: lower_tick,
             tick_upper: upper_tick,
             liquidity,
-            amount0: formatted_amount0,
-            amount1: formatted_amount1,
-            fees0: formatted_tokens_owed_0,
-            fees1: formatted_tokens_owed_1,
+            amount0: TokenAmount::new(amount0, token0_decimals),
+            amount1: TokenAmount::new(amount1, token1_decimals),
+            fees0: TokenAmount::new(U256::from(tokens_owed_0), token0_decimals),
+            fees1: TokenAmount::new(U256::from(tokens_owed_1), token1_decimals),
         };
     } else {
-        position = Position::default();
+        position = Position::empty(token0_decimals, token1_decimals);
     }
 
-    let balance0 = token0
-        .balanceOf(Address::from_str(vault_address)?)
-        .call()
-        .await?;
-
-    let balance1 = token1
-        .balanceOf(Address::from_str(vault_address)?)
-        .call()
-        .await?;
-
-    let balance0: f64 = format_units(balance0, token0_decimals)?.parse()?;
-    let balance1: f64 = format_units(balance1, token1_decimals)?.parse()?;
-
-    let vault_tvl0 = position.amount0 + position.fees0 + balance0;
-    let vault_tvl1 = position.amount1 + position.fees1 + balance1;
+    let balance0 = TokenAmount::new(pool_results.get(&balance0)?, token0_decimals);
+    let balance1 = TokenAmount::new(pool_results.get(&balance1)?, token1_decimals);
 
-    let tvl = VaultTVL {
-        tvl0: vault_tvl0,
-        tvl1: vault_tvl1,
-    };
+    let tvl = get_vault_tvl(&position, &balance0, &balance1)?;
 
     Ok(VaultDetails {
         address: vault_address.to_string(),
@@ -227,44 +256,21 @@ where
     })
 }
 
-pub async fn get_vault_tokens_balances<P>(
-    provider: &P,
-    vault: &VaultDetails,
-) -> Result<VaultTokenBalances>
-where
-    P: Provider + WalletProvider,
-{
-    let vault_address = Address::from_str(vault.address.as_str())?;
-
-    let token0 = &vault.pool.token0;
-    let token1 = &vault.pool.token1;
-
-    let token0_balance: f64;
-    let token1_balance: f64;
-    let token0_balance_u256: U256;
-    let token1_balance_u256: U256;
-
-    let balance = ERC20::new(Address::from_str(token0.address.as_str())?, provider)
-        .balanceOf(vault_address)
-        .call()
-        .await?;
-
-    token0_balance_u256 = balance;
-    token0_balance = format_units(balance, token0.decimals)?.parse()?;
-
-    let balance = ERC20::new(Address::from_str(token1.address.as_str())?, provider)
-        .balanceOf(vault_address)
-        .call()
-        .await?;
-
-    token1_balance_u256 = balance;
-    token1_balance = format_units(balance, token1.decimals)?.parse()?;
-
-    Ok(VaultTokenBalances {
-        token0_balance,
-        token1_balance,
-        token0_balance_u256,
-        token1_balance_u256,
+/// Vault TVL: the position amounts, its uncollected fees and the idle balances of the vault
+fn get_vault_tvl(
+    position: &Position,
+    balance0: &TokenAmount,
+    balance1: &TokenAmount,
+) -> Result<VaultTVL> {
+    Ok(VaultTVL {
+        tvl0: position
+            .amount0
+            .checked_add(&position.fees0)?
+            .checked_add(balance0)?,
+        tvl1: position
+            .amount1
+            .checked_add(&position.fees1)?
+            .checked_add(balance1)?,
     })
 }
 
@@ -283,24 +289,52 @@ where
     Ok(())
 }
 
-pub async fn update_vault_live<P>(provider: &P, vault: &mut VaultDetails) -> Result<()>
+/// Refresh the price, range and position of the vault at a single block. Returns the token balances of the
+/// vault read at that same block.
+pub async fn update_vault_live<P>(
+    provider: &P,
+    vault: &mut VaultDetails,
+) -> Result<VaultTokenBalances>
 where
     P: Provider + WalletProvider,
 {
     trace!("Updating vault live data...");
     let vault_address = Address::from_str(vault.address.as_str())?;
+    let pool_address: Address = vault.pool.address.parse()?;
+
+    // Every read is pinned to the same block, the live data is a consistent snapshot
+    let block = BlockId::number(provider.get_block_number().await?);
+
+    let mut batch = ReadBatch::new();
+    let current_tick = batch.add(vault_address, ManiXAIVault::currentTickCall {});
+    let lower_tick = batch.add(vault_address, ManiXAIVault::lowerTickCall {});
+    let upper_tick = batch.add(vault_address, ManiXAIVault::upperTickCall {});
+    let is_active = batch.add(vault_address, ManiXAIVault::isActiveCall {});
+    let slot0 = batch.add(pool_address, UniswapV3Pool::slot0Call {});
+    let balance0 = batch.add(
+        vault.pool.token0.address.parse()?,
+        ERC20::balanceOfCall {
+            account: vault_address,
+        },
+    );
+    let balance1 = batch.add(
+        vault.pool.token1.address.parse()?,
+        ERC20::balanceOfCall {
+            account: vault_address,
+        },
+    );
 
-    let vault_contract = ManiXAIVault::new(vault_address, provider);
-
-    let current_tick = vault_contract.currentTick().call().await?.as_i32();
-    let lower_tick = vault_contract.lowerTick().call().await?;
-    let upper_tick = vault_contract.upperTick().call().await?;
-
-    let pool_contract = UniswapV3Pool::new(vault.pool.address.parse()?, provider);
+    let vault_results = batch.execute(provider, block).await?;
 
-    let slot0 = pool_contract.slot0().call().await?;
+    let current_tick = vault_results.get(&current_tick)?.as_i32();
+    let lower_tick = vault_results.get(&lower_tick)?;
+    let upper_tick = vault_results.get(&upper_tick)?;
+    let is_active = vault_results.get(&is_active)?;
+    let slot0 = vault_results.get(&slot0)?;
+    let balance0 = vault_results.get(&balance0)?;
+    let balance1 = vault_results.get(&balance1)?;
 
-    let sqrt_price_x96 = U256::from_str(slot0.sqrtPriceX96.to_string().as_str())?;
+    let sqrt_price_x96 = U256::from(slot0.sqrtPriceX96);
 
     let token0_decimals = vault.pool.token0.decimals;
     let token1_decimals = vault.pool.token1.decimals;
@@ -313,16 +347,22 @@ where
     )?;
     let price0 = 1.0 / price1;
 
-    // Fetch position details
-
-    let is_active = vault_contract.isActive().call().await?;
-
+    // Fetch position details, its key depends on the range read above
     let value = (vault_address, lower_tick, upper_tick);
     let res_value = value.abi_encode_packed();
 
     let position_key = keccak256(res_value);
 
-    let position_details = pool_contract.positions(position_key).call().await?;
+    let mut batch = ReadBatch::new();
+    let position_details = batch.add(
+        pool_address,
+        UniswapV3Pool::positionsCall { key: position_key },
+    );
+
+    let position_details = batch
+        .execute(provider, block)
+        .await?
+        .get(&position_details)?;
 
     let liquidity = position_details.liquidity;
     let tokens_owed_0 = position_details.tokensOwed0;
@@ -340,43 +380,25 @@ where
         liquidity,
     )?;
 
-    let formatted_amount0: f64 = format_units(amount0, token0_decimals)?.parse()?;
-    let formatted_amount1: f64 = format_units(amount1, token1_decimals)?.parse()?;
-    let formatted_tokens_owed_0: f64 = format_units(tokens_owed_0, token0_decimals)?.parse()?;
-    let formatted_tokens_owed_1: f64 = format_units(tokens_owed_1, token1_decimals)?.parse()?;
-
-    let mut position = Position::default();
+    let mut position = Position::empty(token0_decimals, token1_decimals);
 
     if is_active {
         position = Position {
             tick_lower: lower_tick.as_i32(),
             tick_upper: upper_tick.as_i32(),
             liquidity,
-            amount0: formatted_amount0,
-            amount1: formatted_amount1,
-            fees0: formatted_tokens_owed_0,
-            fees1: formatted_tokens_owed_1,
+            amount0: TokenAmount::new(amount0, token0_decimals),
+            amount1: TokenAmount::new(amount1, token1_decimals),
+            fees0: TokenAmount::new(U256::from(tokens_owed_0), token0_decimals),
+            fees1: TokenAmount::new(U256::from(tokens_owed_1), token1_decimals),
         };
     }
 
     // Calculate the TVL
-    let token0 = ERC20::new(vault.pool.token0.address.parse()?, provider);
-    let token1 = ERC20::new(vault.pool.token1.address.parse()?, provider);
-
-    let balance0 = token0.balanceOf(vault_address).call().await?;
-
-    let balance1 = token1.balanceOf(vault_address).call().await?;
-
-    let balance0: f64 = format_units(balance0, token0_decimals)?.parse()?;
-    let balance1: f64 = format_units(balance1, token1_decimals)?.parse()?;
+    let balance0 = TokenAmount::new(balance0, token0_decimals);
+    let balance1 = TokenAmount::new(balance1, token1_decimals);
 
-    let vault_tvl0 = position.amount0 + position.fees0 + balance0;
-    let vault_tvl1 = position.amount1 + position.fees1 + balance1;
-
-    let tvl = VaultTVL {
-        tvl0: vault_tvl0,
-        tvl1: vault_tvl1,
-    };
+    let tvl = get_vault_tvl(&position, &balance0, &balance1)?;
 
     vault.pool.current_tick = current_tick;
     vault.pool.sqrt_price_x96 = sqrt_price_x96;
@@ -388,5 +410,117 @@ where
     vault.position = position;
     vault.tvl = tvl;
 
+    Ok(VaultTokenBalances {
+        token0_balance: balance0,
+        token1_balance: balance1,
+    })
+}
+
+/// Pool price and vault range read at a single block, the values a rebalance decision is made on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VaultStateReading {
+    pub current_tick: i32,
+    pub sqrt_price_x96: U256,
+    pub lower_tick: i32,
+    pub upper_tick: i32,
+}
+
+async fn read_vault_state_at<P>(
+    provider: &P,
+    vault: &VaultDetails,
+    block_number: u64,
+) -> Result<VaultStateReading>
+where
+    P: Provider,
+{
+    let vault_address = Address::from_str(vault.address.as_str())?;
+
+    let mut batch = ReadBatch::new();
+    let slot0 = batch.add(vault.pool.address.parse()?, UniswapV3Pool::slot0Call {});
+    let lower_tick = batch.add(vault_address, ManiXAIVault::lowerTickCall {});
+    let upper_tick = batch.add(vault_address, ManiXAIVault::upperTickCall {});
+
+    let results = batch
+        .execute(provider, BlockId::number(block_number))
+        .await?;
+
+    let slot0 = results.get(&slot0)?;
+    let lower_tick = results.get(&lower_tick)?;
+    let upper_tick = results.get(&upper_tick)?;
+
+    Ok(VaultStateReading {
+        current_tick: slot0.tick.as_i32(),
+        sqrt_price_x96: U256::from(slot0.sqrtPriceX96),
+        lower_tick: lower_tick.as_i32(),
+        upper_tick: upper_tick.as_i32(),
+    })
+}
+
+/// Read the pool price and vault range on two RPC endpoints at the same block and check they agree with
+/// each other and with the live data of `vault`, so a lagging or faulty node can not drive a rebalance
+pub async fn cross_check_vault_state(rpc_pool: &RpcPool, vault: &VaultDetails) -> Result<()> {
+    let endpoints = rpc_pool.available_endpoints(2)?;
+    let (first, second) = (&endpoints[0], &endpoints[1]);
+
+    let first_block = first.provider.get_block_number().await?;
+    let second_block = second.provider.get_block_number().await?;
+
+    if first_block.abs_diff(second_block) > RPC_MAX_BLOCK_LAG {
+        return Err(eyre!(
+            "RPC endpoints are {} blocks apart ({} at {}, {} at {}), one of them is lagging",
+            first_block.abs_diff(second_block),
+            first.url,
+            first_block,
+            second.url,
+            second_block
+        ));
+    }
+
+    // Both nodes have the older block, their readings must be identical
+    let block_number = first_block.min(second_block);
+
+    let first_reading = read_vault_state_at(&first.provider, vault, block_number).await?;
+    let second_reading = read_vault_state_at(&second.provider, vault, block_number).await?;
+
+    if first_reading != second_reading {
+        return Err(eyre!(
+            "RPC endpoints disagree on vault {} state at block {}: {} returned {:?}, {} returned {:?}",
+            vault.address,
+            block_number,
+            first.url,
+            first_reading,
+            second.url,
+            second_reading
+        ));
+    }
+
+    if first_reading.lower_tick != vault.lower_tick || first_reading.upper_tick != vault.upper_tick
+    {
+        return Err(eyre!(
+            "Vault {} range [{}, {}] does not match the cross checked range [{}, {}]",
+            vault.address,
+            vault.lower_tick,
+            vault.upper_tick,
+            first_reading.lower_tick,
+            first_reading.upper_tick
+        ));
+    }
+
+    if (first_reading.current_tick - vault.pool.current_tick).abs() > CROSS_CHECK_MAX_TICK_DEVIATION
+    {
+        return Err(eyre!(
+            "Vault {} pool tick {} is too far from the cross checked tick {} at block {}",
+            vault.address,
+            vault.pool.current_tick,
+            first_reading.current_tick,
+            block_number
+        ));
+    }
+
+    debug!(
+        "Vault {} state cross checked on {} and {} at block {}",
+        vault.address, first.url, second.url, block_number
+    );
+
     Ok(())
 }
@@ -1,30 +1,41 @@
 use std::str::FromStr;
 
 use crate::{
-    config::{CONFIG, MONITOR_VAULT_INTERVAL_SECONDS},
-    core::{self, csv_logger::RebalanceLogEntry, vault::ManiXAIVault},
-    helpers::{self},
-    strategies,
-    types::{PrepareSwapArgs, VaultDetails, VaultTokenBalances, WebAppState},
+    config::{CONFIG, POOL_SNAPSHOT_BITMAP_WORDS_AROUND},
+    core::{
+        self,
+        ai_journal::AiDecisionAction,
+        coingecko::{COINGECKO_CLIENT, OhlcvTimeframe},
+        csv_logger::RebalanceLogEntry,
+        rebalance_policy::{self, PolicyVerdict},
+        vault::ManiXAIVault,
+    },
+    helpers::{self, amount::TokenAmount},
+    strategies::{StrategyContext, StrategyDecision},
+    types::{
+        OhlcvEntry, OhlcvSource, PrepareSwapArgs, VaultConfig, VaultDetails, VaultTokenBalances,
+        WebAppState,
+    },
 };
-use alloy::primitives::{
-    Address, U256,
-    aliases::I24,
-    utils::{format_units, parse_units},
-};
-use color_eyre::eyre::{Context, Result};
+use alloy::primitives::{Address, aliases::I24};
+use color_eyre::eyre::{Context, Result, eyre};
 use tracing::{debug, error, info, warn};
 
-pub async fn start_vault_liq_management(vault_address: &str, app_state: WebAppState) -> Result<()> {
+pub async fn start_vault_liq_management(
+    vault_config: &VaultConfig,
+    app_state: WebAppState,
+) -> Result<()> {
+    let vault_address = vault_config.address.as_str();
+
     info!(
         "Vault liquidity management loop started for vault address: {:?}",
         vault_address
     );
 
-    // for each MONITOR_VAULT_INTERVAL_SECONDS, check if we need to rebalance the vault
+    // for each monitor interval of the vault, check if we need to rebalance the vault
     loop {
         // Implement the logic to rebalance the vault
-        match start_rebalance_strategy(vault_address, &app_state).await {
+        match start_rebalance_strategy(vault_config, &app_state).await {
             Ok(_) => {
                 info!(
                     "Start Rebalance strategy for vault {} completed successfully",
@@ -57,17 +68,22 @@ pub async fn start_vault_liq_management(vault_address: &str, app_state: WebAppSt
 
         info!(
             "Sleeping for {} seconds for vault {}",
-            MONITOR_VAULT_INTERVAL_SECONDS, vault_address
+            vault_config.monitor_interval_seconds, vault_address
         );
 
         tokio::time::sleep(std::time::Duration::from_secs(
-            MONITOR_VAULT_INTERVAL_SECONDS,
+            vault_config.monitor_interval_seconds,
         ))
         .await;
     }
 }
 
-async fn start_rebalance_strategy(vault_address: &str, app_state: &WebAppState) -> Result<()> {
+async fn start_rebalance_strategy(
+    vault_config: &VaultConfig,
+    app_state: &WebAppState,
+) -> Result<()> {
+    let vault_address = vault_config.address.as_str();
+
     // 1. Check if the vault already has a position or not by checkinfg the isActive flag
     let vault_details = app_state.all_vaults.get_mut(vault_address);
 
@@ -82,11 +98,38 @@ async fn start_rebalance_strategy(vault_address: &str, app_state: &WebAppState)
 
     let has_a_position = vault_details.is_active;
 
+    // Update the vault live data from the blockchain (tick, prices), the balances are read at the same block
     let vault_token_balances =
-        core::vault::get_vault_tokens_balances(&app_state.evm_provider, &vault_details).await?;
+        core::vault::update_vault_live(&app_state.evm_provider, &mut vault_details).await?;
 
-    // Update the vault live data from the blockchain (tick, prices)
-    core::vault::update_vault_live(&app_state.evm_provider, &mut vault_details).await?;
+    // Make sure a lagging or faulty RPC node is not behind the data the decision is made on
+    if CONFIG.toml_config.cross_check_reads {
+        core::vault::cross_check_vault_state(&app_state.rpc_pool, &vault_details).await?;
+    }
+
+    // Only run the strategies when the rebalance policy of the vault allows it
+    let policy = rebalance_policy::evaluate(
+        &vault_config.policy,
+        &mut app_state
+            .rebalance_policies
+            .entry(vault_address.to_lowercase())
+            .or_default(),
+        &vault_details,
+        chrono::Utc::now(),
+    )?;
+
+    if !policy.allowed {
+        info!(
+            "Rebalance policy of vault {} holds the position ({:?}): {}",
+            vault_address, policy.rule, policy.detail
+        );
+        return Ok(());
+    }
+
+    debug!(
+        "Rebalance policy of vault {} lets the strategies run ({:?}): {}",
+        vault_address, policy.rule, policy.detail
+    );
 
     if has_a_position {
         debug!(
@@ -94,26 +137,7 @@ async fn start_rebalance_strategy(vault_address: &str, app_state: &WebAppState)
             vault_address
         );
 
-        // Check if teh vault is out of range by checking the current tick
-        let current_tick = vault_details.pool.current_tick;
-        let lower_tick = vault_details.lower_tick;
-        let upper_tick = vault_details.upper_tick;
-
-        let is_out_of_range = current_tick < lower_tick || current_tick > upper_tick;
-
-        // If the vault is not out of range, we skip the rebalance if teh fees are very low
-        if !is_out_of_range {
-            let fees0 = vault_details.position.fees0;
-            let fees1 = vault_details.position.fees1;
-
-            if fees0 < 0.01 || fees1 < 0.01 {
-                warn!(
-                    "Vault {} is still in range and generated fees are very low. Skipping AI strategy and rebalance.",
-                    vault_address
-                );
-                return Ok(());
-            }
-        }
+        // Whether an in range position is worth moving is decided by the profitability gate in `rebalance_vault`
 
         // TEST ERROR
         // return Err(color_eyre::eyre::eyre!(
@@ -122,25 +146,9 @@ async fn start_rebalance_strategy(vault_address: &str, app_state: &WebAppState)
         // ));
 
         // Estimate balances after removing the existant liquidity bygetting the vault tvl, then call the rebalance function
-        let estim_balance0 = vault_details.tvl.tvl0;
-        let estim_balance1 = vault_details.tvl.tvl1;
-        let estim_balance0_u256 = parse_units(
-            estim_balance0.to_string().as_str(),
-            vault_details.pool.token0.decimals,
-        )?
-        .into();
-
-        let estim_balance1_u256 = parse_units(
-            estim_balance1.to_string().as_str(),
-            vault_details.pool.token1.decimals,
-        )?
-        .into();
-
         let vault_token_balances = VaultTokenBalances {
-            token0_balance: estim_balance0,
-            token1_balance: estim_balance1,
-            token0_balance_u256: estim_balance0_u256,
-            token1_balance_u256: estim_balance1_u256,
+            token0_balance: vault_details.tvl.tvl0,
+            token1_balance: vault_details.tvl.tvl1,
         };
 
         debug!(
@@ -149,7 +157,14 @@ async fn start_rebalance_strategy(vault_address: &str, app_state: &WebAppState)
         );
 
         // Call the rebalance function
-        rebalance_vault(&mut vault_details, &app_state, &vault_token_balances).await?;
+        rebalance_vault(
+            &mut vault_details,
+            vault_config,
+            app_state,
+            &vault_token_balances,
+            &policy,
+        )
+        .await?;
     } else {
         debug!(
             "Vault {} does not have a position. Checking if it is possible to mint a new position...",
@@ -160,7 +175,7 @@ async fn start_rebalance_strategy(vault_address: &str, app_state: &WebAppState)
         let balance0 = vault_token_balances.token0_balance;
         let balance1 = vault_token_balances.token1_balance;
 
-        if balance0 <= f64::EPSILON && balance1 <= f64::EPSILON {
+        if balance0.is_zero() && balance1.is_zero() {
             warn!(
                 "Vault {} does not have any token balances.Its balances are: {} , {} . Skipping minting liquidity.",
                 vault_address, balance0, balance1
@@ -169,7 +184,14 @@ async fn start_rebalance_strategy(vault_address: &str, app_state: &WebAppState)
         }
 
         // 3. Call the rebalance function
-        rebalance_vault(&mut vault_details, &app_state, &vault_token_balances).await?;
+        rebalance_vault(
+            &mut vault_details,
+            vault_config,
+            app_state,
+            &vault_token_balances,
+            &policy,
+        )
+        .await?;
     }
 
     // 4. Update the vault details in the app state after rebalance
@@ -178,236 +200,459 @@ async fn start_rebalance_strategy(vault_address: &str, app_state: &WebAppState)
     Ok(())
 }
 
+/// Candles of the vault pool from its `ohlcv_source`, oldest first
+async fn get_vault_ohlcv(
+    app_state: &WebAppState,
+    vault_details: &VaultDetails,
+    vault_config: &VaultConfig,
+) -> Result<Vec<OhlcvEntry>> {
+    let pool = &vault_details.pool;
+
+    match vault_config.ohlcv_source {
+        OhlcvSource::Coingecko => {
+            let timeframe = OhlcvTimeframe::from_seconds(vault_config.ohlcv_interval_seconds)
+                .ok_or_else(|| {
+                    eyre!(
+                        "No CoinGecko candles of {}s",
+                        vault_config.ohlcv_interval_seconds
+                    )
+                })?;
+
+            let mut candles = COINGECKO_CLIENT
+                .get_pool_ohlcv(&pool.address, &pool.token0.address, timeframe)
+                .await?
+                .data
+                .attributes
+                .ohlcv_list;
+            candles.sort_by_key(|candle| candle.timestamp());
+
+            Ok(candles)
+        }
+        OhlcvSource::SwapLogs => {
+            core::swap_ohlcv::update_swap_ohlcv(
+                &app_state.evm_provider,
+                pool,
+                vault_config.ohlcv_interval_seconds,
+                vault_config.ohlcv_start_block,
+            )
+            .await
+        }
+    }
+}
+
 pub async fn rebalance_vault(
     vault_details: &mut VaultDetails,
-    _app_state: &WebAppState,
+    vault_config: &VaultConfig,
+    app_state: &WebAppState,
     vault_token_balances: &VaultTokenBalances,
+    policy: &PolicyVerdict,
 ) -> Result<()> {
-    let balance0 = vault_token_balances.token0_balance;
-    let balance1 = vault_token_balances.token1_balance;
+    // 3.1 Get the pool candles from the configured source. Without them the strategies needing market data fail
+    // and the next strategies of the chain decide
+    let ohlcv = match get_vault_ohlcv(app_state, vault_details, vault_config).await {
+        Ok(ohlcv) => ohlcv,
+        Err(e) => {
+            warn!(
+                "Failed to get the candles of vault {}, deciding without market data: {:?}",
+                vault_details.address, e
+            );
+            vec![]
+        }
+    };
+
+    // 3.2 Run the strategies of the vault, in order until one decides, to get the best tick range to put liq on
+    let strategy_chain = app_state.strategies.chain(vault_config.strategy_names())?;
+
+    let decision = strategy_chain
+        .decide(&StrategyContext {
+            vault: vault_details,
+            ohlcv: Some(&ohlcv),
+            journal: Some(&app_state.ai_journal),
+        })
+        .await?;
+
+    let action = apply_strategy_decision(
+        vault_details,
+        vault_config,
+        app_state,
+        vault_token_balances,
+        &ohlcv,
+        &decision,
+        policy,
+    )
+    .await;
+
+    // A rebalance sent on chain starts the cooldown of the policy and counts towards its daily cap. A decision to
+    // not move delays the next run of the strategies on the same price, an error leaves the policy as it was
+    let mut policy_state = app_state
+        .rebalance_policies
+        .entry(vault_details.address.to_lowercase())
+        .or_default();
+
+    match &action {
+        Ok(AiDecisionAction::Executed { .. }) => policy_state.record_rebalance(chrono::Utc::now()),
+        Ok(
+            AiDecisionAction::KeepRange
+            | AiDecisionAction::AlreadyInRange
+            | AiDecisionAction::NotProfitable
+            | AiDecisionAction::DryRun,
+        ) => policy_state.record_declined(chrono::Utc::now()),
+        _ => {}
+    }
+
+    drop(policy_state);
+
+    // 3.9 Record what was done with the decision of a model
+    if let Some(journal_id) = &decision.journal_id {
+        let journal_action = match &action {
+            Ok(action) => action.clone(),
+            Err(e) => AiDecisionAction::Error {
+                error: e.to_string(),
+            },
+        };
+
+        app_state
+            .ai_journal
+            .set_action(&vault_details.address, journal_id, journal_action);
+    }
 
-    // 3.2 Start strategy thta will get me the best tick range to put liq on
-    let _basic_tick_range = strategies::basic::get_best_range(&vault_details).await?;
+    action.map(|_| ())
+}
 
-    // 3.3 Start ai strategy that will get me the best tick range to put liq on
-    let ai_strategy_result = strategies::ai::start(&vault_details).await?;
+/// Move the vault liquidity to the range of the decision when it is worth it, and return what was done
+async fn apply_strategy_decision(
+    vault_details: &VaultDetails,
+    vault_config: &VaultConfig,
+    app_state: &WebAppState,
+    vault_token_balances: &VaultTokenBalances,
+    ohlcv: &[OhlcvEntry],
+    decision: &StrategyDecision,
+    policy: &PolicyVerdict,
+) -> Result<AiDecisionAction> {
+    info!(
+        "Strategy {} decision for vault {}: rebalance_required: {}, rationale: {}",
+        decision.strategy, vault_details.address, decision.rebalance_required, decision.rationale
+    );
 
-    if !ai_strategy_result.rebalance_required {
+    if !decision.rebalance_required {
         warn!(
-            "AI strategy does not recommend rebalance for vault {}. Skipping rebalance.",
-            vault_details.address
+            "Strategy {} does not recommend rebalance for vault {}. Skipping rebalance.",
+            decision.strategy, vault_details.address
         );
-        return Ok(());
+        return Ok(AiDecisionAction::KeepRange);
     }
 
-    let ai_tick_range =
-        strategies::ai::get_tick_range_from_ai_response(ai_strategy_result, &vault_details).await?;
-
-    info!("AI strategy Tick range: {:?}", ai_tick_range);
+    info!(
+        "Strategy {} Tick range: {:?}",
+        decision.strategy, decision.tick_range
+    );
 
-    let tick_range = ai_tick_range;
+    let tick_range = &decision.tick_range;
 
     let lower_tick = tick_range.lower_tick;
     let upper_tick = tick_range.upper_tick;
-    let current_tick = tick_range.curent_tick;
 
     if vault_details.lower_tick == lower_tick && vault_details.upper_tick == upper_tick {
         warn!(
             "Vault {} already has the best tick range. Skipping rebalance.",
             vault_details.address
         );
-        return Ok(());
+        return Ok(AiDecisionAction::AlreadyInRange);
     }
 
     // DEBUG: STop here for debugging purposes
     // return Ok(());
 
-    // 3.3 Get the appropriate amount of token0 and token1 to add liquidity
-    let lower_tick_sqrt_price =
-        helpers::math::uniswap_v3::tick_math::get_sqrt_ratio_at_tick(lower_tick)?;
-    let upper_tick_sqrt_price =
-        helpers::math::uniswap_v3::tick_math::get_sqrt_ratio_at_tick(upper_tick)?;
-    let current_tick_sqrt_price =
-        helpers::math::uniswap_v3::tick_math::get_sqrt_ratio_at_tick(current_tick)?;
-
-    let balance1_token0_equivalent = balance1 * vault_details.pool.price0;
-    let is_balance0_larger = balance0 > balance1_token0_equivalent;
-
-    let liquidity = if is_balance0_larger {
-        helpers::math::uniswap_v3::liquidity_math::get_liquidity_for_amount0(
-            lower_tick_sqrt_price,
-            upper_tick_sqrt_price,
-            vault_token_balances.token0_balance_u256,
-        )?
-    } else {
-        helpers::math::uniswap_v3::liquidity_math::get_liquidity_for_amount1(
-            lower_tick_sqrt_price,
-            upper_tick_sqrt_price,
-            vault_token_balances.token1_balance_u256,
-        )?
-    };
+    // 3.3 Refuse to act on a pool price that moved away from its TWAP, e.g. manipulated within a block
+    let twap =
+        core::oracle::check_spot_against_twap(&app_state.evm_provider, vault_details, vault_config)
+            .await?;
 
-    let (amount0, amount1) = helpers::math::uniswap_v3::liquidity_math::get_amounts_for_liquidity(
-        current_tick_sqrt_price,
-        lower_tick_sqrt_price,
-        upper_tick_sqrt_price,
-        liquidity,
+    // 3.4 Solve the swap that leaves the balances in the ratio of the new range, after the pool fee and price impact
+    let mut snapshot = core::pool::fetch_pool_snapshot(
+        &app_state.evm_provider,
+        &vault_details.pool.address,
+        POOL_SNAPSHOT_BITMAP_WORDS_AROUND,
+    )
+    .await?;
+
+    let pool_liquidity = snapshot.liquidity;
+
+    // The current position is burned by the vault before the swap
+    if vault_details.is_active {
+        snapshot.remove_position(
+            vault_details.lower_tick,
+            vault_details.upper_tick,
+            vault_details.position.liquidity,
+        )?;
+    }
+
+    let solution = helpers::math::swap_solver::solve_rebalance_swap(
+        &snapshot,
+        vault_token_balances.token0_balance.raw,
+        vault_token_balances.token1_balance.raw,
+        lower_tick,
+        upper_tick,
+        vault_config.swap_slippage_bps,
     )?;
 
-    let desired_amount0: f64 =
-        format_units(amount0, vault_details.pool.token0.decimals)?.parse()?;
-    let desired_amount1: f64 =
-        format_units(amount1, vault_details.pool.token1.decimals)?.parse()?;
-
-    // Prepare if need to swap token0 for token1 or teh reverse and how much to swap
-    let exess0 = balance0 - desired_amount0;
-    let exess1 = balance1 - desired_amount1;
-
-    // Prepare the swap args for the negative value betwen the exess0 and exess1
-    let swap_arg: PrepareSwapArgs;
-
-    if exess0 < 0.0 {
-        let exact_amount_out = exess0.abs();
-        let parsed_exact_amount_out: U256 = parse_units(
-            exact_amount_out.to_string().as_str(),
-            vault_details.pool.token0.decimals,
-        )?
-        .into();
-        let max_amount_in: U256 = parse_units(
-            exess1.abs().to_string().as_str(),
-            vault_details.pool.token1.decimals,
-        )?
-        .into();
-
-        swap_arg = PrepareSwapArgs {
-            exact_amount_out,
-            parsed_exact_amount_out,
-            token_in: vault_details.pool.token1.clone(),
-            token_out: vault_details.pool.token0.clone(),
-            is_swap_0_to_1: false,
-            max_amount_in,
-            formatted_max_amount_in: exess1.abs(),
-        };
-    } else if exess1 < 0.0 {
-        let exact_amount_out = exess1.abs();
-        let parsed_exact_amount_out: U256 = parse_units(
-            exact_amount_out.to_string().as_str(),
-            vault_details.pool.token1.decimals,
-        )?
-        .into();
-        let max_amount_in: U256 = parse_units(
-            exess0.abs().to_string().as_str(),
-            vault_details.pool.token0.decimals,
-        )?
-        .into();
-
-        swap_arg = PrepareSwapArgs {
-            exact_amount_out,
-            parsed_exact_amount_out,
-            token_in: vault_details.pool.token0.clone(),
-            token_out: vault_details.pool.token1.clone(),
-            is_swap_0_to_1: true,
-            max_amount_in,
-            formatted_max_amount_in: exess0.abs(),
-        };
+    let swap_arg = prepare_swap_args(vault_details, &solution);
+
+    debug!(
+        "Expected vault balances after the swap: {} {}, {} {}",
+        TokenAmount::new(solution.balance0_after, vault_details.pool.token0.decimals),
+        vault_details.pool.token0.symbol,
+        TokenAmount::new(solution.balance1_after, vault_details.pool.token1.decimals),
+        vault_details.pool.token1.symbol
+    );
+
+    // 3.5 Log the predicted outcome of the swap before sending it
+    if let Some(simulation) = &solution.simulation {
+        let amount_in = TokenAmount::new(solution.amount_in, swap_arg.token_in.decimals);
+
+        info!(
+            "Predicted rebalance swap for vault {}: {} {} in (max {}) for {} {} out, price impact: {:.4}%, tick after: {}, ticks crossed: {}",
+            vault_details.address,
+            amount_in,
+            swap_arg.token_in.symbol,
+            swap_arg.max_amount_in,
+            swap_arg.exact_amount_out,
+            swap_arg.token_out.symbol,
+            solution.price_impact_pct(),
+            simulation.tick_after,
+            simulation.ticks_crossed
+        );
     } else {
-        // No need to swap
-        swap_arg = PrepareSwapArgs {
-            exact_amount_out: 0.0,
-            parsed_exact_amount_out: U256::ZERO,
-            token_in: vault_details.pool.token0.clone(),
-            token_out: vault_details.pool.token1.clone(),
-            is_swap_0_to_1: true,
-            max_amount_in: U256::ZERO,
-            formatted_max_amount_in: 0.0,
-        };
+        info!(
+            "Vault {} balances already match the new range, no swap needed",
+            vault_details.address
+        );
     }
 
+    // 3.6 Estimate the HBAR the call has to carry for the SaucerSwap fees and simulate it to get its gas
     let vault_address = vault_details.address.as_str();
 
-    let is_execute = CONFIG.is_execute;
+    let hbar_value = core::tx_costs::get_hbar_value_to_send(
+        &app_state.evm_provider,
+        vault_address,
+        &vault_details.pool.address,
+    )
+    .await?;
+
+    let call_simulation = core::plan::simulate_rebalance_call(
+        &app_state.evm_provider,
+        vault_address,
+        tick_range,
+        &swap_arg,
+        hbar_value.value_to_send_weibars,
+    )
+    .await?;
+
+    let tx_cost_hbar = core::tx_costs::get_tx_cost_hbar(
+        &app_state.evm_provider,
+        call_simulation.gas_limit,
+        &hbar_value,
+    )
+    .await?;
+
+    // 3.7 Only move the liquidity when the expected fees over the horizon beat the costs of the rebalance
+    let estimate = core::profitability::estimate_vault_rebalance(
+        vault_details,
+        vault_config,
+        ohlcv,
+        &solution,
+        tick_range,
+        &core::profitability::RebalanceOnChainInputs {
+            pool_liquidity,
+            pool_liquidity_without_vault: snapshot.liquidity,
+            tx_cost_hbar,
+        },
+    )?;
 
-    if is_execute {
-        // Reint evm provider to ensure it has teh latest nonce
-        let evm_provider = core::init::init_evm_provider().await?;
+    info!(
+        "Rebalance estimate for vault {} over {}h (token1 units): daily volume: {}, expected fees new range: {}, expected fees current range: {}, swap fee: {}, price impact: {}, gas: {}, net expected value: {}",
+        vault_details.address,
+        estimate.horizon_hours,
+        estimate.daily_volume_token1,
+        estimate.expected_fees_new_range,
+        estimate.expected_fees_current_range,
+        estimate.swap_fee_cost,
+        estimate.price_impact_cost,
+        estimate.gas_cost,
+        estimate.net_expected_value
+    );
 
-        // call rebelance on the vault with new tick range and swap direction and amount
-        let vault_contract = ManiXAIVault::new(Address::from_str(vault_address)?, &evm_provider);
+    // 3.8 Record the plan of the rebalance so operators can review it through the API
+    let mut plan = core::plan::RebalancePlan::new(
+        vault_details,
+        decision,
+        &swap_arg,
+        &solution,
+        &estimate,
+        &call_simulation,
+        &hbar_value,
+    )?;
+    plan.twap = twap;
+    plan.policy = Some(policy.clone());
 
-        let upper_tick = I24::from_str(upper_tick.to_string().as_str())?;
-        let lower_tick = I24::from_str(lower_tick.to_string().as_str())?;
+    // A vault out of range earns no fees at all, it is moved back even when the volume of the pool is low or unknown
+    let is_out_of_range = !vault_details.is_active
+        || vault_details.pool.current_tick < vault_details.lower_tick
+        || vault_details.pool.current_tick >= vault_details.upper_tick;
 
-        let value_to_send: U256 = parse_units("0.2", 18)?.into();
+    if is_out_of_range {
+        if !estimate.is_profitable() {
+            warn!(
+                "Rebalance of vault {} is not expected to pay for itself, rebalancing it back in range anyway",
+                vault_details.address
+            );
+        }
+    } else if !estimate.is_profitable() {
+        core::plan::store_rebalance_plan(&app_state.rebalance_plans, plan);
 
-        let rebalnce_reciept = vault_contract
-            .rebalance(
-                lower_tick,
-                upper_tick,
-                swap_arg.parsed_exact_amount_out,
-                swap_arg.max_amount_in,
-                swap_arg.is_swap_0_to_1,
-            )
-            .value(value_to_send)
-            .gas(15_000_000)
-            .send()
-            .await?
-            .get_receipt()
-            .await?;
+        warn!(
+            "Rebalance of vault {} is not expected to be profitable. Skipping rebalance.",
+            vault_details.address
+        );
+        return Ok(AiDecisionAction::NotProfitable);
+    }
 
-        let rebalnce_tx_hash = rebalnce_reciept.transaction_hash;
+    let is_execute = CONFIG.is_execute && vault_config.is_execute;
 
-        info!(
-            "Rebalance TX Hash for vault {} is: {}",
-            vault_address, rebalnce_tx_hash
+    if !is_execute {
+        core::plan::store_rebalance_plan(&app_state.rebalance_plans, plan);
+
+        warn!(
+            "Execution is disabled. Dry-run plan recorded for vault {}",
+            vault_address
         );
+        return Ok(AiDecisionAction::DryRun);
+    }
 
-        let rebalnce_tx_status = rebalnce_reciept.status();
+    if call_simulation.reverted {
+        core::plan::store_rebalance_plan(&app_state.rebalance_plans, plan);
 
-        core::csv_logger::log_rebalance_result_to_csv(RebalanceLogEntry {
-            timestamp: chrono::Utc::now().to_string(),
-            vault_address: vault_address.to_string(),
-            transaction_hash: rebalnce_tx_hash.to_string(),
-            transaction_status: if rebalnce_tx_status {
-                "Success".to_string()
-            } else {
-                "Failed".to_string()
-            },
-            tvl0: vault_details.tvl.tvl0,
-            tvl1: vault_details.tvl.tvl1,
-            fees0_bef: vault_details.position.fees0,
-            fees1_bef: vault_details.position.fees1,
-            current_tick: vault_details.pool.current_tick,
-            lower_tick_bef: vault_details.lower_tick,
-            upper_tick_bef: vault_details.upper_tick,
-            lower_tick_aft: lower_tick.as_i32(),
-            upper_tick_aft: upper_tick.as_i32(),
-            liquidity_bef: vault_details.position.liquidity,
-            amount0_bef: vault_details.position.amount0,
-            amount1_bef: vault_details.position.amount1,
-            swap_amount_out: swap_arg.exact_amount_out,
-            swap_max_amount_in: swap_arg.formatted_max_amount_in,
-            is_swap_0_to_1: swap_arg.is_swap_0_to_1,
-        })?;
-
-        if !rebalnce_tx_status {
-            return Err(color_eyre::eyre::eyre!(
-                "Rebalance transaction failed for vault {}. TX Hash: {},  Error: {:?}",
-                vault_address,
-                rebalnce_tx_hash,
-                rebalnce_reciept
-            ));
+        return Err(color_eyre::eyre::eyre!(
+            "Rebalance call of vault {} reverts in simulation, not sending it. Error: {:?}",
+            vault_address,
+            call_simulation.error
+        ));
+    }
+
+    plan.is_dry_run = false;
+
+    // call rebelance on the vault with new tick range and swap direction and amount
+    let vault_contract =
+        ManiXAIVault::new(Address::from_str(vault_address)?, &app_state.evm_provider);
+
+    let upper_tick = I24::from_str(upper_tick.to_string().as_str())?;
+    let lower_tick = I24::from_str(lower_tick.to_string().as_str())?;
+
+    let rebalance_tx = vault_contract
+        .rebalance(
+            lower_tick,
+            upper_tick,
+            swap_arg.exact_amount_out.raw,
+            swap_arg.max_amount_in.raw,
+            swap_arg.is_swap_0_to_1,
+        )
+        .value(hbar_value.value_to_send_weibars)
+        .gas(call_simulation.gas_limit)
+        .into_transaction_request();
+
+    // Sent through the shared queue so concurrent vault loops never reuse a nonce
+    let rebalnce_reciept = app_state
+        .tx_sender
+        .send(format!("rebalance {}", vault_address), rebalance_tx)
+        .await?;
+
+    let rebalnce_tx_hash = rebalnce_reciept.transaction_hash;
+
+    info!(
+        "Rebalance TX Hash for vault {} is: {}",
+        vault_address, rebalnce_tx_hash
+    );
+
+    let rebalnce_tx_status = rebalnce_reciept.status();
+
+    // Track the HBAR left unused in the vault, it pays the fees of the next calls
+    let vault_hbar_balance_after =
+        core::tx_costs::get_vault_hbar_balance(&app_state.evm_provider, vault_address).await?;
+
+    info!(
+        "Vault {} HBAR: sent {}, mint fee {}, unused balance left in the vault {}",
+        vault_address, hbar_value.value_to_send, hbar_value.mint_fee, vault_hbar_balance_after
+    );
+
+    plan.tx_hash = Some(rebalnce_tx_hash.to_string());
+    plan.vault_hbar_balance_after = Some(vault_hbar_balance_after);
+    core::plan::store_rebalance_plan(&app_state.rebalance_plans, plan);
+
+    core::csv_logger::log_rebalance_result_to_csv(RebalanceLogEntry {
+        timestamp: chrono::Utc::now().to_string(),
+        vault_address: vault_address.to_string(),
+        transaction_hash: rebalnce_tx_hash.to_string(),
+        transaction_status: if rebalnce_tx_status {
+            "Success".to_string()
         } else {
-            info!(
-                "Rebalance transaction succeeded for vault {}. TX Hash: {}",
-                vault_address, rebalnce_tx_hash
-            );
-        }
+            "Failed".to_string()
+        },
+        tvl0: vault_details.tvl.tvl0,
+        tvl1: vault_details.tvl.tvl1,
+        fees0_bef: vault_details.position.fees0,
+        fees1_bef: vault_details.position.fees1,
+        current_tick: vault_details.pool.current_tick,
+        lower_tick_bef: vault_details.lower_tick,
+        upper_tick_bef: vault_details.upper_tick,
+        lower_tick_aft: lower_tick.as_i32(),
+        upper_tick_aft: upper_tick.as_i32(),
+        liquidity_bef: vault_details.position.liquidity,
+        amount0_bef: vault_details.position.amount0,
+        amount1_bef: vault_details.position.amount1,
+        swap_amount_out: swap_arg.exact_amount_out,
+        swap_max_amount_in: swap_arg.max_amount_in,
+        is_swap_0_to_1: swap_arg.is_swap_0_to_1,
+        gas_limit: call_simulation.gas_limit,
+        hbar_value_sent: hbar_value.value_to_send,
+        hbar_mint_fee: hbar_value.mint_fee,
+        vault_hbar_balance_after,
+    })?;
+
+    if !rebalnce_tx_status {
+        return Err(color_eyre::eyre::eyre!(
+            "Rebalance transaction failed for vault {}. TX Hash: {},  Error: {:?}",
+            vault_address,
+            rebalnce_tx_hash,
+            rebalnce_reciept
+        ));
     } else {
-        warn!(
-            "Execution is disabled. Skipping rebalance for vault {}",
-            vault_address
+        info!(
+            "Rebalance transaction succeeded for vault {}. TX Hash: {}",
+            vault_address, rebalnce_tx_hash
         );
     }
 
-    Ok(())
+    Ok(AiDecisionAction::Executed {
+        tx_hash: rebalnce_tx_hash.to_string(),
+    })
+}
+
+/// Turn a swap solution into the arguments of the vault `rebalance` call
+fn prepare_swap_args(
+    vault_details: &VaultDetails,
+    solution: &helpers::math::swap_solver::SwapSolution,
+) -> PrepareSwapArgs {
+    let (token_in, token_out) = if solution.zero_for_one {
+        (&vault_details.pool.token0, &vault_details.pool.token1)
+    } else {
+        (&vault_details.pool.token1, &vault_details.pool.token0)
+    };
+
+    PrepareSwapArgs {
+        exact_amount_out: TokenAmount::new(solution.amount_out, token_out.decimals),
+        token_in: token_in.clone(),
+        token_out: token_out.clone(),
+        is_swap_0_to_1: solution.zero_for_one,
+        max_amount_in: TokenAmount::new(solution.max_amount_in, token_in.decimals),
+    }
 }
@@ -0,0 +1,184 @@
+use std::{fmt, str::FromStr};
+
+use alloy::primitives::U256;
+use color_eyre::eyre::{Report, Result, eyre};
+use serde::{Deserialize, Deserializer, Serialize, Serializer, de};
+
+/// Exact token amount: the raw integer amount of the token smallest unit and the token decimals.
+///
+/// Amounts read on chain stay in this form down to the contract calls, so they are never rounded through
+/// an f64. It is written as a decimal string with all its decimals (e.g. `"1.500000"` for 6 decimals),
+/// which is enough to read it back without losing anything.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TokenAmount {
+    pub raw: U256,
+    pub decimals: u8,
+}
+
+impl TokenAmount {
+    pub fn new(raw: U256, decimals: u8) -> Self {
+        Self { raw, decimals }
+    }
+
+    pub fn zero(decimals: u8) -> Self {
+        Self::new(U256::ZERO, decimals)
+    }
+
+    /// Amount closest to `value`, rounded to the token decimals. Only meant for amounts that are
+    /// floats in the first place, e.g. user inputs or simulated balances.
+    pub fn from_f64(value: f64, decimals: u8) -> Result<Self> {
+        if !value.is_finite() || value < 0.0 {
+            return Err(eyre!("Invalid token amount: {}", value));
+        }
+
+        // Fixed notation, a plain `to_string` can give `1e-7`
+        format!("{:.*}", decimals as usize, value).parse()
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.raw.is_zero()
+    }
+
+    /// Approximate value for analytics and logs, never convert it back to an amount sent on chain
+    pub fn to_f64(self) -> f64 {
+        self.to_string()
+            .parse()
+            .expect("a decimal string is a valid f64")
+    }
+
+    pub fn checked_add(&self, other: &Self) -> Result<Self> {
+        self.check_decimals(other)?;
+
+        let raw = self
+            .raw
+            .checked_add(other.raw)
+            .ok_or_else(|| eyre!("Token amount overflow: {} + {}", self, other))?;
+
+        Ok(Self::new(raw, self.decimals))
+    }
+
+    fn check_decimals(&self, other: &Self) -> Result<()> {
+        if self.decimals != other.decimals {
+            return Err(eyre!(
+                "Token amounts with different decimals: {} and {}",
+                self.decimals,
+                other.decimals
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for TokenAmount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let decimals = self.decimals as usize;
+
+        if decimals == 0 {
+            return write!(f, "{}", self.raw);
+        }
+
+        // Left pad so there is at least one digit before the point
+        let digits = format!("{:0>width$}", self.raw.to_string(), width = decimals + 1);
+        let (integer, fraction) = digits.split_at(digits.len() - decimals);
+
+        write!(f, "{}.{}", integer, fraction)
+    }
+}
+
+/// Parse a decimal string, the number of digits after the point gives the decimals
+impl FromStr for TokenAmount {
+    type Err = Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (integer, fraction) = s.split_once('.').unwrap_or((s, ""));
+
+        let is_digits = |part: &str| part.chars().all(|c| c.is_ascii_digit());
+
+        if integer.is_empty() || !is_digits(integer) || !is_digits(fraction) {
+            return Err(eyre!("Invalid token amount: {:?}", s));
+        }
+
+        let decimals = u8::try_from(fraction.len())
+            .map_err(|_| eyre!("Too many decimals in token amount: {:?}", s))?;
+        let raw = U256::from_str_radix(&format!("{}{}", integer, fraction), 10)
+            .map_err(|e| eyre!("Invalid token amount {:?}: {}", s, e))?;
+
+        Ok(Self::new(raw, decimals))
+    }
+}
+
+impl Serialize for TokenAmount {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for TokenAmount {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+
+        s.parse().map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_display_keeps_every_decimal() {
+        assert_eq!(
+            TokenAmount::new(U256::from(1_500_000), 6).to_string(),
+            "1.500000"
+        );
+        assert_eq!(
+            TokenAmount::new(U256::from(42), 8).to_string(),
+            "0.00000042"
+        );
+        assert_eq!(TokenAmount::new(U256::from(42), 0).to_string(), "42");
+        assert_eq!(TokenAmount::zero(2).to_string(), "0.00");
+    }
+
+    #[test]
+    fn test_json_round_trip_is_lossless() {
+        // More digits than an f64 holds
+        let amount = TokenAmount::new(U256::from(123_456_789_012_345_678_901_u128), 18);
+
+        let json = serde_json::to_string(&amount).unwrap();
+        assert_eq!(json, "\"123.456789012345678901\"");
+
+        let parsed: TokenAmount = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, amount);
+
+        assert!(serde_json::from_str::<TokenAmount>("1.5").is_err());
+        assert!("-1.5".parse::<TokenAmount>().is_err());
+        assert!("1e-7".parse::<TokenAmount>().is_err());
+        assert!(".5".parse::<TokenAmount>().is_err());
+    }
+
+    #[test]
+    fn test_from_f64_avoids_scientific_notation() {
+        assert_eq!(
+            TokenAmount::from_f64(0.0000001, 8).unwrap(),
+            TokenAmount::new(U256::from(10), 8)
+        );
+        assert_eq!(
+            TokenAmount::from_f64(2.0, 6).unwrap(),
+            TokenAmount::new(U256::from(2_000_000), 6)
+        );
+        assert!(TokenAmount::from_f64(-1.0, 6).is_err());
+        assert!(TokenAmount::from_f64(f64::NAN, 6).is_err());
+    }
+
+    #[test]
+    fn test_addition_checks_decimals() {
+        let a = TokenAmount::new(U256::from(300), 2);
+        let b = TokenAmount::new(U256::from(125), 2);
+
+        assert_eq!(a.checked_add(&b).unwrap().to_string(), "4.25");
+        assert!(a.checked_add(&TokenAmount::zero(6)).is_err());
+        assert!(TokenAmount::new(U256::MAX, 2).checked_add(&b).is_err());
+        assert_eq!(a.to_f64(), 3.0);
+    }
+}
@@ -0,0 +1,397 @@
+/*
+    Market features of a candle series, computed locally so the AI prompt gets a few numbers instead of the raw
+    candles. Windows are in candles, not in days, so the features work with any candle interval. A feature is `None`
+    when the series is too short for it.
+*/
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{
+    config::{
+        FEATURE_ATR_PERIOD, FEATURE_RANGE_WINDOW, FEATURE_TREND_WINDOW, FEATURE_VOLATILITY_WINDOWS,
+        FEATURE_VOLUME_PROFILE_BUCKETS,
+    },
+    types::OhlcvEntry,
+};
+
+/// Percentiles of the closes given to the model
+const RANGE_PERCENTILES: [f64; 5] = [5.0, 25.0, 50.0, 75.0, 95.0];
+
+/// Features of the candles the strategies decide on, percentages are per candle
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, ToSchema)]
+pub struct MarketFeatures {
+    pub candles: usize,
+    /// Median time between two candles
+    pub interval_seconds: Option<i64>,
+    pub last_close: Option<f64>,
+    /// Standard deviation of the close to close log returns over each window
+    pub realized_volatility: Vec<WindowVolatility>,
+    /// Average true range as a percentage of the last close
+    pub atr_pct: Option<f64>,
+    pub trend: Option<Trend>,
+    /// Percentiles of the closes of the last `FEATURE_RANGE_WINDOW` candles
+    pub close_percentiles: Vec<ClosePercentile>,
+    /// Share of the volume traded in each price bucket of the last `FEATURE_RANGE_WINDOW` candles
+    pub volume_profile: Vec<VolumeBucket>,
+    /// Percentage of the last `FEATURE_RANGE_WINDOW` closes inside the current range, `None` without a position
+    pub time_in_range_pct: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct WindowVolatility {
+    pub window: usize,
+    pub volatility_pct: f64,
+}
+
+/// Least squares line of the log closes
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct Trend {
+    pub window: usize,
+    /// Price change per candle along the line, positive when the price goes up
+    pub slope_pct: f64,
+    /// How well the line fits the closes, from 0 (no trend) to 1
+    pub r_squared: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct ClosePercentile {
+    pub percentile: f64,
+    pub price: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct VolumeBucket {
+    pub lower_price: f64,
+    pub upper_price: f64,
+    pub volume_share: f64,
+}
+
+/// Features of `candles` (oldest first). `current_range` is the (lower, upper) price of the vault position.
+pub fn market_features(
+    candles: &[OhlcvEntry],
+    current_range: Option<(f64, f64)>,
+) -> MarketFeatures {
+    let range_candles = last(candles, FEATURE_RANGE_WINDOW);
+
+    MarketFeatures {
+        candles: candles.len(),
+        interval_seconds: median_interval_seconds(candles),
+        last_close: candles.last().map(|candle| candle.close()),
+        realized_volatility: FEATURE_VOLATILITY_WINDOWS
+            .iter()
+            .filter_map(|&window| {
+                realized_volatility(candles, window).map(|volatility| WindowVolatility {
+                    window,
+                    volatility_pct: volatility * 100.0,
+                })
+            })
+            .collect(),
+        atr_pct: average_true_range(candles, FEATURE_ATR_PERIOD).and_then(|atr| {
+            let last_close = candles.last()?.close();
+            (last_close > 0.0).then(|| atr / last_close * 100.0)
+        }),
+        trend: trend(candles, FEATURE_TREND_WINDOW),
+        close_percentiles: close_percentiles(range_candles),
+        volume_profile: volume_profile(range_candles, FEATURE_VOLUME_PROFILE_BUCKETS),
+        time_in_range_pct: current_range
+            .and_then(|(lower, upper)| time_in_range(range_candles, lower, upper))
+            .map(|share| share * 100.0),
+    }
+}
+
+/// Standard deviation of the log returns of the last `window` candles, `None` with fewer candles
+pub fn realized_volatility(candles: &[OhlcvEntry], window: usize) -> Option<f64> {
+    if window < 2 || candles.len() < window {
+        return None;
+    }
+
+    let returns = log_returns(last(candles, window));
+    if returns.is_empty() {
+        return None;
+    }
+
+    let mean = returns.iter().sum::<f64>() / returns.len() as f64;
+    let variance = returns.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / returns.len() as f64;
+
+    Some(variance.sqrt())
+}
+
+/// Mean true range of the last `period` candles, in token1 units
+pub fn average_true_range(candles: &[OhlcvEntry], period: usize) -> Option<f64> {
+    if period == 0 || candles.len() < period + 1 {
+        return None;
+    }
+
+    let candles = last(candles, period + 1);
+    let true_ranges = candles.windows(2).map(|pair| {
+        let previous_close = pair[0].close();
+        let candle = &pair[1];
+
+        (candle.high() - candle.low())
+            .max((candle.high() - previous_close).abs())
+            .max((candle.low() - previous_close).abs())
+    });
+
+    Some(true_ranges.sum::<f64>() / period as f64)
+}
+
+/// Trend of the last `window` closes
+pub fn trend(candles: &[OhlcvEntry], window: usize) -> Option<Trend> {
+    if window < 2 || candles.len() < window {
+        return None;
+    }
+
+    let log_closes = last(candles, window)
+        .iter()
+        .map(|candle| candle.close())
+        .filter(|close| *close > 0.0)
+        .map(f64::ln)
+        .collect::<Vec<f64>>();
+
+    if log_closes.len() < 2 {
+        return None;
+    }
+
+    let n = log_closes.len() as f64;
+    let mean_x = (n - 1.0) / 2.0;
+    let mean_y = log_closes.iter().sum::<f64>() / n;
+
+    let (mut sxy, mut sxx, mut syy) = (0.0, 0.0, 0.0);
+    for (x, y) in log_closes.iter().enumerate() {
+        let dx = x as f64 - mean_x;
+        let dy = y - mean_y;
+        sxy += dx * dy;
+        sxx += dx * dx;
+        syy += dy * dy;
+    }
+
+    let slope = sxy / sxx;
+    // A flat series is fully explained by a flat line
+    let r_squared = if syy == 0.0 {
+        1.0
+    } else {
+        (sxy * sxy) / (sxx * syy)
+    };
+
+    Some(Trend {
+        window,
+        slope_pct: slope.exp_m1() * 100.0,
+        r_squared,
+    })
+}
+
+/// Share of the closes inside [lower, upper], `None` without candles or position
+pub fn time_in_range(candles: &[OhlcvEntry], lower: f64, upper: f64) -> Option<f64> {
+    if candles.is_empty() || lower >= upper || upper <= 0.0 {
+        return None;
+    }
+
+    let in_range = candles
+        .iter()
+        .filter(|candle| (lower..=upper).contains(&candle.close()))
+        .count();
+
+    Some(in_range as f64 / candles.len() as f64)
+}
+
+/// Merge consecutive candles so at most `max_candles` are left
+pub fn downsample(candles: &[OhlcvEntry], max_candles: usize) -> Vec<OhlcvEntry> {
+    if max_candles == 0 || candles.len() <= max_candles {
+        return candles.to_vec();
+    }
+
+    let group_size = candles.len().div_ceil(max_candles);
+
+    // Groups are aligned on the end of the series, only the oldest one can be shorter
+    let mut groups = candles
+        .rchunks(group_size)
+        .map(|group| {
+            let first = &group[0];
+            let last = &group[group.len() - 1];
+
+            OhlcvEntry(
+                first.timestamp(),
+                first.1,
+                group.iter().map(OhlcvEntry::high).fold(f64::MIN, f64::max),
+                group.iter().map(OhlcvEntry::low).fold(f64::MAX, f64::min),
+                last.close(),
+                group.iter().map(OhlcvEntry::volume).sum(),
+            )
+        })
+        .collect::<Vec<OhlcvEntry>>();
+
+    groups.reverse();
+    groups
+}
+
+fn close_percentiles(candles: &[OhlcvEntry]) -> Vec<ClosePercentile> {
+    let mut closes = candles
+        .iter()
+        .map(|candle| candle.close())
+        .collect::<Vec<f64>>();
+    if closes.is_empty() {
+        return vec![];
+    }
+
+    closes.sort_by(f64::total_cmp);
+
+    RANGE_PERCENTILES
+        .iter()
+        .map(|&percentile| {
+            // Linear interpolation between the closest ranks
+            let rank = percentile / 100.0 * (closes.len() - 1) as f64;
+            let (below, above) = (rank.floor() as usize, rank.ceil() as usize);
+            let price = closes[below] + (closes[above] - closes[below]) * (rank - below as f64);
+
+            ClosePercentile { percentile, price }
+        })
+        .collect()
+}
+
+/// Volume of each candle is put in the bucket of its typical price (high + low + close) / 3
+fn volume_profile(candles: &[OhlcvEntry], buckets: usize) -> Vec<VolumeBucket> {
+    let min_price = candles.iter().map(OhlcvEntry::low).fold(f64::MAX, f64::min);
+    let max_price = candles
+        .iter()
+        .map(OhlcvEntry::high)
+        .fold(f64::MIN, f64::max);
+    let total_volume = candles.iter().map(OhlcvEntry::volume).sum::<f64>();
+
+    if buckets == 0 || candles.is_empty() || max_price <= min_price || total_volume <= 0.0 {
+        return vec![];
+    }
+
+    let bucket_width = (max_price - min_price) / buckets as f64;
+    let mut volumes = vec![0.0; buckets];
+
+    for candle in candles {
+        let typical_price = (candle.high() + candle.low() + candle.close()) / 3.0;
+        let bucket = (((typical_price - min_price) / bucket_width) as usize).min(buckets - 1);
+        volumes[bucket] += candle.volume();
+    }
+
+    volumes
+        .into_iter()
+        .enumerate()
+        .map(|(index, volume)| VolumeBucket {
+            lower_price: min_price + bucket_width * index as f64,
+            upper_price: min_price + bucket_width * (index + 1) as f64,
+            volume_share: volume / total_volume,
+        })
+        .collect()
+}
+
+fn median_interval_seconds(candles: &[OhlcvEntry]) -> Option<i64> {
+    let mut intervals = candles
+        .windows(2)
+        .map(|pair| pair[1].timestamp() - pair[0].timestamp())
+        .collect::<Vec<i64>>();
+    if intervals.is_empty() {
+        return None;
+    }
+
+    intervals.sort_unstable();
+    Some(intervals[intervals.len() / 2])
+}
+
+fn log_returns(candles: &[OhlcvEntry]) -> Vec<f64> {
+    candles
+        .windows(2)
+        .filter(|pair| pair[0].close() > 0.0 && pair[1].close() > 0.0)
+        .map(|pair| (pair[1].close() / pair[0].close()).ln())
+        .collect()
+}
+
+fn last(candles: &[OhlcvEntry], count: usize) -> &[OhlcvEntry] {
+    &candles[candles.len().saturating_sub(count)..]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn candles_of(closes: &[f64]) -> Vec<OhlcvEntry> {
+        closes
+            .iter()
+            .enumerate()
+            .map(|(index, close)| {
+                OhlcvEntry(
+                    index as i64 * 3_600,
+                    *close,
+                    close * 1.01,
+                    close * 0.99,
+                    *close,
+                    10.0,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_volatility_and_trend_of_a_steady_rise() {
+        // +1% every candle: no dispersion of the returns and a perfect trend
+        let closes = (0..40).map(|i| 1.01f64.powi(i)).collect::<Vec<f64>>();
+        let candles = candles_of(&closes);
+
+        assert!(realized_volatility(&candles, 30).unwrap() < 1e-9);
+        assert!(realized_volatility(&candles, 41).is_none());
+
+        let trend = trend(&candles, 30).unwrap();
+        assert!((trend.slope_pct - 1.0).abs() < 1e-9);
+        assert!((trend.r_squared - 1.0).abs() < 1e-9);
+
+        // Alternating +/-10% moves
+        let closes = (0..11)
+            .map(|i| if i % 2 == 0 { 1.0 } else { 1.1 })
+            .collect::<Vec<f64>>();
+        let volatility = realized_volatility(&candles_of(&closes), 11).unwrap();
+        assert!((volatility - 1.1f64.ln()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_atr_range_and_volume_features() {
+        let candles = candles_of(&[1.0, 1.0, 1.0, 2.0]);
+
+        // The last candle gaps from 1.0 to a 1.98 - 2.02 candle
+        let atr = average_true_range(&candles, 3).unwrap();
+        assert!((atr - (0.02 + 0.02 + 1.02) / 3.0).abs() < 1e-9);
+
+        assert_eq!(time_in_range(&candles, 0.5, 1.5), Some(0.75));
+        assert_eq!(time_in_range(&candles, 1.5, 0.5), None);
+
+        let profile = volume_profile(&candles, 4);
+        assert_eq!(profile.len(), 4);
+        assert!((profile[0].volume_share - 0.75).abs() < 1e-9);
+        assert!((profile[3].volume_share - 0.25).abs() < 1e-9);
+
+        let percentiles = close_percentiles(&candles);
+        assert_eq!(percentiles[2].price, 1.0);
+        assert!((percentiles[4].price - 1.85).abs() < 1e-9);
+
+        let features = market_features(&candles, None);
+        assert_eq!(features.interval_seconds, Some(3_600));
+        assert_eq!(features.time_in_range_pct, None);
+        assert!(features.realized_volatility.is_empty());
+    }
+
+    #[test]
+    fn test_downsample_merges_candles_from_the_end() {
+        let candles = candles_of(&[1.0, 2.0, 3.0, 4.0, 5.0]);
+
+        let merged = downsample(&candles, 2);
+        assert_eq!(merged.len(), 2);
+
+        assert_eq!(merged[0].timestamp(), 0);
+        assert_eq!(merged[0].close(), 2.0);
+        assert_eq!(merged[0].volume(), 20.0);
+
+        assert_eq!(merged[1].timestamp(), 2 * 3_600);
+        assert_eq!(merged[1].1, 3.0);
+        assert_eq!(merged[1].high(), 5.0 * 1.01);
+        assert_eq!(merged[1].low(), 3.0 * 0.99);
+        assert_eq!(merged[1].close(), 5.0);
+
+        assert_eq!(downsample(&candles, 10).len(), 5);
+    }
+}
@@ -1,27 +1,121 @@
+pub mod features;
+pub mod oracle;
+pub mod swap_solver;
 pub mod uniswap_v3;
 
 use alloy::primitives::U256;
-use color_eyre::eyre::Result;
+use color_eyre::eyre::{Result, eyre};
 
 use crate::helpers::math::uniswap_v3::{
     liquidity_math::{get_amount1_for_liquidity, get_liquidity_for_amount0},
-    tick_math::get_sqrt_ratio_at_tick,
+    tick_math::{
+        MAX_SQRT_RATIO, MAX_TICK, MIN_SQRT_RATIO, MIN_TICK, get_sqrt_ratio_at_tick,
+        get_tick_at_sqrt_ratio,
+    },
 };
 
+/// 2^96, the fixed point scale of the sqrt prices
+const Q96: f64 = 79_228_162_514_264_337_593_543_950_336.0;
+
+/// Direction a price or a tick is rounded to when it falls between two ticks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TickRounding {
+    /// Greatest tick at or below the price
+    Down,
+    /// Smallest tick at or above the price
+    Up,
+}
+
 /// Convert a tick to a price.
 /// It caclulate the price1 which the price of token1 per token0
 pub fn tick_to_price(tick: i32, token0_decimals: u8, token1_decimals: u8) -> Result<f64> {
-    let price_tick = 1.0001f64.powi(tick as i32);
+    let sqrt_price_x96 = get_sqrt_ratio_at_tick(tick)?;
+
+    Ok(sqrt_price_x96_to_price(
+        sqrt_price_x96,
+        token0_decimals,
+        token1_decimals,
+    ))
+}
+
+/// Price1 (token1 per token0, in token units) of a sqrt price
+pub fn sqrt_price_x96_to_price(
+    sqrt_price_x96: U256,
+    token0_decimals: u8,
+    token1_decimals: u8,
+) -> f64 {
+    let sqrt_price = f64::from(sqrt_price_x96) / Q96;
+
+    sqrt_price * sqrt_price / decimals_factor(token0_decimals, token1_decimals)
+}
+
+/// Sqrt price of a price1, errors when the price is not in the range of the pool ticks
+pub fn price_to_sqrt_price_x96(
+    price: f64,
+    token0_decimals: u8,
+    token1_decimals: u8,
+) -> Result<U256> {
+    if !price.is_finite() || price <= 0.0 {
+        return Err(eyre!("Invalid price: {}", price));
+    }
 
-    let diff_decimals = token1_decimals as i8 - token0_decimals as i8;
+    let raw_price = price * decimals_factor(token0_decimals, token1_decimals);
+    let sqrt_price_x96 = U256::try_from(raw_price.sqrt() * Q96)
+        .map_err(|_| eyre!("Price {} is out of the tick range", price))?;
 
-    let price = price_tick / 10f64.powi(diff_decimals as i32);
+    if sqrt_price_x96 < MIN_SQRT_RATIO || sqrt_price_x96 >= MAX_SQRT_RATIO {
+        return Err(eyre!("Price {} is out of the tick range", price));
+    }
 
-    Ok(price)
+    Ok(sqrt_price_x96)
 }
 
-pub fn align_to_pool_tick_spacing(tick: i32, spacing: i32) -> i32 {
-    tick - (tick % spacing)
+/// Tick of a price1, rounded down or up when the price is between two ticks
+pub fn price_to_tick(
+    price: f64,
+    token0_decimals: u8,
+    token1_decimals: u8,
+    rounding: TickRounding,
+) -> Result<i32> {
+    let sqrt_price_x96 = price_to_sqrt_price_x96(price, token0_decimals, token1_decimals)?;
+
+    // Greatest tick whose sqrt price is at or below the price
+    let tick = get_tick_at_sqrt_ratio(sqrt_price_x96)?;
+
+    if rounding == TickRounding::Up && get_sqrt_ratio_at_tick(tick)? != sqrt_price_x96 {
+        return Ok(tick + 1);
+    }
+
+    Ok(tick)
+}
+
+/// Round a tick to a multiple of the pool tick spacing, kept in the usable range of the pool
+pub fn align_to_pool_tick_spacing(tick: i32, spacing: i32, rounding: TickRounding) -> Result<i32> {
+    if spacing <= 0 {
+        return Err(eyre!("Invalid tick spacing: {}", spacing));
+    }
+
+    if !(MIN_TICK..=MAX_TICK).contains(&tick) {
+        return Err(eyre!("Tick {} is out of the tick range", tick));
+    }
+
+    // Floor and ceil, also for the negative ticks
+    let aligned_tick = match rounding {
+        TickRounding::Down => tick.div_euclid(spacing) * spacing,
+        TickRounding::Up => -(-tick).div_euclid(spacing) * spacing,
+    };
+
+    let (min_tick, max_tick) = usable_tick_range(spacing);
+
+    Ok(aligned_tick.clamp(min_tick, max_tick))
+}
+
+/// Lowest and highest ticks of a pool that are multiples of its tick spacing
+pub fn usable_tick_range(spacing: i32) -> (i32, i32) {
+    (
+        -(MAX_TICK / spacing) * spacing,
+        (MAX_TICK / spacing) * spacing,
+    )
 }
 
 pub fn convert_price_to_tick(
@@ -29,21 +123,61 @@ pub fn convert_price_to_tick(
     token0_decimals: u8,
     token1_decimals: u8,
     tick_spacing: i32,
+    rounding: TickRounding,
 ) -> Result<i32> {
-    let tick = price1_to_tick(price, token0_decimals, token1_decimals);
+    let tick = price_to_tick(price, token0_decimals, token1_decimals, rounding)?;
 
     // Align the tick to the pool tick spacing
-    let aligned_tick = align_to_pool_tick_spacing(tick, tick_spacing);
-
-    Ok(aligned_tick)
+    align_to_pool_tick_spacing(tick, tick_spacing, rounding)
 }
 
-pub fn price1_to_tick(price: f64, token0_decimals: u8, token1_decimals: u8) -> i32 {
-    let diff_decimals = token1_decimals as i8 - token0_decimals as i8;
-    let adjusted_price = price * 10_f64.powi(diff_decimals as i32);
+/// Ticks of a price range, aligned outwards so the range covers at least the prices asked for
+pub fn price_range_to_ticks(
+    lower_price: f64,
+    upper_price: f64,
+    token0_decimals: u8,
+    token1_decimals: u8,
+    tick_spacing: i32,
+) -> Result<(i32, i32)> {
+    if lower_price >= upper_price {
+        return Err(eyre!(
+            "Invalid price range: lower price {} is not below upper price {}",
+            lower_price,
+            upper_price
+        ));
+    }
+
+    let lower_tick = convert_price_to_tick(
+        lower_price,
+        token0_decimals,
+        token1_decimals,
+        tick_spacing,
+        TickRounding::Down,
+    )?;
+    let upper_tick = convert_price_to_tick(
+        upper_price,
+        token0_decimals,
+        token1_decimals,
+        tick_spacing,
+        TickRounding::Up,
+    )?;
+
+    // Both prices between the same two aligned ticks, only possible at the edges of the usable range
+    if lower_tick >= upper_tick {
+        return Err(eyre!(
+            "Price range [{}, {}] is too narrow for the tick spacing {}",
+            lower_price,
+            upper_price,
+            tick_spacing
+        ));
+    }
+
+    Ok((lower_tick, upper_tick))
+}
 
-    let log_base = 1.0001_f64.ln(); // natural log of 1.0001
-    (adjusted_price.ln() / log_base).floor() as i32
+/// 10^(token1 decimals - token0 decimals), from a price in token units to a price in raw units
+fn decimals_factor(token0_decimals: u8, token1_decimals: u8) -> f64 {
+    10f64.powi(token1_decimals as i32 - token0_decimals as i32)
 }
 
 pub fn estimate_amount1_given_amount0(
@@ -63,3 +197,90 @@ pub fn estimate_amount1_given_amount0(
 
     Ok(amount1)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_tick_price_round_trip() {
+        for tick in [-887_000, -200_000, -60, -1, 0, 1, 60, 200_000, 887_000] {
+            let price = tick_to_price(tick, 8, 6).unwrap();
+
+            assert_eq!(
+                price_to_tick(price * 1.000_001, 8, 6, TickRounding::Down).unwrap(),
+                tick
+            );
+            assert_eq!(
+                price_to_tick(price * 0.999_999, 8, 6, TickRounding::Up).unwrap(),
+                tick
+            );
+        }
+
+        // 1 token1 per token0 with 8 and 6 decimals is 0.01 in raw units
+        let tick = price_to_tick(1.0, 8, 6, TickRounding::Down).unwrap();
+        assert!(tick_to_price(tick, 8, 6).unwrap() <= 1.0);
+        assert!(tick_to_price(tick + 1, 8, 6).unwrap() > 1.0);
+    }
+
+    #[test]
+    fn test_invalid_prices_and_ticks_are_rejected() {
+        assert!(price_to_tick(0.0, 18, 18, TickRounding::Down).is_err());
+        assert!(price_to_tick(-1.0, 18, 18, TickRounding::Down).is_err());
+        assert!(price_to_tick(f64::NAN, 18, 18, TickRounding::Down).is_err());
+        assert!(price_to_tick(1e40, 18, 18, TickRounding::Down).is_err());
+        assert!(tick_to_price(MAX_TICK + 1, 18, 18).is_err());
+        assert!(align_to_pool_tick_spacing(0, 0, TickRounding::Down).is_err());
+    }
+
+    #[test]
+    fn test_align_rounds_negative_ticks_the_right_way() {
+        assert_eq!(
+            align_to_pool_tick_spacing(-61, 60, TickRounding::Down).unwrap(),
+            -120
+        );
+        assert_eq!(
+            align_to_pool_tick_spacing(-61, 60, TickRounding::Up).unwrap(),
+            -60
+        );
+        assert_eq!(
+            align_to_pool_tick_spacing(61, 60, TickRounding::Down).unwrap(),
+            60
+        );
+        assert_eq!(
+            align_to_pool_tick_spacing(61, 60, TickRounding::Up).unwrap(),
+            120
+        );
+        assert_eq!(
+            align_to_pool_tick_spacing(-120, 60, TickRounding::Up).unwrap(),
+            -120
+        );
+
+        // Clamped to the multiples of the spacing inside the tick range
+        assert_eq!(
+            align_to_pool_tick_spacing(MIN_TICK, 60, TickRounding::Down).unwrap(),
+            -887_220
+        );
+        assert_eq!(
+            align_to_pool_tick_spacing(MAX_TICK, 60, TickRounding::Up).unwrap(),
+            887_220
+        );
+    }
+
+    #[test]
+    fn test_price_range_is_widened_to_the_tick_spacing() {
+        let price = tick_to_price(-23_030, 8, 6).unwrap();
+
+        let (lower_tick, upper_tick) =
+            price_range_to_ticks(price * 0.99, price * 1.01, 8, 6, 60).unwrap();
+
+        assert_eq!(lower_tick % 60, 0);
+        assert_eq!(upper_tick % 60, 0);
+        assert!(tick_to_price(lower_tick, 8, 6).unwrap() <= price * 0.99);
+        assert!(tick_to_price(upper_tick, 8, 6).unwrap() >= price * 1.01);
+        assert!(upper_tick - lower_tick <= 200 + 2 * 60);
+
+        assert!(price_range_to_ticks(price, price, 8, 6, 60).is_err());
+        assert!(price_range_to_ticks(price * 1.01, price * 0.99, 8, 6, 60).is_err());
+    }
+}
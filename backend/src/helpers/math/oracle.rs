@@ -0,0 +1,137 @@
+/*
+    Port of the TWAP and quote functions of `contracts/src/libraries/OracleLibrary.sol`.
+    The functions take the values read from the pool (e.g. the result of `observe`) instead of the pool address,
+    the calls are done by `core::oracle`.
+*/
+
+use alloy::primitives::{Address, U256, aliases::U160};
+use color_eyre::eyre::{Result, eyre};
+
+use crate::helpers::math::uniswap_v3::{full_math::mul_div, tick_math::get_sqrt_ratio_at_tick};
+
+/// Time-weighted means of tick and liquidity from the two observations returned by
+/// `observe([seconds_ago, 0])`. Returns (arithmetic mean tick, harmonic mean liquidity).
+pub fn consult(
+    seconds_ago: u32,
+    tick_cumulatives: [i64; 2],
+    seconds_per_liquidity_cumulative_x128s: [U160; 2],
+) -> Result<(i32, u128)> {
+    if seconds_ago == 0 {
+        return Err(eyre!("BP: seconds ago must not be 0"));
+    }
+
+    let tick_cumulatives_delta = tick_cumulatives[1] - tick_cumulatives[0];
+    let seconds_per_liquidity_cumulatives_delta = seconds_per_liquidity_cumulative_x128s[1]
+        .wrapping_sub(seconds_per_liquidity_cumulative_x128s[0]);
+
+    let mut arithmetic_mean_tick = (tick_cumulatives_delta / seconds_ago as i64) as i32;
+    // Always round to negative infinity
+    if tick_cumulatives_delta < 0 && tick_cumulatives_delta % seconds_ago as i64 != 0 {
+        arithmetic_mean_tick -= 1;
+    }
+
+    if seconds_per_liquidity_cumulatives_delta.is_zero() {
+        return Err(eyre!(
+            "No seconds per liquidity accumulated over the window"
+        ));
+    }
+
+    // Multiplying instead of shifting so the harmonic mean liquidity does not overflow u128
+    let seconds_ago_x160 = U256::from(seconds_ago) * U256::from(U160::MAX);
+    let harmonic_mean_liquidity: U256 =
+        seconds_ago_x160 / (U256::from(seconds_per_liquidity_cumulatives_delta) << 32);
+
+    Ok((
+        arithmetic_mean_tick,
+        harmonic_mean_liquidity.wrapping_to::<u128>(),
+    ))
+}
+
+/// Amount of `quote_token` received for `base_amount` of `base_token` at the price of `tick`
+pub fn get_quote_at_tick(
+    tick: i32,
+    base_amount: u128,
+    base_token: Address,
+    quote_token: Address,
+) -> Result<U256> {
+    let sqrt_ratio_x96 = get_sqrt_ratio_at_tick(tick)?;
+    let base_amount = U256::from(base_amount);
+
+    // Better precision when the ratio does not overflow when multiplied by itself
+    let quote_amount = if sqrt_ratio_x96 <= U256::from(u128::MAX) {
+        let ratio_x192 = sqrt_ratio_x96 * sqrt_ratio_x96;
+
+        if base_token < quote_token {
+            mul_div(ratio_x192, base_amount, U256::ONE << 192)?
+        } else {
+            mul_div(U256::ONE << 192, base_amount, ratio_x192)?
+        }
+    } else {
+        let ratio_x128 = mul_div(sqrt_ratio_x96, sqrt_ratio_x96, U256::ONE << 64)?;
+
+        if base_token < quote_token {
+            mul_div(ratio_x128, base_amount, U256::ONE << 128)?
+        } else {
+            mul_div(U256::ONE << 128, base_amount, ratio_x128)?
+        }
+    };
+
+    Ok(quote_amount)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_consult_rounds_to_negative_infinity() {
+        let seconds_per_liquidity = [U160::ZERO, U160::from(1u64 << 40)];
+
+        let (tick, _) = consult(10, [0, 1_000], seconds_per_liquidity).unwrap();
+        assert_eq!(tick, 100);
+
+        let (tick, _) = consult(10, [0, -1_005], seconds_per_liquidity).unwrap();
+        assert_eq!(tick, -101);
+
+        let (tick, _) = consult(10, [500, -500], seconds_per_liquidity).unwrap();
+        assert_eq!(tick, -100);
+
+        assert!(consult(0, [0, 0], seconds_per_liquidity).is_err());
+    }
+
+    #[test]
+    fn test_consult_harmonic_mean_liquidity() {
+        // Constant liquidity L accumulates seconds * 2^128 / L
+        let liquidity = 1_000_000_000u128;
+        let seconds_ago = 60u32;
+        let delta = (U256::from(seconds_ago) << 128) / U256::from(liquidity);
+
+        let (_, harmonic_mean_liquidity) =
+            consult(seconds_ago, [0, 0], [U160::ZERO, U160::from(delta)]).unwrap();
+
+        let error = harmonic_mean_liquidity.abs_diff(liquidity);
+        assert!(error <= 1, "{}", harmonic_mean_liquidity);
+    }
+
+    #[test]
+    fn test_quote_at_tick() {
+        let token0 = Address::repeat_byte(1);
+        let token1 = Address::repeat_byte(2);
+
+        assert_eq!(
+            get_quote_at_tick(0, 1_000_000, token0, token1).unwrap(),
+            U256::from(1_000_000)
+        );
+
+        // Price of token0 in token1 is 1.0001^tick, and the inverse the other way
+        let quote = get_quote_at_tick(6_932, 1_000_000, token0, token1).unwrap();
+        assert_eq!(quote, U256::from(2_000_036));
+
+        let quote = get_quote_at_tick(6_932, 1_000_000, token1, token0).unwrap();
+        assert_eq!(quote, U256::from(499_990));
+
+        // Ratios above u128::MAX take the lower precision path
+        let quote = get_quote_at_tick(500_000, 1, token1, token0).unwrap();
+        assert_eq!(quote, U256::ZERO);
+    }
+}
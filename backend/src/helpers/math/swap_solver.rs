@@ -0,0 +1,354 @@
+use alloy::primitives::{I256, U256};
+use color_eyre::eyre::Result;
+
+use crate::helpers::math::uniswap_v3::{
+    full_math::mul_div,
+    liquidity_math::{get_amounts_for_liquidity, get_liquidity_for_amounts},
+    swap_simulator::{PoolSnapshot, SwapSimulation, simulate_swap},
+    tick_math::get_sqrt_ratio_at_tick,
+};
+
+/// Liquidity used to read the token ratio of a range at a given price, large enough to keep the rounding
+/// negligible on narrow ranges
+const PROBE_LIQUIDITY: u128 = 1 << 96;
+
+/// Swap to run before minting so the vault balances match the ratio of the new range
+#[derive(Debug, Clone)]
+pub struct SwapSolution {
+    pub zero_for_one: bool,
+    /// Exact amount out of the swap, zero when no swap is needed
+    pub amount_out: U256,
+    /// Simulated amount in, fees included
+    pub amount_in: U256,
+    /// `amount_in` plus the slippage tolerance, capped to the input token balance
+    pub max_amount_in: U256,
+    pub balance0_after: U256,
+    pub balance1_after: U256,
+    pub tick_before: i32,
+    /// Pool price once the swap is done, the mint happens at this price
+    pub sqrt_price_x96_after: U256,
+    pub tick_after: i32,
+    pub simulation: Option<SwapSimulation>,
+}
+
+impl SwapSolution {
+    /// Price move caused by the swap, in percent of the pool price
+    pub fn price_impact_pct(&self) -> f64 {
+        (1.0001_f64.powi(self.tick_after - self.tick_before) - 1.0) * 100.0
+    }
+
+    /// Liquidity and amounts of the position minted on `[tick_lower, tick_upper]` with the post swap balances.
+    /// Returns (uint128 liquidity, uint256 amount0, uint256 amount1)
+    pub fn expected_position(
+        &self,
+        tick_lower: i32,
+        tick_upper: i32,
+    ) -> Result<(u128, U256, U256)> {
+        let sqrt_lower = get_sqrt_ratio_at_tick(tick_lower)?;
+        let sqrt_upper = get_sqrt_ratio_at_tick(tick_upper)?;
+
+        let liquidity = get_liquidity_for_amounts(
+            self.sqrt_price_x96_after,
+            sqrt_lower,
+            sqrt_upper,
+            self.balance0_after,
+            self.balance1_after,
+        )?;
+
+        let (amount0, amount1) = get_amounts_for_liquidity(
+            self.sqrt_price_x96_after,
+            sqrt_lower,
+            sqrt_upper,
+            liquidity,
+        )?;
+
+        Ok((liquidity, amount0, amount1))
+    }
+}
+
+/// Find the exact output swap that leaves `balance0` and `balance1` in the ratio the range `[tick_lower, tick_upper]`
+/// needs at the price the swap ends on. The swap is replayed on the snapshot, so the pool fee and the price impact
+/// are both taken into account.
+///
+/// The snapshot must not contain the liquidity that gets burned before the swap (see [`PoolSnapshot::remove_position`]).
+pub fn solve_rebalance_swap(
+    snapshot: &PoolSnapshot,
+    balance0: U256,
+    balance1: U256,
+    tick_lower: i32,
+    tick_upper: i32,
+    slippage_bps: u32,
+) -> Result<SwapSolution> {
+    let sqrt_lower = get_sqrt_ratio_at_tick(tick_lower)?;
+    let sqrt_upper = get_sqrt_ratio_at_tick(tick_upper)?;
+
+    let no_swap = SwapSolution {
+        zero_for_one: true,
+        amount_out: U256::ZERO,
+        amount_in: U256::ZERO,
+        max_amount_in: U256::ZERO,
+        balance0_after: balance0,
+        balance1_after: balance1,
+        tick_before: snapshot.tick,
+        sqrt_price_x96_after: snapshot.sqrt_price_x96,
+        tick_after: snapshot.tick,
+        simulation: None,
+    };
+
+    let zero_for_one = if has_excess_of_token_in(
+        true,
+        balance0,
+        balance1,
+        snapshot.sqrt_price_x96,
+        sqrt_lower,
+        sqrt_upper,
+    )? {
+        true
+    } else if has_excess_of_token_in(
+        false,
+        balance1,
+        balance0,
+        snapshot.sqrt_price_x96,
+        sqrt_lower,
+        sqrt_upper,
+    )? {
+        false
+    } else {
+        return Ok(no_swap);
+    };
+
+    let (balance_in, balance_out) = if zero_for_one {
+        (balance0, balance1)
+    } else {
+        (balance1, balance0)
+    };
+
+    // The most we can get out is swapping the whole input balance. On a thin pool that swap can walk past the
+    // fetched bitmap words, so it stops at their edge: the solution then trades at most the known liquidity
+    let price_limit = snapshot.known_sqrt_price_limit(zero_for_one)?;
+
+    let is_at_known_edge = price_limit.is_some_and(|limit| {
+        if zero_for_one {
+            limit >= snapshot.sqrt_price_x96
+        } else {
+            limit <= snapshot.sqrt_price_x96
+        }
+    });
+
+    if is_at_known_edge {
+        return Ok(no_swap);
+    }
+
+    let max_amount_out = simulate_swap(
+        snapshot,
+        zero_for_one,
+        I256::from_raw(balance_in),
+        price_limit,
+    )?
+    .amount_out;
+
+    // Binary search the largest amount out that still leaves an excess of the input token.
+    // The excess only shrinks when the amount out grows: less token in, more token out and a price
+    // that moves toward needing more of the input token.
+    let mut low = U256::ZERO;
+    let mut high = max_amount_out;
+    let mut best: Option<SwapSimulation> = None;
+
+    while high - low > U256::from(1) {
+        let mid = low + (high - low) / U256::from(2);
+
+        let simulation = simulate_swap(snapshot, zero_for_one, -I256::from_raw(mid), price_limit)?;
+
+        // the pool can not provide `mid` or we can not pay for it
+        if simulation.amount_out < mid || simulation.amount_in > balance_in {
+            high = mid;
+            continue;
+        }
+
+        if has_excess_of_token_in(
+            zero_for_one,
+            balance_in - simulation.amount_in,
+            balance_out + simulation.amount_out,
+            simulation.sqrt_price_x96_after,
+            sqrt_lower,
+            sqrt_upper,
+        )? {
+            low = mid;
+            best = Some(simulation);
+        } else {
+            high = mid;
+        }
+    }
+
+    let Some(simulation) = best else {
+        return Ok(no_swap);
+    };
+
+    let amount_in = simulation.amount_in;
+    let slippage = mul_div(amount_in, U256::from(slippage_bps), U256::from(10_000))?;
+    let max_amount_in = (amount_in + slippage).min(balance_in);
+
+    let (balance0_after, balance1_after) = if zero_for_one {
+        (balance0 - amount_in, balance1 + simulation.amount_out)
+    } else {
+        (balance0 + simulation.amount_out, balance1 - amount_in)
+    };
+
+    Ok(SwapSolution {
+        zero_for_one,
+        amount_out: low,
+        amount_in,
+        max_amount_in,
+        balance0_after,
+        balance1_after,
+        tick_before: snapshot.tick,
+        sqrt_price_x96_after: simulation.sqrt_price_x96_after,
+        tick_after: simulation.tick_after,
+        simulation: Some(simulation),
+    })
+}
+
+/// Whether the balances hold more of the swap input token than the range needs at `sqrt_price_x96`
+fn has_excess_of_token_in(
+    zero_for_one: bool,
+    balance_in: U256,
+    balance_out: U256,
+    sqrt_price_x96: U256,
+    sqrt_lower: U256,
+    sqrt_upper: U256,
+) -> Result<bool> {
+    let (ratio0, ratio1) =
+        get_amounts_for_liquidity(sqrt_price_x96, sqrt_lower, sqrt_upper, PROBE_LIQUIDITY)?;
+
+    let (ratio_in, ratio_out) = if zero_for_one {
+        (ratio0, ratio1)
+    } else {
+        (ratio1, ratio0)
+    };
+
+    if ratio_in.is_zero() {
+        // the range only holds the output token, all the input token is excess
+        return Ok(!balance_in.is_zero());
+    }
+
+    // amount of output token that pairs with the input balance in the range
+    let paired_amount_out = mul_div(balance_in, ratio_out, ratio_in)?;
+
+    Ok(paired_amount_out > balance_out)
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::helpers::math::uniswap_v3::{U256_1, sqrt_price_math::Q96, tick_bitmap::position};
+
+    const LIQUIDITY: u128 = 1_000_000_000_000_000_000;
+
+    // Pool at tick 0 with a single position on [-6000, 6000]
+    fn snapshot() -> PoolSnapshot {
+        let tick_spacing = 60;
+        let liquidity_net: HashMap<i32, i128> =
+            [(-6000, LIQUIDITY as i128), (6000, -(LIQUIDITY as i128))]
+                .into_iter()
+                .collect();
+
+        let mut tick_bitmap: HashMap<i16, U256> = (-2..2).map(|word| (word, U256::ZERO)).collect();
+        for tick in liquidity_net.keys() {
+            let (word_pos, bit_pos) = position(tick / tick_spacing);
+            *tick_bitmap.get_mut(&word_pos).unwrap() |= U256_1 << bit_pos;
+        }
+
+        PoolSnapshot {
+            sqrt_price_x96: Q96,
+            tick: 0,
+            liquidity: LIQUIDITY,
+            fee_pips: 3000,
+            tick_spacing,
+            tick_bitmap,
+            liquidity_net,
+        }
+    }
+
+    // Share of the balances left unused after minting the largest position they allow
+    fn leftover_share(solution: &SwapSolution, tick_lower: i32, tick_upper: i32) -> (f64, f64) {
+        let (_, used0, used1) = solution.expected_position(tick_lower, tick_upper).unwrap();
+
+        let share = |balance: U256, used: U256| {
+            if balance.is_zero() {
+                0.0
+            } else {
+                (balance - used).to::<u128>() as f64 / balance.to::<u128>() as f64
+            }
+        };
+
+        (
+            share(solution.balance0_after, used0),
+            share(solution.balance1_after, used1),
+        )
+    }
+
+    #[test]
+    fn test_balanced_amounts_need_no_swap() {
+        let snapshot = snapshot();
+        let sqrt_lower = get_sqrt_ratio_at_tick(-600).unwrap();
+        let sqrt_upper = get_sqrt_ratio_at_tick(600).unwrap();
+
+        let (amount0, amount1) =
+            get_amounts_for_liquidity(snapshot.sqrt_price_x96, sqrt_lower, sqrt_upper, LIQUIDITY)
+                .unwrap();
+
+        let solution = solve_rebalance_swap(&snapshot, amount0, amount1, -600, 600, 50).unwrap();
+
+        assert!(solution.amount_out < U256::from(10));
+    }
+
+    #[test]
+    fn test_single_sided_balance_is_split() {
+        let snapshot = snapshot();
+        let balance0 = U256::from(100_000_000_000_000_000u128);
+
+        let solution =
+            solve_rebalance_swap(&snapshot, balance0, U256::ZERO, -600, 600, 50).unwrap();
+
+        assert!(solution.zero_for_one);
+        assert!(solution.amount_in > U256::ZERO);
+        assert!(solution.max_amount_in >= solution.amount_in);
+        assert!(solution.max_amount_in <= balance0);
+        assert_eq!(solution.balance0_after, balance0 - solution.amount_in);
+
+        let (leftover0, leftover1) = leftover_share(&solution, -600, 600);
+        assert!(leftover0 < 1e-9, "leftover0: {leftover0}");
+        assert!(leftover1 < 1e-9, "leftover1: {leftover1}");
+    }
+
+    #[test]
+    fn test_range_above_price_takes_all_token1() {
+        let snapshot = snapshot();
+        let balance1 = U256::from(100_000_000_000_000_000u128);
+
+        let solution =
+            solve_rebalance_swap(&snapshot, U256::ZERO, balance1, 3000, 4200, 50).unwrap();
+
+        assert!(!solution.zero_for_one);
+        // the whole token1 balance is swapped, except for the rounding of the exact output search
+        assert!(solution.balance1_after < U256::from(10));
+        assert!(solution.max_amount_in <= balance1);
+    }
+
+    #[test]
+    fn test_thin_pool_swap_stays_in_known_words() {
+        let snapshot = snapshot();
+        // Far more than the pool liquidity can take before the price leaves the fetched words
+        let balance0 = U256::from(10u128.pow(27));
+
+        let solution =
+            solve_rebalance_swap(&snapshot, balance0, U256::ZERO, -600, 600, 50).unwrap();
+
+        assert!(solution.zero_for_one);
+        assert!(solution.amount_out > U256::ZERO);
+        assert!(solution.amount_in < balance0);
+        assert!(solution.tick_after >= -2 * 256 * 60);
+    }
+}
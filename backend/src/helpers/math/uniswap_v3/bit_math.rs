@@ -0,0 +1,56 @@
+use alloy::primitives::U256;
+
+use crate::helpers::math::uniswap_v3::error::UniswapV3MathError;
+
+/// Returns the index of the most significant bit of the number, where the least significant bit is at index 0
+pub fn most_significant_bit(x: U256) -> Result<u8, UniswapV3MathError> {
+    if x.is_zero() {
+        return Err(UniswapV3MathError::ZeroValue);
+    }
+
+    Ok((x.bit_len() - 1) as u8)
+}
+
+/// Returns the index of the least significant bit of the number, where the least significant bit is at index 0
+pub fn least_significant_bit(x: U256) -> Result<u8, UniswapV3MathError> {
+    if x.is_zero() {
+        return Err(UniswapV3MathError::ZeroValue);
+    }
+
+    Ok(x.trailing_zeros() as u8)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::helpers::math::uniswap_v3::U256_1;
+
+    #[test]
+    fn test_most_significant_bit() {
+        let result = most_significant_bit(U256::ZERO);
+        assert_eq!(
+            result.err().unwrap().to_string(),
+            "Can not get most significant bit or least significant bit on zero value"
+        );
+
+        assert_eq!(most_significant_bit(U256_1).unwrap(), 0);
+        assert_eq!(most_significant_bit(U256::from(2)).unwrap(), 1);
+        assert_eq!(most_significant_bit(U256_1 << 100).unwrap(), 100);
+        assert_eq!(most_significant_bit(U256::MAX).unwrap(), 255);
+    }
+
+    #[test]
+    fn test_least_significant_bit() {
+        let result = least_significant_bit(U256::ZERO);
+        assert_eq!(
+            result.err().unwrap().to_string(),
+            "Can not get most significant bit or least significant bit on zero value"
+        );
+
+        assert_eq!(least_significant_bit(U256_1).unwrap(), 0);
+        assert_eq!(least_significant_bit(U256::from(2)).unwrap(), 1);
+        assert_eq!(least_significant_bit(U256_1 << 100).unwrap(), 100);
+        assert_eq!(least_significant_bit(U256::MAX).unwrap(), 0);
+    }
+}
@@ -35,6 +35,12 @@ pub enum UniswapV3MathError {
     SafeCastToU160Overflow,
     #[error("Tick spacing error")]
     TickSpacingError,
+    #[error("Tick bitmap word {0} is not part of the pool snapshot")]
+    TickBitmapWordMissing(i16),
+    #[error("Liquidity net of initialized tick {0} is not part of the pool snapshot")]
+    TickLiquidityMissing(i32),
+    #[error("Sqrt price limit is on the wrong side of the current price or outside the tick range")]
+    SqrtPriceLimit,
     #[error("Middleware error when getting next_initialized_tick_within_one_word")]
     MiddlewareError(String),
     #[error("Parse error")]
@@ -1,13 +1,14 @@
 use alloy::primitives::U256;
 
-// pub mod bit_math;
+pub mod bit_math;
 pub mod error;
 pub mod full_math;
 pub mod liquidity_math;
 pub mod sqrt_price_math;
-// pub mod swap_math;
+pub mod swap_math;
+pub mod swap_simulator;
 // pub mod tick;
-// pub mod tick_bitmap;
+pub mod tick_bitmap;
 pub mod tick_math;
 pub mod unsafe_math;
 
@@ -0,0 +1,229 @@
+use alloy::primitives::{I256, U256};
+
+use crate::helpers::math::uniswap_v3::{
+    error::UniswapV3MathError,
+    full_math::{mul_div, mul_div_rounding_up},
+    sqrt_price_math::{
+        _get_amount_0_delta, _get_amount_1_delta, get_next_sqrt_price_from_input,
+        get_next_sqrt_price_from_output,
+    },
+};
+
+pub const FEE_PIPS_DENOMINATOR: u32 = 1_000_000;
+
+/// Computes the result of swapping some amount in, or amount out, given the parameters of the swap.
+/// Returns (uint160 sqrtRatioNextX96, uint256 amountIn, uint256 amountOut, uint256 feeAmount)
+///
+/// - sqrt_ratio_current_x_96: The current sqrt price of the pool
+/// - sqrt_ratio_target_x_96: The price that cannot be exceeded, from which the direction of the swap is inferred
+/// - liquidity: The usable liquidity
+/// - amount_remaining: How much input or output amount is remaining to be swapped in/out (positive for exact input)
+/// - fee_pips: The fee taken from the input amount, expressed in hundredths of a bip
+pub fn compute_swap_step(
+    sqrt_ratio_current_x_96: U256,
+    sqrt_ratio_target_x_96: U256,
+    liquidity: u128,
+    amount_remaining: I256,
+    fee_pips: u32,
+) -> Result<(U256, U256, U256, U256), UniswapV3MathError> {
+    let zero_for_one = sqrt_ratio_current_x_96 >= sqrt_ratio_target_x_96;
+    let exact_in = amount_remaining >= I256::ZERO;
+
+    let sqrt_ratio_next_x_96: U256;
+    let mut amount_in = U256::ZERO;
+    let mut amount_out = U256::ZERO;
+
+    if exact_in {
+        let amount_remaining_less_fee = mul_div(
+            amount_remaining.into_raw(),
+            U256::from(FEE_PIPS_DENOMINATOR - fee_pips),
+            U256::from(FEE_PIPS_DENOMINATOR),
+        )?;
+
+        amount_in = if zero_for_one {
+            _get_amount_0_delta(
+                sqrt_ratio_target_x_96,
+                sqrt_ratio_current_x_96,
+                liquidity,
+                true,
+            )?
+        } else {
+            _get_amount_1_delta(
+                sqrt_ratio_current_x_96,
+                sqrt_ratio_target_x_96,
+                liquidity,
+                true,
+            )?
+        };
+
+        sqrt_ratio_next_x_96 = if amount_remaining_less_fee >= amount_in {
+            sqrt_ratio_target_x_96
+        } else {
+            get_next_sqrt_price_from_input(
+                sqrt_ratio_current_x_96,
+                liquidity,
+                amount_remaining_less_fee,
+                zero_for_one,
+            )?
+        };
+    } else {
+        amount_out = if zero_for_one {
+            _get_amount_1_delta(
+                sqrt_ratio_target_x_96,
+                sqrt_ratio_current_x_96,
+                liquidity,
+                false,
+            )?
+        } else {
+            _get_amount_0_delta(
+                sqrt_ratio_current_x_96,
+                sqrt_ratio_target_x_96,
+                liquidity,
+                false,
+            )?
+        };
+
+        sqrt_ratio_next_x_96 = if (-amount_remaining).into_raw() >= amount_out {
+            sqrt_ratio_target_x_96
+        } else {
+            get_next_sqrt_price_from_output(
+                sqrt_ratio_current_x_96,
+                liquidity,
+                (-amount_remaining).into_raw(),
+                zero_for_one,
+            )?
+        };
+    }
+
+    let max = sqrt_ratio_target_x_96 == sqrt_ratio_next_x_96;
+
+    // get the input/output amounts
+    if zero_for_one {
+        if !max || !exact_in {
+            amount_in = _get_amount_0_delta(
+                sqrt_ratio_next_x_96,
+                sqrt_ratio_current_x_96,
+                liquidity,
+                true,
+            )?;
+        }
+
+        if !max || exact_in {
+            amount_out = _get_amount_1_delta(
+                sqrt_ratio_next_x_96,
+                sqrt_ratio_current_x_96,
+                liquidity,
+                false,
+            )?;
+        }
+    } else {
+        if !max || !exact_in {
+            amount_in = _get_amount_1_delta(
+                sqrt_ratio_current_x_96,
+                sqrt_ratio_next_x_96,
+                liquidity,
+                true,
+            )?;
+        }
+
+        if !max || exact_in {
+            amount_out = _get_amount_0_delta(
+                sqrt_ratio_current_x_96,
+                sqrt_ratio_next_x_96,
+                liquidity,
+                false,
+            )?;
+        }
+    }
+
+    // cap the output amount to not exceed the remaining output amount
+    if !exact_in && amount_out > (-amount_remaining).into_raw() {
+        amount_out = (-amount_remaining).into_raw();
+    }
+
+    let fee_amount = if exact_in && sqrt_ratio_next_x_96 != sqrt_ratio_target_x_96 {
+        // we didn't reach the target, so take the remainder of the maximum input as fee
+        amount_remaining.into_raw() - amount_in
+    } else {
+        mul_div_rounding_up(
+            amount_in,
+            U256::from(fee_pips),
+            U256::from(FEE_PIPS_DENOMINATOR - fee_pips),
+        )?
+    };
+
+    Ok((sqrt_ratio_next_x_96, amount_in, amount_out, fee_amount))
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use alloy::primitives::{I256, U256};
+
+    use super::compute_swap_step;
+
+    #[test]
+    fn test_compute_swap_step() {
+        // exact amount in that gets capped at price target in one for zero
+        let price = U256::from_str("79228162514264337593543950336").unwrap();
+        let price_target = U256::from_str("79623317895830914510639640423").unwrap();
+        let liquidity = 2e18 as u128;
+        let amount = I256::from_dec_str("1000000000000000000").unwrap();
+        let fee = 600;
+
+        let (sqrt_q, amount_in, amount_out, fee_amount) =
+            compute_swap_step(price, price_target, liquidity, amount, fee).unwrap();
+
+        assert_eq!(amount_in, U256::from_str("9975124224178055").unwrap());
+        assert_eq!(fee_amount, U256::from_str("5988667735148").unwrap());
+        assert_eq!(amount_out, U256::from_str("9925619580021728").unwrap());
+        assert!(amount_in + fee_amount < amount.into_raw());
+        assert_eq!(sqrt_q, price_target);
+
+        // exact amount out that gets capped at price target in one for zero
+        let amount = -I256::from_dec_str("1000000000000000000").unwrap();
+
+        let (sqrt_q, amount_in, amount_out, fee_amount) =
+            compute_swap_step(price, price_target, liquidity, amount, fee).unwrap();
+
+        assert_eq!(amount_in, U256::from_str("9975124224178055").unwrap());
+        assert_eq!(fee_amount, U256::from_str("5988667735148").unwrap());
+        assert_eq!(amount_out, U256::from_str("9925619580021728").unwrap());
+        assert!(amount_out < (-amount).into_raw());
+        assert_eq!(sqrt_q, price_target);
+
+        // amount out is capped at the desired amount out
+        let (sqrt_q, amount_in, amount_out, fee_amount) = compute_swap_step(
+            U256::from_str("417332158212080721273783715441582").unwrap(),
+            U256::from_str("1452870262520218020823638996").unwrap(),
+            159344665391607089467575320103,
+            I256::from_dec_str("-1").unwrap(),
+            1,
+        )
+        .unwrap();
+
+        assert_eq!(amount_in, U256::from(1));
+        assert_eq!(fee_amount, U256::from(1));
+        assert_eq!(amount_out, U256::from(1));
+        assert_eq!(
+            sqrt_q,
+            U256::from_str("417332158212080721273783715441581").unwrap()
+        );
+
+        // entire input amount taken as fee
+        let (sqrt_q, amount_in, amount_out, fee_amount) = compute_swap_step(
+            U256::from(2413),
+            U256::from_str("79887613182836312").unwrap(),
+            1985041575832132834610021537970,
+            I256::from_dec_str("10").unwrap(),
+            1872,
+        )
+        .unwrap();
+
+        assert_eq!(amount_in, U256::ZERO);
+        assert_eq!(fee_amount, U256::from(10));
+        assert_eq!(amount_out, U256::ZERO);
+        assert_eq!(sqrt_q, U256::from(2413));
+    }
+}
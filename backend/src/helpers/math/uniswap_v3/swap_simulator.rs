@@ -0,0 +1,409 @@
+use std::collections::HashMap;
+
+use alloy::primitives::{I256, U256};
+use serde::Serialize;
+
+use crate::helpers::math::uniswap_v3::{
+    U256_1,
+    error::UniswapV3MathError,
+    liquidity_math::add_delta,
+    swap_math::compute_swap_step,
+    tick_bitmap::{next_initialized_tick_within_one_word, position},
+    tick_math::{
+        MAX_SQRT_RATIO, MAX_TICK, MIN_SQRT_RATIO, MIN_TICK, get_sqrt_ratio_at_tick,
+        get_tick_at_sqrt_ratio,
+    },
+};
+
+/// State of a pool at a given block, enough to replay a swap off-chain.
+/// Only the bitmap words in `tick_bitmap` are known, a swap that walks past them fails instead of guessing.
+#[derive(Debug, Clone)]
+pub struct PoolSnapshot {
+    pub sqrt_price_x96: U256,
+    pub tick: i32,
+    pub liquidity: u128,
+    /// Pool fee in hundredths of a bip (3000 = 0.3%)
+    pub fee_pips: u32,
+    pub tick_spacing: i32,
+    pub tick_bitmap: HashMap<i16, U256>,
+    /// Liquidity net of every initialized tick inside the known bitmap words
+    pub liquidity_net: HashMap<i32, i128>,
+}
+
+impl PoolSnapshot {
+    /// Take a position out of the snapshot, as when it gets burned before a swap.
+    /// Ticks outside of the known bitmap words are left untouched since the simulation can not reach them.
+    pub fn remove_position(
+        &mut self,
+        tick_lower: i32,
+        tick_upper: i32,
+        liquidity: u128,
+    ) -> Result<(), UniswapV3MathError> {
+        if liquidity == 0 {
+            return Ok(());
+        }
+
+        let liquidity_delta = liquidity as i128;
+
+        if let Some(liquidity_net) = self.liquidity_net.get_mut(&tick_lower) {
+            *liquidity_net -= liquidity_delta;
+        }
+        if let Some(liquidity_net) = self.liquidity_net.get_mut(&tick_upper) {
+            *liquidity_net += liquidity_delta;
+        }
+
+        if self.tick >= tick_lower && self.tick < tick_upper {
+            self.liquidity = add_delta(self.liquidity, -liquidity_delta)?;
+        }
+
+        Ok(())
+    }
+
+    /// Furthest price a swap can reach without leaving the bitmap words around the current tick, `None` when the
+    /// known words go up to the min/max tick of the pool
+    pub fn known_sqrt_price_limit(
+        &self,
+        zero_for_one: bool,
+    ) -> Result<Option<U256>, UniswapV3MathError> {
+        if self.tick_spacing <= 0 {
+            return Err(UniswapV3MathError::TickSpacingError);
+        }
+
+        let mut compressed = self.tick / self.tick_spacing;
+        if self.tick < 0 && self.tick % self.tick_spacing != 0 {
+            compressed -= 1;
+        }
+
+        let (mut word_pos, _) = position(compressed);
+        let step: i16 = if zero_for_one { -1 } else { 1 };
+
+        while self.tick_bitmap.contains_key(&(word_pos + step)) {
+            word_pos += step;
+        }
+
+        let edge_compressed = if zero_for_one {
+            word_pos as i32 * 256
+        } else {
+            word_pos as i32 * 256 + 255
+        };
+        let edge_tick = edge_compressed * self.tick_spacing;
+
+        if edge_tick <= MIN_TICK || edge_tick >= MAX_TICK {
+            return Ok(None);
+        }
+
+        Ok(Some(get_sqrt_ratio_at_tick(edge_tick)?))
+    }
+}
+
+/// Result of a simulated swap
+#[derive(Debug, Clone, Serialize)]
+pub struct SwapSimulation {
+    /// Amount of the input token paid by the swapper, fees included
+    pub amount_in: U256,
+    pub amount_out: U256,
+    /// Part of `amount_in` that went to the LPs
+    pub fee_amount: U256,
+    pub sqrt_price_x96_after: U256,
+    pub tick_after: i32,
+    pub liquidity_after: u128,
+    pub ticks_crossed: u32,
+}
+
+/// Replays `UniswapV3Pool.swap` on a snapshot, walking across the initialized ticks. Returns the amounts and the
+/// state of the pool after the swap.
+///
+/// - zero_for_one: The direction of the swap, true for token0 to token1, false for token1 to token0
+/// - amount_specified: The amount of the swap, which implicitly configures the swap as exact input (positive), or exact output (negative)
+/// - sqrt_price_limit_x96: The price the swap can not go past, defaults to the min/max price of the pool
+pub fn simulate_swap(
+    snapshot: &PoolSnapshot,
+    zero_for_one: bool,
+    amount_specified: I256,
+    sqrt_price_limit_x96: Option<U256>,
+) -> Result<SwapSimulation, UniswapV3MathError> {
+    let sqrt_price_limit_x96 = sqrt_price_limit_x96.unwrap_or(if zero_for_one {
+        MIN_SQRT_RATIO + U256_1
+    } else {
+        MAX_SQRT_RATIO - U256_1
+    });
+
+    let limit_is_valid = if zero_for_one {
+        sqrt_price_limit_x96 < snapshot.sqrt_price_x96 && sqrt_price_limit_x96 > MIN_SQRT_RATIO
+    } else {
+        sqrt_price_limit_x96 > snapshot.sqrt_price_x96 && sqrt_price_limit_x96 < MAX_SQRT_RATIO
+    };
+
+    if !limit_is_valid {
+        return Err(UniswapV3MathError::SqrtPriceLimit);
+    }
+
+    let exact_input = amount_specified > I256::ZERO;
+
+    let mut amount_specified_remaining = amount_specified;
+    let mut sqrt_price_x96 = snapshot.sqrt_price_x96;
+    let mut tick = snapshot.tick;
+    let mut liquidity = snapshot.liquidity;
+
+    let mut amount_in = U256::ZERO;
+    let mut amount_out = U256::ZERO;
+    let mut fee_amount = U256::ZERO;
+    let mut ticks_crossed = 0;
+
+    // continue swapping as long as we haven't used the entire input/output and haven't reached the price limit
+    while !amount_specified_remaining.is_zero() && sqrt_price_x96 != sqrt_price_limit_x96 {
+        let sqrt_price_start_x96 = sqrt_price_x96;
+
+        let (mut tick_next, initialized) = next_initialized_tick_within_one_word(
+            &snapshot.tick_bitmap,
+            tick,
+            snapshot.tick_spacing,
+            zero_for_one,
+        )?;
+
+        // ensure that we do not overshoot the min/max tick, as the tick bitmap is not aware of these bounds
+        tick_next = tick_next.clamp(MIN_TICK, MAX_TICK);
+
+        let sqrt_price_next_x96 = get_sqrt_ratio_at_tick(tick_next)?;
+
+        let sqrt_price_target_x96 = if zero_for_one {
+            sqrt_price_next_x96.max(sqrt_price_limit_x96)
+        } else {
+            sqrt_price_next_x96.min(sqrt_price_limit_x96)
+        };
+
+        let (sqrt_price_after_step_x96, step_amount_in, step_amount_out, step_fee_amount) =
+            compute_swap_step(
+                sqrt_price_x96,
+                sqrt_price_target_x96,
+                liquidity,
+                amount_specified_remaining,
+                snapshot.fee_pips,
+            )?;
+
+        sqrt_price_x96 = sqrt_price_after_step_x96;
+
+        if exact_input {
+            amount_specified_remaining -= I256::from_raw(step_amount_in + step_fee_amount);
+        } else {
+            amount_specified_remaining += I256::from_raw(step_amount_out);
+        }
+
+        amount_in += step_amount_in + step_fee_amount;
+        amount_out += step_amount_out;
+        fee_amount += step_fee_amount;
+
+        if sqrt_price_x96 == sqrt_price_next_x96 {
+            // the price reached the next tick, if it is initialized the active liquidity changes
+            if initialized {
+                let liquidity_net = *snapshot
+                    .liquidity_net
+                    .get(&tick_next)
+                    .ok_or(UniswapV3MathError::TickLiquidityMissing(tick_next))?;
+
+                // when moving leftward, liquidity net is interpreted as the opposite sign
+                let liquidity_net = if zero_for_one {
+                    -liquidity_net
+                } else {
+                    liquidity_net
+                };
+
+                liquidity = add_delta(liquidity, liquidity_net)?;
+                ticks_crossed += 1;
+            }
+
+            tick = if zero_for_one {
+                tick_next - 1
+            } else {
+                tick_next
+            };
+        } else if sqrt_price_x96 != sqrt_price_start_x96 {
+            // recompute unless we're on a lower tick boundary (i.e. already transitioned ticks), and haven't moved
+            tick = get_tick_at_sqrt_ratio(sqrt_price_x96)?;
+        }
+    }
+
+    Ok(SwapSimulation {
+        amount_in,
+        amount_out,
+        fee_amount,
+        sqrt_price_x96_after: sqrt_price_x96,
+        tick_after: tick,
+        liquidity_after: liquidity,
+        ticks_crossed,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::helpers::math::uniswap_v3::sqrt_price_math::Q96;
+
+    const LIQUIDITY: u128 = 1_000_000_000_000_000_000;
+
+    // Pool at tick 0 with a wide position on [-600, 600] and a narrow one on [-120, 120]
+    fn snapshot() -> PoolSnapshot {
+        let tick_spacing = 60;
+        let liquidity_net: HashMap<i32, i128> = [
+            (-600, LIQUIDITY as i128),
+            (-120, LIQUIDITY as i128),
+            (120, -(LIQUIDITY as i128)),
+            (600, -(LIQUIDITY as i128)),
+        ]
+        .into_iter()
+        .collect();
+
+        let mut tick_bitmap: HashMap<i16, U256> = (-2..2).map(|word| (word, U256::ZERO)).collect();
+        for tick in liquidity_net.keys() {
+            let (word_pos, bit_pos) = position(tick / tick_spacing);
+            *tick_bitmap.get_mut(&word_pos).unwrap() |= U256_1 << bit_pos;
+        }
+
+        PoolSnapshot {
+            sqrt_price_x96: Q96,
+            tick: 0,
+            liquidity: 2 * LIQUIDITY,
+            fee_pips: 3000,
+            tick_spacing,
+            tick_bitmap,
+            liquidity_net,
+        }
+    }
+
+    #[test]
+    fn test_swap_within_current_range() {
+        let snapshot = snapshot();
+        let amount = I256::from_dec_str("1000000000000000").unwrap();
+
+        let result = simulate_swap(&snapshot, true, amount, None).unwrap();
+
+        // a single step on the current liquidity
+        let (sqrt_price, step_in, step_out, step_fee) = compute_swap_step(
+            snapshot.sqrt_price_x96,
+            get_sqrt_ratio_at_tick(-120).unwrap(),
+            snapshot.liquidity,
+            amount,
+            snapshot.fee_pips,
+        )
+        .unwrap();
+
+        assert_eq!(result.amount_in, amount.into_raw());
+        assert_eq!(result.amount_in, step_in + step_fee);
+        assert_eq!(result.amount_out, step_out);
+        assert_eq!(result.fee_amount, step_fee);
+        assert_eq!(result.sqrt_price_x96_after, sqrt_price);
+        assert_eq!(result.ticks_crossed, 0);
+        assert_eq!(result.liquidity_after, snapshot.liquidity);
+        assert!(result.tick_after < 0 && result.tick_after > -120);
+    }
+
+    #[test]
+    fn test_swap_crosses_initialized_ticks() {
+        let snapshot = snapshot();
+
+        // zero for one, enough to leave the narrow position
+        let result = simulate_swap(
+            &snapshot,
+            true,
+            I256::from_dec_str("20000000000000000").unwrap(),
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(result.ticks_crossed, 1);
+        assert_eq!(result.liquidity_after, LIQUIDITY);
+        assert!(result.tick_after < -120 && result.tick_after > -600);
+
+        // one for zero, exact output
+        let amount_out = U256::from(20_000_000_000_000_000u128);
+        let result = simulate_swap(&snapshot, false, -I256::from_raw(amount_out), None).unwrap();
+
+        assert_eq!(result.amount_out, amount_out);
+        assert!(result.amount_in > amount_out);
+        assert_eq!(result.ticks_crossed, 1);
+        assert_eq!(result.liquidity_after, LIQUIDITY);
+        assert!(result.tick_after >= 120 && result.tick_after < 600);
+    }
+
+    #[test]
+    fn test_swap_stops_at_price_limit() {
+        let snapshot = snapshot();
+        let limit = get_sqrt_ratio_at_tick(-60).unwrap();
+
+        let result = simulate_swap(
+            &snapshot,
+            true,
+            I256::from_dec_str("20000000000000000").unwrap(),
+            Some(limit),
+        )
+        .unwrap();
+
+        assert_eq!(result.sqrt_price_x96_after, limit);
+        assert_eq!(result.tick_after, -60);
+        assert_eq!(result.ticks_crossed, 0);
+        assert!(result.amount_in < U256::from(20_000_000_000_000_000u128));
+
+        // limit on the wrong side of the price
+        assert!(simulate_swap(&snapshot, false, I256::ONE, Some(limit)).is_err());
+    }
+
+    #[test]
+    fn test_remove_position() {
+        let mut snapshot = snapshot();
+        snapshot.remove_position(-120, 120, LIQUIDITY).unwrap();
+
+        assert_eq!(snapshot.liquidity, LIQUIDITY);
+
+        // no liquidity left to cross at -120
+        let result = simulate_swap(
+            &snapshot,
+            true,
+            I256::from_dec_str("20000000000000000").unwrap(),
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(result.liquidity_after, LIQUIDITY);
+    }
+
+    #[test]
+    fn test_known_sqrt_price_limit() {
+        let snapshot = snapshot();
+
+        // Words -2 to 1 are known, 256 tick spacings each
+        let limit = snapshot.known_sqrt_price_limit(true).unwrap().unwrap();
+        assert_eq!(limit, get_sqrt_ratio_at_tick(-2 * 256 * 60).unwrap());
+
+        let limit = snapshot.known_sqrt_price_limit(false).unwrap().unwrap();
+        assert_eq!(limit, get_sqrt_ratio_at_tick((2 * 256 - 1) * 60).unwrap());
+
+        // A swap on the limit stays inside the known words
+        let limit_down = snapshot.known_sqrt_price_limit(true).unwrap().unwrap();
+        let result = simulate_swap(
+            &snapshot,
+            true,
+            I256::from_dec_str("1000000000000000000000").unwrap(),
+            Some(limit_down),
+        )
+        .unwrap();
+        assert_eq!(result.sqrt_price_x96_after, limit_down);
+    }
+
+    #[test]
+    fn test_swap_outside_snapshot_words() {
+        let snapshot = snapshot();
+
+        // drains all the liquidity and walks to the unknown bitmap words
+        let result = simulate_swap(
+            &snapshot,
+            true,
+            I256::from_dec_str("1000000000000000000000").unwrap(),
+            None,
+        );
+
+        assert_eq!(
+            result.err().unwrap().to_string(),
+            "Tick bitmap word -3 is not part of the pool snapshot"
+        );
+    }
+}
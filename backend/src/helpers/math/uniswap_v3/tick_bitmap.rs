@@ -0,0 +1,201 @@
+use std::collections::HashMap;
+
+use alloy::primitives::U256;
+
+use crate::helpers::math::uniswap_v3::{
+    U256_1,
+    bit_math::{least_significant_bit, most_significant_bit},
+    error::UniswapV3MathError,
+};
+
+/// Computes the position in the mapping where the initialized bit for a tick lives. Returns (int16 wordPos, uint8 bitPos)
+pub fn position(tick: i32) -> (i16, u8) {
+    ((tick >> 8) as i16, (tick % 256) as u8)
+}
+
+/// Returns the next initialized tick contained in the same word (or adjacent word) as the tick that is either
+/// to the left (less than or equal to) or right (greater than) of the given tick. Returns (int24 next, bool initialized)
+///
+/// - tick_bitmap: The words of the pool tick bitmap, a missing word is an error as its content is unknown
+/// - tick: The starting tick
+/// - tick_spacing: The spacing between usable ticks
+/// - lte: Whether to search for the next initialized tick to the left (less than or equal to the starting tick)
+pub fn next_initialized_tick_within_one_word(
+    tick_bitmap: &HashMap<i16, U256>,
+    tick: i32,
+    tick_spacing: i32,
+    lte: bool,
+) -> Result<(i32, bool), UniswapV3MathError> {
+    if tick_spacing <= 0 {
+        return Err(UniswapV3MathError::TickSpacingError);
+    }
+
+    let mut compressed = tick / tick_spacing;
+    if tick < 0 && tick % tick_spacing != 0 {
+        compressed -= 1;
+    }
+
+    if lte {
+        let (word_pos, bit_pos) = position(compressed);
+        let word = *tick_bitmap
+            .get(&word_pos)
+            .ok_or(UniswapV3MathError::TickBitmapWordMissing(word_pos))?;
+
+        // all the 1s at or to the right of the current bit_pos
+        let mask = (U256_1 << bit_pos) - U256_1 + (U256_1 << bit_pos);
+        let masked = word & mask;
+
+        let initialized = !masked.is_zero();
+
+        let next = if initialized {
+            (compressed - (bit_pos - most_significant_bit(masked)?) as i32) * tick_spacing
+        } else {
+            (compressed - bit_pos as i32) * tick_spacing
+        };
+
+        Ok((next, initialized))
+    } else {
+        let (word_pos, bit_pos) = position(compressed + 1);
+        let word = *tick_bitmap
+            .get(&word_pos)
+            .ok_or(UniswapV3MathError::TickBitmapWordMissing(word_pos))?;
+
+        // all the 1s at or to the left of the bit_pos
+        let mask = !((U256_1 << bit_pos) - U256_1);
+        let masked = word & mask;
+
+        let initialized = !masked.is_zero();
+
+        let next = if initialized {
+            (compressed + 1 + (least_significant_bit(masked)? - bit_pos) as i32) * tick_spacing
+        } else {
+            (compressed + 1 + (u8::MAX - bit_pos) as i32) * tick_spacing
+        };
+
+        Ok((next, initialized))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Bitmap with the given ticks initialized (tick spacing 1), words -5 to 4 are part of the snapshot
+    fn init_bitmap(ticks: &[i32]) -> HashMap<i16, U256> {
+        let mut bitmap: HashMap<i16, U256> = (-5..5).map(|word| (word, U256::ZERO)).collect();
+
+        for tick in ticks {
+            let (word_pos, bit_pos) = position(*tick);
+            let word = bitmap.entry(word_pos).or_insert(U256::ZERO);
+            *word |= U256_1 << bit_pos;
+        }
+
+        bitmap
+    }
+
+    #[test]
+    fn test_position() {
+        assert_eq!(position(0), (0, 0));
+        assert_eq!(position(255), (0, 255));
+        assert_eq!(position(256), (1, 0));
+        assert_eq!(position(-1), (-1, 255));
+        assert_eq!(position(-256), (-1, 0));
+        assert_eq!(position(-257), (-2, 255));
+    }
+
+    #[test]
+    fn test_next_initialized_tick_within_one_word_lte_false() {
+        let bitmap = init_bitmap(&[-200, -55, -4, 70, 78, 84, 139, 240, 535]);
+
+        // returns tick to right if at initialized tick
+        assert_eq!(
+            next_initialized_tick_within_one_word(&bitmap, 78, 1, false).unwrap(),
+            (84, true)
+        );
+        // returns the tick directly to the right
+        assert_eq!(
+            next_initialized_tick_within_one_word(&bitmap, 77, 1, false).unwrap(),
+            (78, true)
+        );
+        assert_eq!(
+            next_initialized_tick_within_one_word(&bitmap, -56, 1, false).unwrap(),
+            (-55, true)
+        );
+        // returns the next words initialized tick if on the right boundary
+        assert_eq!(
+            next_initialized_tick_within_one_word(&bitmap, 255, 1, false).unwrap(),
+            (511, false)
+        );
+        assert_eq!(
+            next_initialized_tick_within_one_word(&bitmap, -257, 1, false).unwrap(),
+            (-200, true)
+        );
+        // does not exceed boundary
+        assert_eq!(
+            next_initialized_tick_within_one_word(&bitmap, 508, 1, false).unwrap(),
+            (511, false)
+        );
+        // skips half word
+        assert_eq!(
+            next_initialized_tick_within_one_word(&bitmap, 383, 1, false).unwrap(),
+            (511, false)
+        );
+    }
+
+    #[test]
+    fn test_next_initialized_tick_within_one_word_lte_true() {
+        let bitmap = init_bitmap(&[-200, -55, -4, 70, 78, 84, 139, 240, 535]);
+
+        // returns same tick if initialized
+        assert_eq!(
+            next_initialized_tick_within_one_word(&bitmap, 78, 1, true).unwrap(),
+            (78, true)
+        );
+        // returns tick directly to the left of input tick if not initialized
+        assert_eq!(
+            next_initialized_tick_within_one_word(&bitmap, 79, 1, true).unwrap(),
+            (78, true)
+        );
+        // will not exceed the word boundary
+        assert_eq!(
+            next_initialized_tick_within_one_word(&bitmap, 258, 1, true).unwrap(),
+            (256, false)
+        );
+        // at the word boundary
+        assert_eq!(
+            next_initialized_tick_within_one_word(&bitmap, 256, 1, true).unwrap(),
+            (256, false)
+        );
+        // word boundary less 1 (next initialized tick in next word)
+        assert_eq!(
+            next_initialized_tick_within_one_word(&bitmap, 72, 1, true).unwrap(),
+            (70, true)
+        );
+        // word boundary
+        assert_eq!(
+            next_initialized_tick_within_one_word(&bitmap, -257, 1, true).unwrap(),
+            (-512, false)
+        );
+        // entire empty word
+        assert_eq!(
+            next_initialized_tick_within_one_word(&bitmap, 1023, 1, true).unwrap(),
+            (768, false)
+        );
+        // returns the word boundary when no tick is initialized on its left
+        assert_eq!(
+            next_initialized_tick_within_one_word(&bitmap, 329, 1, true).unwrap(),
+            (256, false)
+        );
+    }
+
+    #[test]
+    fn test_missing_word() {
+        let bitmap = init_bitmap(&[]);
+
+        let result = next_initialized_tick_within_one_word(&bitmap, 256 * 10, 1, true);
+        assert_eq!(
+            result.err().unwrap().to_string(),
+            "Tick bitmap word 10 is not part of the pool snapshot"
+        );
+    }
+}
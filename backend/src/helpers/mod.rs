@@ -1,3 +1,4 @@
+pub mod amount;
 pub mod evm;
 pub mod vault;
 pub mod math;
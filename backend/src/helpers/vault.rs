@@ -1,11 +1,7 @@
 use std::str::FromStr;
 
 use alloy::{
-    primitives::{
-        Address, U256,
-        aliases::I24,
-        utils::{format_units, parse_units},
-    },
+    primitives::{Address, U256, aliases::I24, utils::format_units},
     providers::{Provider, WalletProvider},
     rpc::types::TransactionReceipt,
     sol,
@@ -14,7 +10,8 @@ use color_eyre::eyre::Result;
 
 use crate::{
     config::{FEE_FACTOR, HBAR_EVM_ADDRESS},
-    helpers,
+    core::{self, tx_sender::TxSender},
+    helpers::{self, amount::TokenAmount},
     types::{Pool, Position, Token, VaultDetails, VaultTVL, VaultTokenBalances},
 };
 
@@ -143,10 +140,10 @@ where
         upper_tick,
         is_active,
         is_vault_tokens_associated,
-        position: Position::default(),
+        position: Position::empty(token0_decimals, token1_decimals),
         tvl: VaultTVL {
-            tvl0: 0.0,
-            tvl1: 0.0,
+            tvl0: TokenAmount::zero(token0_decimals),
+            tvl1: TokenAmount::zero(token1_decimals),
         },
     })
 }
@@ -173,6 +170,7 @@ where
 
 pub async fn deposit_tokens_to_vault<P>(
     provider: &P,
+    tx_sender: &TxSender,
     vault: &VaultDetails,
     deposit0: f64,
     deposit1: f64,
@@ -185,13 +183,11 @@ where
     let vault_contract = ManiXAIVault::new(vault_address, provider);
 
     // We use 18 decimals cause evm relay handles nativve hbar as 18 decimals
-    let custom_deposit_0: U256 = parse_units(deposit0.to_string().as_str(), 18)?.into();
-    let custom_deposit_1: U256 = parse_units(deposit1.to_string().as_str(), 18)?.into();
+    let custom_deposit_0 = TokenAmount::from_f64(deposit0, 18)?.raw;
+    let custom_deposit_1 = TokenAmount::from_f64(deposit1, 18)?.raw;
 
-    let deposit0: U256 =
-        parse_units(deposit0.to_string().as_str(), vault.pool.token0.decimals)?.into();
-    let deposit1: U256 =
-        parse_units(deposit1.to_string().as_str(), vault.pool.token1.decimals)?.into();
+    let deposit0 = TokenAmount::from_f64(deposit0, vault.pool.token0.decimals)?.raw;
+    let deposit1 = TokenAmount::from_f64(deposit1, vault.pool.token1.decimals)?.raw;
 
     let token0_contract = ERC20::new(
         Address::from_str(vault.pool.token0.address.as_str())?,
@@ -242,10 +238,11 @@ where
         if allownace0 < deposit0 {
             let approve_tx = token0_contract
                 .approve(vault_address, deposit0)
-                .send()
-                .await?;
+                .into_transaction_request();
 
-            let approve_receipt = approve_tx.get_receipt().await?;
+            let approve_receipt = tx_sender
+                .send(format!("approve deposit0 {}", vault.address), approve_tx)
+                .await?;
 
             let approve_tx_hash = approve_receipt.transaction_hash;
             let approve_status = approve_receipt.status();
@@ -299,10 +296,11 @@ where
         if allownace1 < deposit1 {
             let approve_tx = token1_contract
                 .approve(vault_address, deposit1)
-                .send()
-                .await?;
+                .into_transaction_request();
 
-            let approve_receipt = approve_tx.get_receipt().await?;
+            let approve_receipt = tx_sender
+                .send(format!("approve deposit1 {}", vault.address), approve_tx)
+                .await?;
 
             let approve_tx_hash = approve_receipt.transaction_hash;
             let approve_status = approve_receipt.status();
@@ -318,14 +316,19 @@ where
         }
     }
 
-    let deposit_tx = vault_contract
+    let deposit_call = vault_contract
         .deposit(deposit0, deposit1, provider.default_signer_address())
-        .value(value_to_send)
-        .gas(15_000_000)
-        .send()
-        .await?;
+        .value(value_to_send);
+
+    let gas_estimate = deposit_call.estimate_gas().await?;
 
-    let deposit_receipt = deposit_tx.get_receipt().await?;
+    let deposit_tx = deposit_call
+        .gas(core::tx_costs::with_gas_margin(gas_estimate))
+        .into_transaction_request();
+
+    let deposit_receipt = tx_sender
+        .send(format!("deposit {}", vault.address), deposit_tx)
+        .await?;
 
     let deposit_tx_hash = deposit_receipt.transaction_hash;
     let deposit_status = deposit_receipt.status();
@@ -342,6 +345,7 @@ where
 
 pub async fn mint_liquidity_from_amount0<P>(
     provider: &P,
+    tx_sender: &TxSender,
     vault: &VaultDetails,
     lower_tick: i32,
     upper_tick: i32,
@@ -354,11 +358,7 @@ where
 
     let vault_contract = ManiXAIVault::new(vault_address, provider);
 
-    let amount0_desired: U256 = parse_units(
-        amount0_desired.to_string().as_str(),
-        vault.pool.token0.decimals,
-    )?
-    .into();
+    let amount0_desired = TokenAmount::from_f64(amount0_desired, vault.pool.token0.decimals)?.raw;
 
     let amount1_desired = helpers::math::estimate_amount1_given_amount0(
         vault.pool.sqrt_price_x96,
@@ -373,17 +373,27 @@ where
     let upper_tick = I24::from_str(upper_tick.to_string().as_str())?;
     let lower_tick = I24::from_str(lower_tick.to_string().as_str())?;
 
-    // hbar conversation rate
-    let value_to_send: U256 = parse_units("0.5", 18)?.into();
+    // HBAR for the SaucerSwap mint fee, at the network exchange rate
+    let hbar_value = core::tx_costs::get_hbar_value_to_send(
+        provider,
+        vault.address.as_str(),
+        vault.pool.address.as_str(),
+    )
+    .await?;
 
-    let mint_tx = vault_contract
+    let mint_call = vault_contract
         .mintLiquidity(amount0_desired, amount1_desired, lower_tick, upper_tick)
-        .gas(15_000_000)
-        .value(value_to_send)
-        .send()
-        .await?;
+        .value(hbar_value.value_to_send_weibars);
+
+    let gas_estimate = mint_call.estimate_gas().await?;
 
-    let mint_receipt = mint_tx.get_receipt().await?;
+    let mint_tx = mint_call
+        .gas(core::tx_costs::with_gas_margin(gas_estimate))
+        .into_transaction_request();
+
+    let mint_receipt = tx_sender
+        .send(format!("mint {}", vault.address), mint_tx)
+        .await?;
 
     let mint_tx_hash = mint_receipt.transaction_hash;
     let mint_status = mint_receipt.status();
@@ -401,7 +411,11 @@ where
     Ok(())
 }
 
-pub async fn burn_all_liquidity<P>(provider: &P, vault: &VaultDetails) -> Result<()>
+pub async fn burn_all_liquidity<P>(
+    provider: &P,
+    tx_sender: &TxSender,
+    vault: &VaultDetails,
+) -> Result<()>
 where
     P: Provider + WalletProvider,
 {
@@ -414,9 +428,11 @@ where
 
     let vault_contract = ManiXAIVault::new(vault_address, provider);
 
-    let burn_tx = vault_contract.burnAllLiquidity().send().await?;
+    let burn_tx = vault_contract.burnAllLiquidity().into_transaction_request();
 
-    let burn_receipt = burn_tx.get_receipt().await?;
+    let burn_receipt = tx_sender
+        .send(format!("burn {}", vault.address), burn_tx)
+        .await?;
 
     let burn_tx_hash = burn_receipt.transaction_hash;
     let burn_status = burn_receipt.status();
@@ -431,7 +447,11 @@ where
     Ok(())
 }
 
-pub async fn associate_vault_tokens<P>(provider: &P, vault: &VaultDetails) -> Result<()>
+pub async fn associate_vault_tokens<P>(
+    provider: &P,
+    tx_sender: &TxSender,
+    vault: &VaultDetails,
+) -> Result<()>
 where
     P: Provider + WalletProvider,
 {
@@ -439,9 +459,13 @@ where
 
     let vault_contract = ManiXAIVault::new(vault_address, provider);
 
-    let associate_tx = vault_contract.associateVaultTokens().send().await?;
+    let associate_tx = vault_contract
+        .associateVaultTokens()
+        .into_transaction_request();
 
-    let associate_receipt = associate_tx.get_receipt().await?;
+    let associate_receipt = tx_sender
+        .send(format!("associate tokens {}", vault.address), associate_tx)
+        .await?;
 
     let associate_tx_hash = associate_receipt.transaction_hash;
     let associate_status = associate_receipt.status();
@@ -470,32 +494,26 @@ where
     let token0 = &vault.pool.token0;
     let token1 = &vault.pool.token1;
 
-    let token0_balance: f64;
-    let token1_balance: f64;
-    let token0_balance_u256: U256;
-    let token1_balance_u256: U256;
+    let token0_balance: TokenAmount;
+    let token1_balance: TokenAmount;
 
     let balance = ERC20::new(Address::from_str(token0.address.as_str())?, provider)
         .balanceOf(vault_address)
         .call()
         .await?;
 
-    token0_balance = format_units(balance, token0.decimals)?.parse()?;
-    token0_balance_u256 = balance;
+    token0_balance = TokenAmount::new(balance, token0.decimals);
 
     let balance = ERC20::new(Address::from_str(token1.address.as_str())?, provider)
         .balanceOf(vault_address)
         .call()
         .await?;
 
-    token1_balance = format_units(balance, token1.decimals)?.parse()?;
-    token1_balance_u256 = balance;
+    token1_balance = TokenAmount::new(balance, token1.decimals);
 
     Ok(VaultTokenBalances {
         token0_balance,
         token1_balance,
-        token0_balance_u256,
-        token1_balance_u256,
     })
 }
 
@@ -523,6 +541,7 @@ where
 
 pub async fn withdraw_shares_from_vault<P>(
     evm_provider: &P,
+    tx_sender: &TxSender,
     vault_details: &VaultDetails,
     shares_u256: U256,
     to_address: Address,
@@ -536,10 +555,11 @@ where
 
     let withdraw_tx = vault_contract
         .withdraw(shares_u256, to_address)
-        .send()
-        .await?;
+        .into_transaction_request();
 
-    let withdraw_receipt = withdraw_tx.get_receipt().await?;
+    let withdraw_receipt = tx_sender
+        .send(format!("withdraw {}", vault_details.address), withdraw_tx)
+        .await?;
 
     let withdraw_tx_hash = withdraw_receipt.transaction_hash;
     let withdraw_status = withdraw_receipt.status();
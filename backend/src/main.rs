@@ -1,4 +1,5 @@
 mod api;
+mod backtest;
 mod config;
 mod core;
 mod helpers;
@@ -48,20 +49,25 @@ async fn main() -> std::io::Result<()> {
     info!("Config: {:?}", *CONFIG);
 
     // Init all vaults and store them in the app state
-    init_all_vaults(&app_state).await.unwrap();
+    if let Err(e) = init_all_vaults(&app_state).await {
+        error!("Failed to init vaults: {:?}", e);
+        return Err(std::io::Error::other(e.to_string()));
+    }
 
-    let all_vaults_addresses = &CONFIG.toml_config.vaults;
+    let all_vaults_configs = &CONFIG.toml_config.vaults;
 
     //  Open a tokio thread for each vault stored in the app state, and start the liquidity management loop
-    for address in all_vaults_addresses {
+    for vault_config in all_vaults_configs {
         let cloned_app_state = app_state.clone();
         tokio::spawn(async move {
-            match core::vault_spawn::start_vault_liq_management(address, cloned_app_state).await {
+            match core::vault_spawn::start_vault_liq_management(vault_config, cloned_app_state)
+                .await
+            {
                 Ok(_) => {}
                 Err(e) => {
                     error!(
                         "Failed on start vault liq management for address: {:?}",
-                        address
+                        vault_config.address
                     );
                     error!("Error: {:?}", e);
                 }
@@ -88,6 +94,11 @@ async fn main() -> std::io::Result<()> {
             .service(api::handle_get_all_vaults)
             .service(api::handle_admin_associate_vault_tokens)
             .service(api::handle_chat)
+            .service(api::handle_backtest)
+            .service(api::handle_get_rebalance_plans)
+            .service(api::handle_get_ai_decisions)
+            .service(api::handle_get_rebalance_policy)
+            .service(api::handle_get_rpc_health)
             .split_for_parts();
 
         app.service(SwaggerUi::new("/swagger-ui/{_:.*}").url("/api-docs/openapi.json", app_api))
@@ -101,18 +112,15 @@ async fn main() -> std::io::Result<()> {
 mod test {
 
     use alloy::{
-        primitives::{
-            Address,
-            aliases::I24,
-            utils::{format_units, parse_units},
-        },
+        primitives::{Address, aliases::I24},
         providers::WalletProvider,
     };
     use std::str::FromStr;
 
     use crate::{
         config::{CHAIN_ID, IS_NEW_CONTRACT, RPC_URL},
-        helpers::vault::ManiXAIVault,
+        core::tx_sender::TxSender,
+        helpers::{amount::TokenAmount, vault::ManiXAIVault},
         types::PrepareSwapArgs,
     };
 
@@ -134,17 +142,26 @@ mod test {
         let evm_provider = ProviderBuilder::new()
             .with_chain_id(CONFIG.toml_config.chain_id)
             .wallet(evm_signer)
-            .connect(&CONFIG.toml_config.rpc_url)
+            .connect(&CONFIG.toml_config.rpc_urls[0])
             .await?;
 
-        let contract_address = CONFIG.toml_config.vaults[0].as_str();
+        let tx_sender = TxSender::new(evm_provider.clone());
+
+        let contract_address = CONFIG.toml_config.vaults[0].address.as_str();
         // let contract_address = "0xA5B1102CF31e71b59544BD648EE1fC293B043bE0";
 
         let vault_details = core::vault::get_vault_details(&evm_provider, contract_address).await?;
 
         println!("{:#?}", vault_details);
 
-        helpers::vault::deposit_tokens_to_vault(&evm_provider, &vault_details, 10.0, 0.0).await?;
+        helpers::vault::deposit_tokens_to_vault(
+            &evm_provider,
+            &tx_sender,
+            &vault_details,
+            10.0,
+            0.0,
+        )
+        .await?;
 
         Ok(())
     }
@@ -165,6 +182,8 @@ mod test {
             .connect(RPC_URL)
             .await?;
 
+        let tx_sender = TxSender::new(evm_provider.clone());
+
         let contract_address = config::MANIXAI_CONTRACT_ADDRESS;
 
         let mut vault_details =
@@ -174,10 +193,17 @@ mod test {
 
         if IS_NEW_CONTRACT {
             println!("Associating vault tokens...");
-            helpers::vault::associate_vault_tokens(&evm_provider, &mut vault_details).await?;
-            println!("Associated vault tokens.");
-            helpers::vault::deposit_tokens_to_vault(&evm_provider, &vault_details, 2.0, 1000.0)
+            helpers::vault::associate_vault_tokens(&evm_provider, &tx_sender, &vault_details)
                 .await?;
+            println!("Associated vault tokens.");
+            helpers::vault::deposit_tokens_to_vault(
+                &evm_provider,
+                &tx_sender,
+                &vault_details,
+                2.0,
+                1000.0,
+            )
+            .await?;
         }
 
         // Start strategy thta will get me the best tick range to put liq on
@@ -191,6 +217,7 @@ mod test {
         println!("Trying to mint liquidity with Recommended tick range...");
         helpers::vault::mint_liquidity_from_amount0(
             &evm_provider,
+            &tx_sender,
             &vault_details,
             tick_range.lower_tick,
             tick_range.upper_tick,
@@ -205,7 +232,7 @@ mod test {
         println!("Updated Vault : {:#?}", vault_details);
 
         // try to burn all the liqudity of the vault
-        helpers::vault::burn_all_liquidity(&evm_provider, &vault_details).await?;
+        helpers::vault::burn_all_liquidity(&evm_provider, &tx_sender, &vault_details).await?;
 
         // Update the vault details
         helpers::vault::update_vault_current_position_data(&evm_provider, &mut vault_details)
@@ -231,21 +258,30 @@ mod test {
             .connect(RPC_URL)
             .await?;
 
+        let tx_sender = TxSender::new(evm_provider.clone());
+
         let contract_address = config::MANIXAI_CONTRACT_ADDRESS;
 
-        let mut vault_details =
+        let vault_details =
             helpers::vault::get_vault_details(&evm_provider, contract_address).await?;
 
         println!("{:#?}", vault_details);
 
         if IS_NEW_CONTRACT {
             println!("Associating vault tokens...");
-            helpers::vault::associate_vault_tokens(&evm_provider, &mut vault_details).await?;
+            helpers::vault::associate_vault_tokens(&evm_provider, &tx_sender, &vault_details)
+                .await?;
             println!("Associated vault tokens.");
 
             // Deposit only native hbar tokens
-            helpers::vault::deposit_tokens_to_vault(&evm_provider, &vault_details, 4.0, 0.0)
-                .await?;
+            helpers::vault::deposit_tokens_to_vault(
+                &evm_provider,
+                &tx_sender,
+                &vault_details,
+                4.0,
+                0.0,
+            )
+            .await?;
         }
 
         // Start strategy thta will get me the best tick range to put liq on
@@ -276,21 +312,19 @@ mod test {
 
         let balance_token0 = vault_token_balances.token0_balance;
         let balance_token1 = vault_token_balances.token1_balance;
-        let balance_token0_u256 = vault_token_balances.token0_balance_u256;
-        let balance_token1_u256 = vault_token_balances.token1_balance_u256;
 
-        println!("Balance token0: {:#?}", balance_token0);
-        println!("Balance token1: {:#?}", balance_token1);
+        println!("Balance token0: {}", balance_token0);
+        println!("Balance token1: {}", balance_token1);
 
         // Another way to get the needed amoutns to swp
-        let balance1_token0_equivalent = balance_token1 * vault_details.pool.price0;
+        let balance1_token0_equivalent = balance_token1.to_f64() * vault_details.pool.price0;
 
         println!(
             "Balance1_token0_equivalent: {:#?}",
             balance1_token0_equivalent
         );
 
-        let is_balance0_larger = balance_token0 > balance1_token0_equivalent;
+        let is_balance0_larger = balance_token0.to_f64() > balance1_token0_equivalent;
 
         println!("Is balance0 larger: {:#?}", is_balance0_larger);
 
@@ -298,13 +332,13 @@ mod test {
             helpers::math::uniswap_v3::liquidity_math::get_liquidity_for_amount0(
                 lower_tick_sqrt_price,
                 upper_tick_sqrt_price,
-                vault_token_balances.token0_balance_u256,
+                balance_token0.raw,
             )?
         } else {
             helpers::math::uniswap_v3::liquidity_math::get_liquidity_for_amount1(
                 lower_tick_sqrt_price,
                 upper_tick_sqrt_price,
-                vault_token_balances.token1_balance_u256,
+                balance_token1.raw,
             )?
         };
 
@@ -318,105 +352,68 @@ mod test {
                 liquidity,
             )?;
 
-        println!("Amount0: {:#?}", amount0);
-        println!("Token0 U256 Balance: {:#?}", balance_token0_u256);
-        println!("Amount1: {:#?}", amount1);
-        println!("Token1 U256 Balance: {:#?}", balance_token1_u256);
+        let desired_amount0 = TokenAmount::new(amount0, vault_details.pool.token0.decimals);
+        let desired_amount1 = TokenAmount::new(amount1, vault_details.pool.token1.decimals);
 
-        let desired_amount0: f64 =
-            format_units(amount0, vault_details.pool.token0.decimals)?.parse()?;
-        let desired_amount1: f64 =
-            format_units(amount1, vault_details.pool.token1.decimals)?.parse()?;
-
-        println!("Desired Amount0: {:#?}", desired_amount0);
-        println!("Desired Amount1: {:#?}", desired_amount1);
+        println!("Desired Amount0: {}", desired_amount0);
+        println!("Desired Amount1: {}", desired_amount1);
 
         // Prepare if need to swap token0 for token1 or teh reverse and how much to swap
-        let exess0 = balance_token0 - desired_amount0;
-        let exess1 = balance_token1 - desired_amount1;
-
-        println!("Exess0: {:#?}", exess0);
-        println!("Exess1: {:#?}", exess1);
-
-        // Prepare the swap args for the negative value betwen the exess0 and exess1
-        let swap_arg: PrepareSwapArgs;
+        let abs_diff =
+            |a: TokenAmount, b: TokenAmount| TokenAmount::new(a.raw.abs_diff(b.raw), a.decimals);
 
-        if exess0 < 0.0 {
-            let exact_amount_out = exess0.abs();
-            let parsed_exact_amount_out: U256 = parse_units(
-                exact_amount_out.to_string().as_str(),
-                vault_details.pool.token0.decimals,
-            )?
-            .into();
-            let max_amount_in: U256 = parse_units(
-                exess1.abs().to_string().as_str(),
-                vault_details.pool.token1.decimals,
-            )?
-            .into();
-
-            swap_arg = PrepareSwapArgs {
-                exact_amount_out,
-                parsed_exact_amount_out,
+        // Swap to get the token the vault lacks
+        let swap_arg = if balance_token0.raw < desired_amount0.raw {
+            PrepareSwapArgs {
+                exact_amount_out: abs_diff(desired_amount0, balance_token0),
                 token_in: vault_details.pool.token1,
                 token_out: vault_details.pool.token0,
                 is_swap_0_to_1: false,
-                max_amount_in,
-                formatted_max_amount_in: exess1.abs(),
-            };
-        } else if exess1 < 0.0 {
-            let exact_amount_out = exess1.abs();
-            let parsed_exact_amount_out: U256 = parse_units(
-                exact_amount_out.to_string().as_str(),
-                vault_details.pool.token1.decimals,
-            )?
-            .into();
-            let max_amount_in: U256 = parse_units(
-                exess0.abs().to_string().as_str(),
-                vault_details.pool.token0.decimals,
-            )?
-            .into();
-
-            swap_arg = PrepareSwapArgs {
-                exact_amount_out,
-                parsed_exact_amount_out,
+                max_amount_in: abs_diff(balance_token1, desired_amount1),
+            }
+        } else if balance_token1.raw < desired_amount1.raw {
+            PrepareSwapArgs {
+                exact_amount_out: abs_diff(desired_amount1, balance_token1),
                 token_in: vault_details.pool.token0,
                 token_out: vault_details.pool.token1,
                 is_swap_0_to_1: true,
-                max_amount_in,
-                formatted_max_amount_in: exess0.abs(),
-            };
+                max_amount_in: abs_diff(balance_token0, desired_amount0),
+            }
         } else {
             // No need to swap
-            swap_arg = PrepareSwapArgs {
-                exact_amount_out: 0.0,
-                parsed_exact_amount_out: U256::ZERO,
+            PrepareSwapArgs {
+                exact_amount_out: TokenAmount::zero(desired_amount1.decimals),
                 token_in: vault_details.pool.token0,
                 token_out: vault_details.pool.token1,
                 is_swap_0_to_1: true,
-                max_amount_in: U256::ZERO,
-                formatted_max_amount_in: 0.0,
-            };
-        }
+                max_amount_in: TokenAmount::zero(desired_amount0.decimals),
+            }
+        };
 
         println!("Swap arg: {:#?}", swap_arg);
 
+        let hbar_value = core::tx_costs::get_hbar_value_to_send(
+            &evm_provider,
+            contract_address,
+            vault_details.pool.address.as_str(),
+        )
+        .await?;
+
         // call rebelance on the vault with new tick range and swap direction and amount
         let vault_contract = ManiXAIVault::new(Address::from_str(contract_address)?, evm_provider);
 
         let upper_tick = I24::from_str(upper_tick.to_string().as_str())?;
         let lower_tick = I24::from_str(lower_tick.to_string().as_str())?;
 
-        let value_to_send: U256 = parse_units("1", 18)?.into();
-
         let rebalnce_reciept = vault_contract
             .rebalance(
                 lower_tick,
                 upper_tick,
-                swap_arg.parsed_exact_amount_out,
-                swap_arg.max_amount_in,
+                swap_arg.exact_amount_out.raw,
+                swap_arg.max_amount_in.raw,
                 swap_arg.is_swap_0_to_1,
             )
-            .value(value_to_send)
+            .value(hbar_value.value_to_send_weibars)
             .send()
             .await?
             .get_receipt()
@@ -443,23 +440,31 @@ mod test {
             .connect(RPC_URL)
             .await?;
 
+        let tx_sender = TxSender::new(evm_provider.clone());
+
         let contract_address = config::MANIXAI_CONTRACT_ADDRESS;
 
-        let mut vault_details =
+        let vault_details =
             helpers::vault::get_vault_details(&evm_provider, contract_address).await?;
 
         println!("{:#?}", vault_details);
 
         if IS_NEW_CONTRACT {
             println!("Associating vault tokens...");
-            helpers::vault::associate_vault_tokens(&evm_provider, &mut vault_details).await?;
+            helpers::vault::associate_vault_tokens(&evm_provider, &tx_sender, &vault_details)
+                .await?;
             println!("Associated vault tokens.");
         }
 
         // Deposit tokens to vault
-        let deposit_reciept =
-            helpers::vault::deposit_tokens_to_vault(&evm_provider, &vault_details, 1.0, 0.0)
-                .await?;
+        let deposit_reciept = helpers::vault::deposit_tokens_to_vault(
+            &evm_provider,
+            &tx_sender,
+            &vault_details,
+            1.0,
+            0.0,
+        )
+        .await?;
 
         // let deposit_logs = deposit_reciept.logs();
 
@@ -485,6 +490,7 @@ mod test {
         // Withdraw shares from vault
         helpers::vault::withdraw_shares_from_vault(
             &evm_provider,
+            &tx_sender,
             &vault_details,
             vault_shares_u256,
             evm_provider.default_signer_address(),
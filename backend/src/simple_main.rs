@@ -57,17 +57,17 @@ async fn get_vaults() -> Result<HttpResponse> {
             "upper_tick": 887220,
             "is_active": true,
             "tvl": {
-                "tvl0": 1000.0,
-                "tvl1": 5000.0
+                "tvl0": "1000.000000000000000000",
+                "tvl1": "5000.000000"
             },
             "position": {
                 "tick_lower": -887220,
                 "tick_upper": 887220,
                 "liquidity": 1000000,
-                "amount0": 500.0,
-                "amount1": 2500.0,
-                "fees0": 10.0,
-                "fees1": 50.0
+                "amount0": "500.000000000000000000",
+                "amount1": "2500.000000",
+                "fees0": "10.000000000000000000",
+                "fees1": "50.000000"
             }
         }
     ]);
@@ -1,31 +1,67 @@
+use std::{collections::VecDeque, sync::Arc};
+
 use chrono::{DateTime, Utc};
 use once_cell::sync::Lazy;
-use rig::{agent::Agent, providers::gemini::completion::CompletionModel};
+use rig::agent::Agent;
 
 use crate::{
-    core::init::{init_ai_agent, init_evm_provider},
+    config::CONFIG,
+    core::{
+        ai_journal::{AI_JOURNAL_DIR, AiJournal},
+        init::{init_ai_agent, init_evm_provider},
+        llm::LlmModel,
+        plan::RebalancePlan,
+        rebalance_policy::PolicyState,
+        rpc_pool::RpcPool,
+        tx_sender::TxSender,
+    },
+    strategies::registry::StrategyRegistry,
     types::{EvmProvider, VaultDetails},
 };
 
 pub struct AppState {
+    /// RPC endpoints of the config and their health, shared with the provider transport
+    pub rpc_pool: Arc<RpcPool>,
     pub evm_provider: EvmProvider,
+    /// Every transaction of the operator wallet goes through it, see `core::tx_sender`
+    pub tx_sender: TxSender,
     pub all_vaults: dashmap::DashMap<String, VaultDetails>,
-    pub ai_agent: Agent<CompletionModel>,
+    pub ai_agent: Agent<LlmModel>,
+    pub strategies: StrategyRegistry,
+    /// Last rebalance plans of each vault, keyed by lowercase vault address
+    pub rebalance_plans: dashmap::DashMap<String, VecDeque<RebalancePlan>>,
+    /// Calls of the AI strategy and the action taken on them, persisted in `ai_journal/`
+    pub ai_journal: AiJournal,
+    /// State of the rebalance policy of each vault, keyed by lowercase vault address
+    pub rebalance_policies: dashmap::DashMap<String, PolicyState>,
 }
 
 impl AppState {
     pub async fn new() -> Self {
         // Init evm provider
-        let evm_provider = init_evm_provider().await.unwrap();
+        let rpc_pool = Arc::new(
+            RpcPool::new(&CONFIG.toml_config.rpc_urls).expect("Failed to init rpc endpoints"),
+        );
+        let evm_provider = init_evm_provider(rpc_pool.clone()).await.unwrap();
+        let tx_sender = TxSender::new(evm_provider.clone());
         // Initialize the AI agent
         let ai_agent = init_ai_agent()
             .await
             .expect("Failed to initialize AI agent");
+        let ai_journal = AiJournal::load(AI_JOURNAL_DIR).expect("Failed to load the AI journal");
+        let strategies = StrategyRegistry::with_builtin_strategies(&CONFIG.toml_config)
+            .expect("Failed to register the strategies");
 
         Self {
             ai_agent,
+            rpc_pool,
             evm_provider,
+            tx_sender,
             all_vaults: dashmap::DashMap::new(),
+            strategies,
+            rebalance_plans: dashmap::DashMap::new(),
+            ai_journal,
+            rebalance_policies: dashmap::DashMap::new(),
         }
     }
 }
@@ -1,33 +1,161 @@
 /*
-   This Strategy is not supported on testnet cause it relies on coingecko pools data which is not available for testnet pools
+   This Strategy needs the pool candles. CoinGecko only indexes mainnet pools, testnet vaults and unindexed pools
+   need `ohlcv_source = "swap_logs"` to build them from the pool swaps (see `core::swap_ohlcv`)
 */
 
 use crate::{
-    core, helpers,
-    types::{AiStrategyResponse, CoingeckoOhlcvRes, TickRange, VaultDetails},
+    config::{AI_MAX_RANGE_DISTANCE_BPS, AI_PROMPT_MAX_CANDLES, AI_STRATEGY_MAX_RETRIES},
+    core::{
+        self,
+        ai_journal::{AiDecisionAction, AiDecisionRecord, AiPromptInputs},
+        llm::LlmModel,
+    },
+    helpers,
+    strategies::{Strategy, StrategyContext, StrategyDecision},
+    types::{AiStrategyResponse, LlmConfig, LlmProvider, OhlcvEntry, TickRange, VaultDetails},
 };
-use color_eyre::eyre::Result;
+use async_trait::async_trait;
+use color_eyre::eyre::{Result, eyre};
 
-use rig::{client::ProviderClient, completion::Prompt, providers::gemini};
-use serde_json::json;
-use tokio::time::Instant;
-use tracing::{debug, info};
+use rig::{
+    agent::{Agent, AgentBuilder},
+    completion::{Chat, Message},
+};
+use serde_json::{Value, json};
+use tokio::time::{Duration, Instant};
+use tracing::{debug, info, warn};
 
-pub async fn get_best_range(_vault: &VaultDetails) -> Result<TickRange> {
-    Ok(TickRange {
-        curent_tick: 0,
-        lower_tick: 0,
-        upper_tick: 0,
-    })
+pub struct AiStrategy {
+    llm_config: LlmConfig,
 }
 
-pub async fn start(vault_details: &VaultDetails) -> Result<AiStrategyResponse> {
-    debug!("Start AI strategy...");
-    // 1. Fetch historical OHLCV price data from coingecko
-    let pool_gecko_data: CoingeckoOhlcvRes =
-        core::coingecko::get_pool_ohlcv_data(&vault_details.pool.address, vault_details).await?;
+impl AiStrategy {
+    pub fn new(llm_config: LlmConfig) -> Self {
+        Self { llm_config }
+    }
+}
+
+#[async_trait]
+impl Strategy for AiStrategy {
+    fn name(&self) -> &'static str {
+        "ai"
+    }
+
+    fn calls_model(&self) -> bool {
+        true
+    }
+
+    async fn decide(&self, ctx: &StrategyContext<'_>) -> Result<StrategyDecision> {
+        let vault = ctx.vault;
+        let mut call = AiCall::default();
+        let result = start(&self.llm_config, vault, ctx.ohlcv, &mut call).await;
+
+        // Every call is journaled, the invalid ones too
+        let journal_id = ctx.journal.map(|journal| {
+            let record = AiDecisionRecord {
+                id: format!(
+                    "{}-{}",
+                    vault.address.to_lowercase(),
+                    chrono::Utc::now().timestamp_micros()
+                ),
+                vault_address: vault.address.clone(),
+                created_at: chrono::Utc::now().to_rfc3339(),
+                model: self.llm_config.model_name(),
+                inputs: call.inputs,
+                responses: call.responses,
+                decision: result.as_ref().ok().cloned(),
+                error: result.as_ref().err().map(|e| format!("{:#}", e)),
+                latency_ms: call.latency.as_millis() as u64,
+                action: if result.is_ok() {
+                    AiDecisionAction::Pending
+                } else {
+                    AiDecisionAction::Failed
+                },
+            };
+            let id = record.id.clone();
+            journal.record(record);
+
+            id
+        });
+
+        let ai_strategy_result = result?;
+
+        let rebalance_required = ai_strategy_result.rebalance_required;
+        let confidence = ai_strategy_result.confidence_score;
+        let rationale = ai_strategy_result.analysis.clone();
+
+        // When no rebalance is required the AI returns 0.0 prices, so we keep the current range
+        let tick_range = if rebalance_required {
+            get_tick_range_from_ai_response(ai_strategy_result, vault).await
+        } else {
+            Ok(TickRange {
+                curent_tick: vault.pool.current_tick,
+                lower_tick: vault.lower_tick,
+                upper_tick: vault.upper_tick,
+            })
+        };
+
+        let tick_range = match tick_range {
+            Ok(tick_range) => tick_range,
+            Err(e) => {
+                if let (Some(journal), Some(id)) = (ctx.journal, &journal_id) {
+                    journal.set_action(
+                        &vault.address,
+                        id,
+                        AiDecisionAction::Error {
+                            error: e.to_string(),
+                        },
+                    );
+                }
+                return Err(e);
+            }
+        };
+
+        Ok(StrategyDecision {
+            strategy: self.name().to_string(),
+            tick_range,
+            rebalance_required,
+            rationale,
+            confidence: Some(confidence),
+            journal_id,
+            fallbacks: vec![],
+        })
+    }
+}
 
-    debug!("Fetched historical OHLCV price data from coingecko");
+/// What a model call was given and answered, for the AI journal
+#[derive(Default)]
+pub struct AiCall {
+    pub inputs: AiPromptInputs,
+    /// Raw answers, the re-prompted ones included
+    pub responses: Vec<String>,
+    pub latency: Duration,
+}
+
+pub async fn start(
+    llm_config: &LlmConfig,
+    vault_details: &VaultDetails,
+    ohlcv: Option<&[OhlcvEntry]>,
+    call: &mut AiCall,
+) -> Result<AiStrategyResponse> {
+    debug!("Start AI strategy...");
+    // 1. Fetch historical OHLCV price data from coingecko, unless the caller already provided it
+    let pool_gecko_data: Vec<OhlcvEntry> = match ohlcv {
+        Some(ohlcv) => ohlcv.to_vec(),
+        None => {
+            let pool_gecko_data =
+                core::coingecko::get_pool_ohlcv_data(&vault_details.pool.address, vault_details)
+                    .await?;
+
+            debug!("Fetched historical OHLCV price data from coingecko");
+
+            pool_gecko_data.data.attributes.ohlcv_list
+        }
+    };
+
+    if pool_gecko_data.is_empty() {
+        return Err(eyre!("No candles of the pool to decide on"));
+    }
 
     let current_price = vault_details.pool.price1;
     // Convert tick lower and upper to price
@@ -76,10 +204,20 @@ You are an advanced strategist for an automated liquidity manager. Your job is t
   * `current_price`: $\[value]
   Note: If the vault does not have a position, the upper price and lower price will be passed both as 0
 
+* **Market Features** (JSON), computed from the pool candles (CoinGecko or built from the pool swaps). Windows are in candles:
+
+  * `interval_seconds`: length of a candle
+  * `realized_volatility`: standard deviation of the close to close returns over each window, in %
+  * `atr_pct`: average true range, in % of the last close
+  * `trend`: price change per candle along the fitted trend line in %, and how well it fits (`r_squared`, 0 to 1)
+  * `close_percentiles`: where the recent closes were
+  * `volume_profile`: share of the recent volume traded in each price bucket
+  * `time_in_range_pct`: % of the recent closes inside the current range, null without a position
+
 * **Historical Price Data (OHLCV)**:
 
-  * Format: `[timestamp, open, high, low, close, volume]`
-  * Source: CoinGecko or similar
+  * Format: `[timestamp, open, high, low, close, volume]`, oldest first
+  * Consecutive candles are merged so only the overall price path is given
 
 * **Vault Info**:
   
@@ -137,22 +275,64 @@ You are an advanced strategist for an automated liquidity manager. Your job is t
 ```
 "#;
 
-    let ai_client = gemini::Client::from_env();
-
-    let ai_agent = ai_client
-        .agent("gemini-2.0-flash")
+    let mut agent_builder = AgentBuilder::new(core::llm::completion_model(llm_config)?)
         .preamble(ai_instruction_prompt)
-        .temperature(0.0)
-        .additional_params(json!({
-            "thinkingConfig": {
-                "thinkingBudget": 0,
-            }
-        }))
-        .build();
+        .temperature(0.0);
+
+    // Constrain the answer to the response schema where the provider supports it
+    match llm_config.provider {
+        LlmProvider::Gemini => {
+            agent_builder = agent_builder.additional_params(json!({
+                // Thinking is turned off to keep the answers fast
+                "thinkingConfig": {
+                    "thinkingBudget": 0,
+                },
+                "responseMimeType": "application/json",
+                "responseSchema": gemini_schema(ai_response_schema()),
+            }));
+        }
+        LlmProvider::OpenaiCompatible => {
+            agent_builder = agent_builder.additional_params(json!({
+                "response_format": {
+                    "type": "json_schema",
+                    "json_schema": {
+                        "name": "ai_strategy_response",
+                        "strict": true,
+                        "schema": ai_response_schema(),
+                    }
+                }
+            }));
+        }
+        LlmProvider::Mock => {}
+    }
+
+    let ai_agent = agent_builder.build();
 
     let vault_tick_spacing = vault_details.pool.tick_spacing;
     let vault_fee = vault_details.pool.fee;
 
+    let current_range = vault_details
+        .is_active
+        .then_some((current_lower_price, current_upper_price));
+    let features = helpers::math::features::market_features(&pool_gecko_data, current_range);
+    let features_json = serde_json::to_string(&features)?;
+
+    let candles = helpers::math::features::downsample(&pool_gecko_data, AI_PROMPT_MAX_CANDLES);
+    let candles_count = candles.len();
+    let candles_json = serde_json::to_string(&candles)?;
+
+    call.inputs = AiPromptInputs {
+        current_price,
+        current_lower_price,
+        current_upper_price,
+        tick_spacing: vault_tick_spacing,
+        fee: vault_fee,
+        candles: pool_gecko_data.len(),
+        first_candle_timestamp: pool_gecko_data.first().map(|candle| candle.0),
+        last_candle_timestamp: pool_gecko_data.last().map(|candle| candle.0),
+        features,
+    };
+
     let prompt = format!(
         r#"
 Recommend the best pool liquidity range for the following pool by returning the json object following format provided on the instruction:
@@ -163,27 +343,26 @@ Recommend the best pool liquidity range for the following pool by returning the
     **Vault Info**
       - Tick spacing: {vault_tick_spacing}
       - Pool fee tier: {vault_fee}
-    **Historical Price Data (OHLCV) of the last 6 months From Coingecko**
-    {pool_gecko_data:#?}  
+    **Market Features**
+    {features_json}
+    **Historical Price Data (OHLCV), merged down to {candles_count} candles**
+    {candles_json}
         "#,
     );
 
     debug!("Starting waiting  for AI strategy response...");
     let start_time = Instant::now();
-    let response = ai_agent.prompt(prompt).await?;
+    let ai_strategy_response =
+        prompt_ai_strategy(&ai_agent, prompt, vault_details, &mut call.responses).await;
     let elapsed = start_time.elapsed();
+    call.latency = elapsed;
+    let ai_strategy_response = ai_strategy_response?;
 
     debug!(
         "AI strategy response received in {} seconds",
         elapsed.as_secs_f64()
     );
 
-    debug!("response: {:?}", response);
-
-    let json_response = extract_json_from_markdown(&response);
-
-    let ai_strategy_response: AiStrategyResponse = serde_json::from_str(&json_response)?;
-
     debug!("ai_strategy_response: {:?}", ai_strategy_response);
 
     info!(
@@ -198,6 +377,179 @@ Recommend the best pool liquidity range for the following pool by returning the
     Ok(ai_strategy_response)
 }
 
+/// Prompt the agent until it answers a valid response. Invalid answers are sent back with the validation error,
+/// at most `AI_STRATEGY_MAX_RETRIES` times.
+async fn prompt_ai_strategy(
+    ai_agent: &Agent<LlmModel>,
+    prompt: String,
+    vault_details: &VaultDetails,
+    responses: &mut Vec<String>,
+) -> Result<AiStrategyResponse> {
+    let mut chat_history: Vec<Message> = vec![];
+    let mut message = prompt;
+    let mut retry = 0;
+
+    loop {
+        let response = ai_agent
+            .chat(message.as_str(), chat_history.clone())
+            .await?;
+
+        debug!("response: {:?}", response);
+        responses.push(response.clone());
+
+        let error = match serde_json::from_str::<AiStrategyResponse>(&extract_json_from_markdown(
+            &response,
+        )) {
+            Ok(ai_strategy_response) => {
+                match validate_ai_response(&ai_strategy_response, vault_details) {
+                    Ok(()) => return Ok(ai_strategy_response),
+                    Err(e) => e,
+                }
+            }
+            Err(e) => eyre!("The answer is not a valid JSON response object: {}", e),
+        };
+
+        if retry >= AI_STRATEGY_MAX_RETRIES {
+            return Err(error.wrap_err(format!(
+                "AI strategy answer still invalid after {} retries",
+                retry
+            )));
+        }
+
+        warn!(
+            "Invalid AI strategy answer, retry {}/{}: {}",
+            retry + 1,
+            AI_STRATEGY_MAX_RETRIES,
+            error
+        );
+
+        chat_history.push(Message::user(message));
+        chat_history.push(Message::assistant(response));
+        message = format!(
+            "Your answer is invalid: {}. Answer again with only the JSON object of the output format, fixing this error.",
+            error
+        );
+        retry += 1;
+    }
+}
+
+/// Check an answer against the pool state: a rebalance needs a valid range of the pool close to its price
+pub fn validate_ai_response(
+    response: &AiStrategyResponse,
+    vault_details: &VaultDetails,
+) -> Result<()> {
+    let lower_price = response.new_price_range.lower_price;
+    let upper_price = response.new_price_range.upper_price;
+
+    if !(0.0..=1.0).contains(&response.confidence_score) {
+        return Err(eyre!(
+            "confidence_score must be between 0 and 1, got {}",
+            response.confidence_score
+        ));
+    }
+
+    if !response.rebalance_required {
+        return Ok(());
+    }
+
+    if !(lower_price > 0.0 && lower_price.is_finite() && upper_price.is_finite()) {
+        return Err(eyre!(
+            "a rebalance needs positive prices, got lower_price {} and upper_price {}",
+            lower_price,
+            upper_price
+        ));
+    }
+
+    if lower_price >= upper_price {
+        return Err(eyre!(
+            "lower_price {} must be below upper_price {}",
+            lower_price,
+            upper_price
+        ));
+    }
+
+    let current_price = vault_details.pool.price1;
+    let distance = if current_price < lower_price {
+        (lower_price - current_price) / current_price
+    } else if current_price > upper_price {
+        (current_price - upper_price) / current_price
+    } else {
+        0.0
+    };
+
+    if distance * 10_000.0 > AI_MAX_RANGE_DISTANCE_BPS as f64 {
+        return Err(eyre!(
+            "the range {} - {} is {:.2}% away from the current price {}, at most {:.2}% is allowed",
+            lower_price,
+            upper_price,
+            distance * 100.0,
+            current_price,
+            AI_MAX_RANGE_DISTANCE_BPS as f64 / 100.0
+        ));
+    }
+
+    helpers::math::price_range_to_ticks(
+        lower_price,
+        upper_price,
+        vault_details.pool.token0.decimals,
+        vault_details.pool.token1.decimals,
+        vault_details.pool.tick_spacing,
+    )
+    .map_err(|e| {
+        eyre!(
+            "the range {} - {} can not be used on the pool: {}",
+            lower_price,
+            upper_price,
+            e
+        )
+    })?;
+
+    Ok(())
+}
+
+/// JSON schema of [`AiStrategyResponse`], sent to the providers supporting structured outputs
+fn ai_response_schema() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "rebalance_required": { "type": "boolean" },
+            "new_price_range": {
+                "type": "object",
+                "properties": {
+                    "lower_price": { "type": "number" },
+                    "upper_price": { "type": "number" },
+                },
+                "required": ["lower_price", "upper_price"],
+                "additionalProperties": false,
+            },
+            "analysis": { "type": "string" },
+            "market_outlook": { "type": "string" },
+            "confidence_score": { "type": "number" },
+        },
+        "required": ["rebalance_required", "new_price_range", "analysis", "market_outlook", "confidence_score"],
+        "additionalProperties": false,
+    })
+}
+
+/// Gemini schemas are an OpenAPI subset: upper case types and no `additionalProperties`
+fn gemini_schema(mut schema: Value) -> Value {
+    if let Some(object) = schema.as_object_mut() {
+        object.remove("additionalProperties");
+
+        if let Some(Value::String(schema_type)) = object.get_mut("type") {
+            *schema_type = schema_type.to_uppercase();
+        }
+
+        if let Some(Value::Object(properties)) = object.get_mut("properties") {
+            for property in properties.values_mut() {
+                *property = gemini_schema(property.take());
+            }
+        }
+    }
+
+    schema
+}
+
 pub fn extract_json_from_markdown(md: &str) -> String {
     let json_block = md.replace("```json", "").replace("```", "");
     json_block.trim().to_string()
@@ -210,14 +562,8 @@ pub async fn get_tick_range_from_ai_response(
     let lower_price = response.new_price_range.lower_price;
     let upper_price = response.new_price_range.upper_price;
 
-    let lower_tick = helpers::math::convert_price_to_tick(
+    let (lower_tick, upper_tick) = helpers::math::price_range_to_ticks(
         lower_price,
-        vault_details.pool.token0.decimals,
-        vault_details.pool.token1.decimals,
-        vault_details.pool.tick_spacing,
-    )?;
-
-    let upper_tick = helpers::math::convert_price_to_tick(
         upper_price,
         vault_details.pool.token0.decimals,
         vault_details.pool.token1.decimals,
@@ -230,3 +576,99 @@ pub async fn get_tick_range_from_ai_response(
         upper_tick,
     })
 }
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use rig::client::completion::CompletionModelHandle;
+
+    use super::*;
+    use crate::{
+        core::llm::MockModel,
+        types::{PriceRange, test_vault},
+    };
+
+    fn vault(price1: f64) -> VaultDetails {
+        test_vault(0, 60, price1)
+    }
+
+    fn response(
+        rebalance_required: bool,
+        lower_price: f64,
+        upper_price: f64,
+    ) -> AiStrategyResponse {
+        AiStrategyResponse {
+            rebalance_required,
+            new_price_range: PriceRange {
+                lower_price,
+                upper_price,
+            },
+            analysis: "analysis".to_string(),
+            market_outlook: "outlook".to_string(),
+            confidence_score: 0.8,
+        }
+    }
+
+    #[test]
+    fn test_invalid_ranges_are_rejected() {
+        let vault = vault(0.2);
+
+        assert!(validate_ai_response(&response(true, 0.19, 0.21), &vault).is_ok());
+        assert!(validate_ai_response(&response(false, 0.0, 0.0), &vault).is_ok());
+        // Slightly above the price is fine, far away is not
+        assert!(validate_ai_response(&response(true, 0.205, 0.22), &vault).is_ok());
+        assert!(validate_ai_response(&response(true, 0.3, 0.4), &vault).is_err());
+
+        assert!(validate_ai_response(&response(true, 0.0, 0.0), &vault).is_err());
+        assert!(validate_ai_response(&response(true, 0.21, 0.19), &vault).is_err());
+        assert!(validate_ai_response(&response(true, 0.2, f64::INFINITY), &vault).is_err());
+
+        let mut overconfident = response(false, 0.0, 0.0);
+        overconfident.confidence_score = 81.0;
+        assert!(validate_ai_response(&overconfident, &vault).is_err());
+    }
+
+    fn mock_agent(replies: &[&str]) -> Agent<LlmModel> {
+        let model = MockModel::new(replies.iter().map(|reply| reply.to_string()).collect());
+
+        AgentBuilder::new(CompletionModelHandle {
+            inner: Arc::new(model),
+        })
+        .build()
+    }
+
+    #[tokio::test]
+    async fn test_invalid_answers_are_retried() {
+        let valid = r#"{"rebalance_required": true, "new_price_range": {"lower_price": 0.19, "upper_price": 0.21}, "analysis": "a", "market_outlook": "b", "confidence_score": 0.5}"#;
+        let inverted = r#"{"rebalance_required": true, "new_price_range": {"lower_price": 0.21, "upper_price": 0.19}, "analysis": "a", "market_outlook": "b", "confidence_score": 0.5}"#;
+
+        let agent = mock_agent(&["Sure! Here is the range: 0.19 - 0.21", inverted, valid]);
+        let mut responses = vec![];
+        let response =
+            prompt_ai_strategy(&agent, "prompt".to_string(), &vault(0.2), &mut responses)
+                .await
+                .unwrap();
+        assert_eq!(response.new_price_range.lower_price, 0.19);
+        assert_eq!(responses.len(), 3);
+
+        let agent = mock_agent(&[inverted]);
+        let error = prompt_ai_strategy(&agent, "prompt".to_string(), &vault(0.2), &mut vec![])
+            .await
+            .unwrap_err();
+        assert!(format!("{:?}", error).contains("must be below upper_price"));
+    }
+
+    #[test]
+    fn test_gemini_schema_is_an_openapi_subset() {
+        let schema = gemini_schema(ai_response_schema());
+
+        assert_eq!(schema["type"], "OBJECT");
+        assert_eq!(
+            schema["properties"]["new_price_range"]["properties"]["lower_price"]["type"],
+            "NUMBER"
+        );
+        assert!(schema.get("additionalProperties").is_none());
+        assert_eq!(schema["required"].as_array().unwrap().len(), 5);
+    }
+}
@@ -4,11 +4,36 @@
 
 use crate::{
     helpers,
+    strategies::{Strategy, StrategyContext, StrategyDecision},
     types::{TickRange, VaultDetails},
 };
+use async_trait::async_trait;
 use color_eyre::eyre::Result;
 use tracing::info;
 
+pub struct BasicStrategy;
+
+#[async_trait]
+impl Strategy for BasicStrategy {
+    fn name(&self) -> &'static str {
+        "basic"
+    }
+
+    async fn decide(&self, ctx: &StrategyContext<'_>) -> Result<StrategyDecision> {
+        let tick_range = get_best_range(ctx.vault).await?;
+
+        Ok(StrategyDecision {
+            strategy: self.name().to_string(),
+            tick_range,
+            rebalance_required: true,
+            rationale: "Fixed range of -1% / +1% around the current price".to_string(),
+            confidence: None,
+            journal_id: None,
+            fallbacks: vec![],
+        })
+    }
+}
+
 pub async fn get_best_range(vault: &VaultDetails) -> Result<TickRange> {
     let current_price = vault.pool.price1;
     let pool_tick_spacing = vault.pool.tick_spacing;
@@ -24,13 +49,9 @@ pub async fn get_best_range(vault: &VaultDetails) -> Result<TickRange> {
     let token0_decimals = vault.pool.token0.decimals;
     let token1_decimals = vault.pool.token1.decimals;
 
-    let lower_tick = helpers::math::convert_price_to_tick(
+    // The range is widened to the pool tick spacing, never narrowed
+    let (lower_tick, upper_tick) = helpers::math::price_range_to_ticks(
         low_price,
-        token0_decimals,
-        token1_decimals,
-        pool_tick_spacing,
-    )?;
-    let upper_tick = helpers::math::convert_price_to_tick(
         high_price,
         token0_decimals,
         token1_decimals,
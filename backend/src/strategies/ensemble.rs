@@ -0,0 +1,279 @@
+/*
+    Strategy combining the proposals of several strategies. A proposal is rejected when its range does not contain
+    the current price or its width is out of the `[ensemble]` bounds, and a proposal of a model is only followed when
+    its confidence reaches `min_confidence`. Otherwise the proposal of the deterministic strategy is used.
+*/
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use color_eyre::eyre::{Result, eyre};
+use tracing::{info, warn};
+
+use crate::{
+    core::ai_journal::AiDecisionAction,
+    strategies::{Strategy, StrategyContext, StrategyDecision, fallback::StrategyFallback},
+    types::{EnsembleConfig, TickRange, VaultDetails},
+};
+
+pub struct EnsembleStrategy {
+    config: EnsembleConfig,
+    /// Strategies of `config.strategies`, in order
+    strategies: Vec<Arc<dyn Strategy>>,
+}
+
+impl EnsembleStrategy {
+    pub fn new(config: EnsembleConfig, strategies: Vec<Arc<dyn Strategy>>) -> Self {
+        Self { config, strategies }
+    }
+}
+
+#[async_trait]
+impl Strategy for EnsembleStrategy {
+    fn name(&self) -> &'static str {
+        "ensemble"
+    }
+
+    fn calls_model(&self) -> bool {
+        self.strategies
+            .iter()
+            .any(|strategy| strategy.calls_model())
+    }
+
+    async fn decide(&self, ctx: &StrategyContext<'_>) -> Result<StrategyDecision> {
+        let vault = ctx.vault;
+
+        let mut proposals: Vec<StrategyDecision> = vec![];
+        let mut rejections: Vec<StrategyFallback> = vec![];
+
+        for strategy in &self.strategies {
+            match strategy.decide(ctx).await {
+                Ok(proposal) => proposals.push(proposal),
+                Err(e) => rejections.push(StrategyFallback {
+                    strategy: strategy.name().to_string(),
+                    error: format!("{:#}", e),
+                }),
+            }
+        }
+
+        let mut deterministic: Option<StrategyDecision> = None;
+        let mut chosen: Option<StrategyDecision> = None;
+
+        for proposal in proposals {
+            let is_deterministic = proposal.strategy == self.config.deterministic_strategy;
+
+            let check =
+                check_guardrails(&self.config, vault, &proposal.tick_range).and_then(|()| {
+                    match proposal.confidence {
+                        Some(confidence)
+                            if !is_deterministic && confidence < self.config.min_confidence =>
+                        {
+                            Err(eyre!(
+                                "confidence {} is below {}",
+                                confidence,
+                                self.config.min_confidence
+                            ))
+                        }
+                        _ => Ok(()),
+                    }
+                });
+
+            if let Err(e) = check {
+                warn!(
+                    "Ensemble rejected the proposal of {} for vault {}: {}",
+                    proposal.strategy, vault.address, e
+                );
+
+                if let (Some(journal), Some(journal_id)) = (ctx.journal, &proposal.journal_id) {
+                    journal.set_action(
+                        &vault.address,
+                        journal_id,
+                        AiDecisionAction::Rejected {
+                            reason: e.to_string(),
+                        },
+                    );
+                }
+
+                rejections.push(StrategyFallback {
+                    strategy: proposal.strategy,
+                    error: e.to_string(),
+                });
+                continue;
+            }
+
+            if is_deterministic {
+                deterministic = Some(proposal);
+            } else if chosen.is_none() {
+                chosen = Some(proposal);
+            }
+        }
+
+        let Some(mut decision) = chosen.or(deterministic) else {
+            return Err(eyre!(
+                "No proposal passed the guardrails of the ensemble: {:?}",
+                rejections
+            ));
+        };
+
+        info!(
+            "Ensemble picked the proposal of {} for vault {}",
+            decision.strategy, vault.address
+        );
+
+        decision.strategy = format!("{}/{}", self.name(), decision.strategy);
+        decision.fallbacks.extend(rejections);
+
+        Ok(decision)
+    }
+}
+
+/// Reject a range that does not contain the current tick or whose width is out of the bounds
+pub fn check_guardrails(
+    config: &EnsembleConfig,
+    vault: &VaultDetails,
+    range: &TickRange,
+) -> Result<()> {
+    let current_tick = vault.pool.current_tick;
+
+    if current_tick < range.lower_tick || current_tick >= range.upper_tick {
+        return Err(eyre!(
+            "range {} - {} does not contain the current tick {}",
+            range.lower_tick,
+            range.upper_tick,
+            current_tick
+        ));
+    }
+
+    let width_spacings = (range.upper_tick - range.lower_tick) / vault.pool.tick_spacing;
+
+    if width_spacings < config.min_width_spacings as i32
+        || width_spacings > config.max_width_spacings as i32
+    {
+        return Err(eyre!(
+            "range {} - {} is {} tick spacings wide, the width must be between {} and {}",
+            range.lower_tick,
+            range.upper_tick,
+            width_spacings,
+            config.min_width_spacings,
+            config.max_width_spacings
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::types::test_vault;
+
+    /// Strategy proposing a fixed range
+    struct Proposal {
+        name: &'static str,
+        lower_tick: i32,
+        upper_tick: i32,
+        confidence: Option<f64>,
+    }
+
+    #[async_trait]
+    impl Strategy for Proposal {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        async fn decide(&self, _ctx: &StrategyContext<'_>) -> Result<StrategyDecision> {
+            Ok(StrategyDecision {
+                strategy: self.name.to_string(),
+                tick_range: TickRange {
+                    curent_tick: 0,
+                    lower_tick: self.lower_tick,
+                    upper_tick: self.upper_tick,
+                },
+                rebalance_required: true,
+                rationale: self.name.to_string(),
+                confidence: self.confidence,
+                journal_id: None,
+                fallbacks: vec![],
+            })
+        }
+    }
+
+    async fn decide(ai: Proposal) -> Result<StrategyDecision> {
+        let vault = test_vault(5, 10, 1.0);
+        let ensemble = EnsembleStrategy::new(
+            EnsembleConfig::default(),
+            vec![
+                Arc::new(ai),
+                Arc::new(Proposal {
+                    name: "volatility",
+                    lower_tick: -100,
+                    upper_tick: 100,
+                    confidence: None,
+                }),
+            ],
+        );
+
+        ensemble
+            .decide(&StrategyContext {
+                vault: &vault,
+                ohlcv: Some(&[]),
+                journal: None,
+            })
+            .await
+    }
+
+    #[tokio::test]
+    async fn test_ensemble_follows_a_confident_valid_proposal() {
+        let decision = decide(Proposal {
+            name: "ai",
+            lower_tick: -50,
+            upper_tick: 50,
+            confidence: Some(0.9),
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(decision.strategy, "ensemble/ai");
+        assert_eq!(decision.tick_range.lower_tick, -50);
+        assert!(decision.fallbacks.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_ensemble_falls_back_to_the_deterministic_proposal() {
+        // Not confident enough
+        let decision = decide(Proposal {
+            name: "ai",
+            lower_tick: -50,
+            upper_tick: 50,
+            confidence: Some(0.2),
+        })
+        .await
+        .unwrap();
+        assert_eq!(decision.strategy, "ensemble/volatility");
+        assert!(decision.fallbacks[0].error.contains("confidence"));
+
+        // Confident but away from the current price
+        let decision = decide(Proposal {
+            name: "ai",
+            lower_tick: 100,
+            upper_tick: 200,
+            confidence: Some(0.9),
+        })
+        .await
+        .unwrap();
+        assert_eq!(decision.strategy, "ensemble/volatility");
+        assert!(decision.fallbacks[0].error.contains("current tick"));
+
+        // Narrower than the minimum width
+        let decision = decide(Proposal {
+            name: "ai",
+            lower_tick: 0,
+            upper_tick: 10,
+            confidence: Some(0.9),
+        })
+        .await
+        .unwrap();
+        assert_eq!(decision.strategy, "ensemble/volatility");
+        assert!(decision.fallbacks[0].error.contains("tick spacings wide"));
+    }
+}
@@ -0,0 +1,154 @@
+/*
+    Ordered strategies of a vault, e.g. ai -> volatility -> basic. When a strategy fails (model or market data API down,
+    not enough candles...) the next one decides, and the failures are recorded on the decision so the rebalance plans
+    show why the vault did not move with its main strategy.
+*/
+
+use std::sync::Arc;
+
+use color_eyre::eyre::{Result, eyre};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+use utoipa::ToSchema;
+
+use crate::strategies::{Strategy, StrategyContext, StrategyDecision};
+
+/// A strategy whose decision was not used: it failed, or the ensemble rejected it
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct StrategyFallback {
+    pub strategy: String,
+    pub error: String,
+}
+
+pub struct StrategyChain {
+    strategies: Vec<Arc<dyn Strategy>>,
+}
+
+impl StrategyChain {
+    pub fn new(strategies: Vec<Arc<dyn Strategy>>) -> Self {
+        Self { strategies }
+    }
+
+    /// Decision of the first strategy that does not fail, with the failures of the previous ones
+    pub async fn decide(&self, ctx: &StrategyContext<'_>) -> Result<StrategyDecision> {
+        let mut fallbacks: Vec<StrategyFallback> = vec![];
+
+        for strategy in &self.strategies {
+            match strategy.decide(ctx).await {
+                Ok(mut decision) => {
+                    if !fallbacks.is_empty() {
+                        warn!(
+                            "Strategy {} decided for vault {} after {} failed strategies",
+                            decision.strategy,
+                            ctx.vault.address,
+                            fallbacks.len()
+                        );
+                    }
+
+                    decision.fallbacks = fallbacks;
+                    return Ok(decision);
+                }
+                Err(e) => {
+                    warn!(
+                        "Strategy {} failed for vault {}: {:#}",
+                        strategy.name(),
+                        ctx.vault.address,
+                        e
+                    );
+
+                    fallbacks.push(StrategyFallback {
+                        strategy: strategy.name().to_string(),
+                        error: format!("{:#}", e),
+                    });
+                }
+            }
+        }
+
+        Err(eyre!(
+            "Every strategy failed for vault {}: {:?}",
+            ctx.vault.address,
+            fallbacks
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use async_trait::async_trait;
+
+    use super::*;
+    use crate::types::{TickRange, test_vault};
+
+    struct Failing(&'static str);
+
+    #[async_trait]
+    impl Strategy for Failing {
+        fn name(&self) -> &'static str {
+            self.0
+        }
+
+        async fn decide(&self, _ctx: &StrategyContext<'_>) -> Result<StrategyDecision> {
+            Err(eyre!("{} is down", self.0))
+        }
+    }
+
+    struct Fixed;
+
+    #[async_trait]
+    impl Strategy for Fixed {
+        fn name(&self) -> &'static str {
+            "fixed"
+        }
+
+        async fn decide(&self, _ctx: &StrategyContext<'_>) -> Result<StrategyDecision> {
+            Ok(StrategyDecision {
+                strategy: self.name().to_string(),
+                tick_range: TickRange {
+                    curent_tick: 0,
+                    lower_tick: -60,
+                    upper_tick: 60,
+                },
+                rebalance_required: true,
+                rationale: "Fixed range".to_string(),
+                confidence: None,
+                journal_id: None,
+                fallbacks: vec![],
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_chain_records_each_fallback() {
+        let vault = test_vault(0, 60, 1.0);
+        let ctx = StrategyContext {
+            vault: &vault,
+            ohlcv: Some(&[]),
+            journal: None,
+        };
+
+        let chain = StrategyChain::new(vec![
+            Arc::new(Failing("ai")),
+            Arc::new(Failing("volatility")),
+            Arc::new(Fixed),
+        ]);
+        let decision = chain.decide(&ctx).await.unwrap();
+
+        assert_eq!(decision.strategy, "fixed");
+        assert_eq!(
+            decision.fallbacks,
+            vec![
+                StrategyFallback {
+                    strategy: "ai".to_string(),
+                    error: "ai is down".to_string(),
+                },
+                StrategyFallback {
+                    strategy: "volatility".to_string(),
+                    error: "volatility is down".to_string(),
+                },
+            ]
+        );
+
+        let chain = StrategyChain::new(vec![Arc::new(Failing("ai"))]);
+        assert!(chain.decide(&ctx).await.is_err());
+    }
+}
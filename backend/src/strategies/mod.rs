@@ -1,2 +1,60 @@
 pub mod ai;
-pub mod basic;
\ No newline at end of file
+pub mod basic;
+pub mod ensemble;
+pub mod fallback;
+pub mod registry;
+pub mod volatility;
+
+use async_trait::async_trait;
+use color_eyre::eyre::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    core::ai_journal::AiJournal,
+    strategies::fallback::StrategyFallback,
+    types::{OhlcvEntry, TickRange, VaultDetails},
+};
+
+/// Inputs of a strategy run
+pub struct StrategyContext<'a> {
+    pub vault: &'a VaultDetails,
+    /// Historical candles known at decision time, oldest first. When `None` the strategy
+    /// fetches its market data itself (live mode), backtests always provide them.
+    pub ohlcv: Option<&'a [OhlcvEntry]>,
+    /// Where strategies calling a model record their calls, `None` in backtests
+    pub journal: Option<&'a AiJournal>,
+}
+
+/// Outcome of a strategy run: the range it wants the vault to sit on, whether it thinks the
+/// vault should move there now, and a human readable explanation of why.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StrategyDecision {
+    pub strategy: String,
+    pub tick_range: TickRange,
+    pub rebalance_required: bool,
+    pub rationale: String,
+    /// Confidence of a model in its decision, from 0 to 1. `None` for the deterministic strategies
+    #[serde(default)]
+    pub confidence: Option<f64>,
+    /// Journal record of the model call behind the decision, to set the action taken on it
+    #[serde(default)]
+    pub journal_id: Option<String>,
+    /// Strategies that failed or were rejected by the ensemble before this decision
+    #[serde(default)]
+    pub fallbacks: Vec<StrategyFallback>,
+}
+
+/// A range selection strategy. Implementations are registered by name in the
+/// [`registry::StrategyRegistry`] and picked per vault from the config.
+#[async_trait]
+pub trait Strategy: Send + Sync {
+    /// Name used to reference the strategy from the config files
+    fn name(&self) -> &'static str;
+
+    /// Whether a decision sends a paid prompt to a model, such strategies are not backtested
+    fn calls_model(&self) -> bool {
+        false
+    }
+
+    async fn decide(&self, ctx: &StrategyContext<'_>) -> Result<StrategyDecision>;
+}
@@ -0,0 +1,153 @@
+use std::{collections::HashMap, sync::Arc};
+
+use color_eyre::eyre::Result;
+
+use crate::{
+    strategies::{
+        Strategy, ai::AiStrategy, basic::BasicStrategy, ensemble::EnsembleStrategy,
+        fallback::StrategyChain, volatility::VolatilityStrategy,
+    },
+    types::TomlConfig,
+};
+
+/// Holds every strategy the vault loops can pick from, keyed by their config name.
+#[derive(Default)]
+pub struct StrategyRegistry {
+    strategies: HashMap<&'static str, Arc<dyn Strategy>>,
+}
+
+impl StrategyRegistry {
+    pub fn new() -> Self {
+        Self {
+            strategies: HashMap::new(),
+        }
+    }
+
+    /// Register a strategy, replacing any previous one with the same name
+    pub fn register(&mut self, strategy: Arc<dyn Strategy>) {
+        self.strategies.insert(strategy.name(), strategy);
+    }
+
+    pub fn get(&self, name: &str) -> Result<Arc<dyn Strategy>> {
+        self.strategies.get(name).cloned().ok_or_else(|| {
+            color_eyre::eyre::eyre!(
+                "Unknown strategy '{}'. Available strategies: {:?}",
+                name,
+                self.names()
+            )
+        })
+    }
+
+    /// Chain of the strategies `names`, in order
+    pub fn chain<'a>(&self, names: impl IntoIterator<Item = &'a str>) -> Result<StrategyChain> {
+        let strategies = names
+            .into_iter()
+            .map(|name| self.get(name))
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(StrategyChain::new(strategies))
+    }
+
+    /// Make sure every strategy named in the config is registered
+    pub fn check_config(&self, toml_config: &TomlConfig) -> Result<()> {
+        for vault_config in &toml_config.vaults {
+            self.chain(vault_config.strategy_names())?;
+        }
+
+        self.chain(toml_config.ensemble.strategies.iter().map(String::as_str))?;
+
+        Ok(())
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        let mut names: Vec<&'static str> = self.strategies.keys().copied().collect();
+        names.sort();
+        names
+    }
+
+    /// Registry with all the built-in strategies, errors when a member of the ensemble is not one of them
+    pub fn with_builtin_strategies(toml_config: &TomlConfig) -> Result<Self> {
+        let mut registry = Self::new();
+
+        registry.register(Arc::new(BasicStrategy));
+        registry.register(Arc::new(AiStrategy::new(toml_config.llm.clone())));
+        registry.register(Arc::new(VolatilityStrategy::new(
+            toml_config.volatility.clone(),
+        )));
+
+        // Built last, from the strategies registered above
+        let ensemble_strategies = toml_config
+            .ensemble
+            .strategies
+            .iter()
+            .map(|name| registry.get(name))
+            .collect::<Result<Vec<_>>>()?;
+        registry.register(Arc::new(EnsembleStrategy::new(
+            toml_config.ensemble.clone(),
+            ensemble_strategies,
+        )));
+
+        Ok(registry)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn toml_config() -> TomlConfig {
+        toml::from_str(include_str!("../config/testnet.toml")).unwrap()
+    }
+
+    #[test]
+    fn test_unknown_names_are_errors() {
+        let mut registry = StrategyRegistry::new();
+        registry.register(Arc::new(BasicStrategy));
+
+        assert_eq!(registry.get("basic").unwrap().name(), "basic");
+        let error = registry.get("missing").err().unwrap().to_string();
+        assert!(error.contains("Unknown strategy 'missing'"));
+        assert!(error.contains("[\"basic\"]"));
+
+        assert!(registry.chain(["basic"]).is_ok());
+        let error = registry.chain(["basic", "missing"]).err().unwrap();
+        assert!(error.to_string().contains("Unknown strategy 'missing'"));
+    }
+
+    #[test]
+    fn test_builtin_strategies_refuse_unknown_ensemble_members() {
+        let mut toml_config = toml_config();
+
+        let registry = StrategyRegistry::with_builtin_strategies(&toml_config).unwrap();
+        assert_eq!(
+            registry.names(),
+            vec!["ai", "basic", "ensemble", "volatility"]
+        );
+
+        toml_config.ensemble.strategies.push("missing".to_string());
+        let error = StrategyRegistry::with_builtin_strategies(&toml_config)
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("Unknown strategy 'missing'"));
+    }
+
+    #[test]
+    fn test_check_config_needs_every_strategy_registered() {
+        let mut toml_config = toml_config();
+        let mut registry = StrategyRegistry::new();
+        registry.register(Arc::new(BasicStrategy));
+
+        let error = registry.check_config(&toml_config).unwrap_err();
+        assert!(error.to_string().contains("Unknown strategy"));
+
+        registry.register(Arc::new(AiStrategy::new(toml_config.llm.clone())));
+        registry.register(Arc::new(VolatilityStrategy::new(
+            toml_config.volatility.clone(),
+        )));
+        registry.check_config(&toml_config).unwrap();
+
+        toml_config.vaults[0].fallback_strategies = vec!["missing".to_string()];
+        let error = registry.check_config(&toml_config).unwrap_err();
+        assert!(error.to_string().contains("Unknown strategy 'missing'"));
+    }
+}
@@ -0,0 +1,293 @@
+/*
+    Deterministic strategy sizing the range from the realized volatility of the pool candles, like a Bollinger band
+    (optionally widened to an ATR band), clamped to a width in tick spacings and moved along the trend.
+    It needs no model nor API key, so it can run where the AI strategy is unavailable or too costly.
+*/
+
+use async_trait::async_trait;
+use color_eyre::eyre::{Result, eyre};
+use tracing::info;
+
+use crate::{
+    config::FEATURE_ATR_PERIOD,
+    core,
+    helpers::math::{
+        TickRounding, align_to_pool_tick_spacing,
+        features::{average_true_range, realized_volatility, trend},
+        usable_tick_range,
+    },
+    strategies::{Strategy, StrategyContext, StrategyDecision},
+    types::{OhlcvEntry, TickRange, VaultDetails, VolatilityStrategyConfig},
+};
+
+/// ln(1.0001), the log price step of a tick
+const LOG_TICK_BASE: f64 = 0.000_099_995_000_333_308_3;
+
+pub struct VolatilityStrategy {
+    config: VolatilityStrategyConfig,
+}
+
+impl VolatilityStrategy {
+    pub fn new(config: VolatilityStrategyConfig) -> Self {
+        Self { config }
+    }
+}
+
+#[async_trait]
+impl Strategy for VolatilityStrategy {
+    fn name(&self) -> &'static str {
+        "volatility"
+    }
+
+    async fn decide(&self, ctx: &StrategyContext<'_>) -> Result<StrategyDecision> {
+        let vault = ctx.vault;
+
+        let candles = match ctx.ohlcv {
+            Some(ohlcv) => ohlcv.to_vec(),
+            None => {
+                core::coingecko::get_pool_ohlcv_data(&vault.pool.address, vault)
+                    .await?
+                    .data
+                    .attributes
+                    .ohlcv_list
+            }
+        };
+
+        let band = volatility_band(
+            &self.config,
+            vault.pool.current_tick,
+            vault.pool.tick_spacing,
+            &candles,
+        )?;
+
+        info!(
+            "Volatility Strategy range for vault {}: {} - {}",
+            vault.address, band.tick_range.lower_tick, band.tick_range.upper_tick
+        );
+
+        let rebalance_required = is_rebalance_required(&self.config, vault, &band.tick_range);
+
+        Ok(StrategyDecision {
+            strategy: self.name().to_string(),
+            rationale: format!(
+                "Realized volatility {:.2}% per candle over {} candles, band of +/-{:.2}% with its center moved {:+.2}% along the trend, {} tick spacings wide",
+                band.volatility * 100.0,
+                self.config.window,
+                band.half_width.exp_m1() * 100.0,
+                band.center_shift.exp_m1() * 100.0,
+                (band.tick_range.upper_tick - band.tick_range.lower_tick) / vault.pool.tick_spacing
+            ),
+            tick_range: band.tick_range,
+            rebalance_required,
+            confidence: None,
+            journal_id: None,
+            fallbacks: vec![],
+        })
+    }
+}
+
+/// Range of the strategy and the values it was sized from, log price units
+#[derive(Debug, Clone)]
+pub struct VolatilityBand {
+    pub tick_range: TickRange,
+    pub volatility: f64,
+    pub half_width: f64,
+    pub center_shift: f64,
+}
+
+/// Size the range from the last `config.window` candles. The range always contains `current_tick`
+pub fn volatility_band(
+    config: &VolatilityStrategyConfig,
+    current_tick: i32,
+    tick_spacing: i32,
+    candles: &[OhlcvEntry],
+) -> Result<VolatilityBand> {
+    if tick_spacing <= 0 {
+        return Err(eyre!("Invalid tick spacing: {}", tick_spacing));
+    }
+
+    let volatility = realized_volatility(candles, config.window).ok_or_else(|| {
+        eyre!(
+            "The volatility strategy needs at least {} candles, got {}",
+            config.window,
+            candles.len()
+        )
+    })?;
+
+    let horizon = config.horizon_candles as f64;
+
+    let mut half_width = config.sigma_multiple * volatility * horizon.sqrt();
+
+    if config.atr_multiple > 0.0 {
+        let last_close = candles.last().map(OhlcvEntry::close).unwrap_or_default();
+        let atr = average_true_range(candles, FEATURE_ATR_PERIOD).filter(|_| last_close > 0.0);
+
+        if let Some(atr) = atr {
+            let atr_half_width = config.atr_multiple * (atr / last_close).ln_1p() * horizon.sqrt();
+            half_width = half_width.max(atr_half_width);
+        }
+    }
+
+    // Only a trend the closes follow moves the center, and never by more than half the band
+    let center_shift = trend(candles, config.window)
+        .map(|trend| {
+            config.trend_skew * (trend.slope_pct / 100.0).ln_1p() * horizon * trend.r_squared
+        })
+        .unwrap_or_default()
+        .clamp(-half_width / 2.0, half_width / 2.0);
+
+    // A band can not be wider than the usable ticks of the pool, whatever the configured widths
+    let (min_tick, max_tick) = usable_tick_range(tick_spacing);
+    let usable_width_spacings = ((max_tick - min_tick) / tick_spacing) as u32;
+
+    let width_spacings = ((2.0 * half_width / LOG_TICK_BASE / tick_spacing as f64).ceil() as u32)
+        .clamp(config.min_width_spacings, config.max_width_spacings)
+        .min(usable_width_spacings);
+    let width_ticks = width_spacings as i32 * tick_spacing;
+
+    let center_tick = current_tick as f64 + center_shift / LOG_TICK_BASE;
+    let mut lower_tick = align_to_pool_tick_spacing(
+        (center_tick - width_ticks as f64 / 2.0).round() as i32,
+        tick_spacing,
+        TickRounding::Down,
+    )?;
+
+    // Rounding can leave the current tick just outside a narrow range
+    let current_spacing_tick =
+        align_to_pool_tick_spacing(current_tick, tick_spacing, TickRounding::Down)?;
+    if current_tick < lower_tick {
+        lower_tick = current_spacing_tick;
+    } else if current_tick >= lower_tick + width_ticks {
+        lower_tick = current_spacing_tick - width_ticks + tick_spacing;
+    }
+
+    let lower_tick = lower_tick.clamp(min_tick, max_tick - width_ticks);
+
+    Ok(VolatilityBand {
+        tick_range: TickRange {
+            curent_tick: current_tick,
+            lower_tick,
+            upper_tick: lower_tick + width_ticks,
+        },
+        volatility,
+        half_width,
+        center_shift,
+    })
+}
+
+/// Move the vault when it has no position, is out of range, or its width is too far from the band width
+pub fn is_rebalance_required(
+    config: &VolatilityStrategyConfig,
+    vault: &VaultDetails,
+    band: &TickRange,
+) -> bool {
+    let current_tick = vault.pool.current_tick;
+
+    if !vault.is_active || current_tick < vault.lower_tick || current_tick >= vault.upper_tick {
+        return true;
+    }
+
+    let width_ratio =
+        (vault.upper_tick - vault.lower_tick) as f64 / (band.upper_tick - band.lower_tick) as f64;
+
+    width_ratio > config.rebalance_width_ratio || width_ratio < 1.0 / config.rebalance_width_ratio
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn candles(closes: &[f64]) -> Vec<OhlcvEntry> {
+        closes
+            .iter()
+            .enumerate()
+            .map(|(index, close)| {
+                OhlcvEntry(index as i64 * 86_400, *close, *close, *close, *close, 1.0)
+            })
+            .collect()
+    }
+
+    /// Closes alternating between +/-`move_pct` moves
+    fn choppy(move_pct: f64, count: usize) -> Vec<OhlcvEntry> {
+        let closes = (0..count)
+            .map(|i| {
+                if i % 2 == 0 {
+                    1.0
+                } else {
+                    1.0 + move_pct / 100.0
+                }
+            })
+            .collect::<Vec<f64>>();
+
+        candles(&closes)
+    }
+
+    #[test]
+    fn test_band_width_follows_volatility() {
+        let config = VolatilityStrategyConfig::default();
+
+        let calm = volatility_band(&config, 0, 10, &choppy(0.5, 31)).unwrap();
+        let wild = volatility_band(&config, 0, 10, &choppy(5.0, 31)).unwrap();
+
+        let width = |band: &VolatilityBand| band.tick_range.upper_tick - band.tick_range.lower_tick;
+
+        // +/-2 sigma of ~0.5% moves is a ~2% wide range, ~200 ticks
+        assert!((190..=210).contains(&width(&calm)), "{:?}", calm);
+        assert!(width(&wild) > 9 * width(&calm));
+
+        for band in [&calm, &wild] {
+            assert_eq!(band.tick_range.lower_tick % 10, 0);
+            assert!(band.tick_range.lower_tick <= 0 && band.tick_range.upper_tick > 0);
+        }
+
+        // Flat prices hit the minimum width, large moves the maximum one
+        let flat = volatility_band(&config, 5, 10, &candles(&[1.0; 31])).unwrap();
+        assert_eq!(flat.tick_range.lower_tick, -10);
+        assert_eq!(flat.tick_range.upper_tick, 10);
+
+        let capped = volatility_band(&config, 0, 10, &choppy(50.0, 31)).unwrap();
+        assert_eq!(width(&capped), 200 * 10);
+
+        assert!(volatility_band(&config, 0, 10, &choppy(1.0, 10)).is_err());
+    }
+
+    #[test]
+    fn test_band_is_capped_to_the_usable_ticks() {
+        let config = VolatilityStrategyConfig {
+            min_width_spacings: 40_000,
+            max_width_spacings: 50_000,
+            ..VolatilityStrategyConfig::default()
+        };
+
+        let band = volatility_band(&config, 1_000, 60, &choppy(1.0, 31)).unwrap();
+
+        let (min_tick, max_tick) = usable_tick_range(60);
+        assert_eq!(band.tick_range.lower_tick, min_tick);
+        assert_eq!(band.tick_range.upper_tick, max_tick);
+    }
+
+    #[test]
+    fn test_trend_moves_the_band_center() {
+        // +1% every candle with some noise so the band is not at its minimum width
+        let closes = (0..31)
+            .map(|i| 1.01f64.powi(i) * if i % 2 == 0 { 1.0 } else { 1.002 })
+            .collect::<Vec<f64>>();
+
+        let config = VolatilityStrategyConfig {
+            trend_skew: 0.0,
+            ..VolatilityStrategyConfig::default()
+        };
+        let centered = volatility_band(&config, 0, 1, &candles(&closes)).unwrap();
+
+        let config = VolatilityStrategyConfig {
+            trend_skew: 1.0,
+            ..VolatilityStrategyConfig::default()
+        };
+        let skewed = volatility_band(&config, 0, 1, &candles(&closes)).unwrap();
+
+        assert_eq!(centered.center_shift, 0.0);
+        assert!(skewed.center_shift > 0.0);
+        assert!(skewed.tick_range.lower_tick > centered.tick_range.lower_tick);
+        assert!(skewed.tick_range.lower_tick <= 0 && skewed.tick_range.upper_tick > 0);
+    }
+}
@@ -1,9 +1,17 @@
 use actix_web::web;
 use alloy::primitives::{Address, U256};
 use serde::{Deserialize, Serialize};
-use utoipa::ToSchema;
+use utoipa::{IntoParams, ToSchema};
 
-use crate::state::AppState;
+use crate::{
+    backtest::BacktestConfig,
+    config::{
+        MAX_TWAP_DEVIATION_TICKS, MONITOR_VAULT_INTERVAL_SECONDS,
+        POLICY_REEVALUATE_INTERVAL_SECONDS, SWAP_OHLCV_INTERVAL_SECONDS, TWAP_WINDOW_SECONDS,
+    },
+    helpers::amount::TokenAmount,
+    state::AppState,
+};
 
 pub type EvmProvider = alloy::providers::fillers::FillProvider<
     alloy::providers::fillers::JoinFill<
@@ -81,53 +89,434 @@ pub struct Position {
     pub tick_lower: i32,
     pub tick_upper: i32,
     pub liquidity: u128,
-    pub amount0: f64,
-    pub amount1: f64,
-    pub fees0: f64,
-    pub fees1: f64,
+    #[schema(value_type = String)]
+    pub amount0: TokenAmount,
+    #[schema(value_type = String)]
+    pub amount1: TokenAmount,
+    #[schema(value_type = String)]
+    pub fees0: TokenAmount,
+    #[schema(value_type = String)]
+    pub fees1: TokenAmount,
 }
 
-impl Default for Position {
-    fn default() -> Self {
+impl Position {
+    /// No position, the amounts are zero in the decimals of the pool tokens
+    pub fn empty(token0_decimals: u8, token1_decimals: u8) -> Self {
         Self {
             tick_lower: 0,
             tick_upper: 0,
             liquidity: 0,
-            amount0: 0.0,
-            amount1: 0.0,
-            fees0: 0.0,
-            fees1: 0.0,
+            amount0: TokenAmount::zero(token0_decimals),
+            amount1: TokenAmount::zero(token1_decimals),
+            fees0: TokenAmount::zero(token0_decimals),
+            fees1: TokenAmount::zero(token1_decimals),
         }
     }
 }
 
+/// Vault without position on a 0.3% pool of an 8 decimals token0 and a 6 decimals token1, for the tests
+#[cfg(test)]
+pub fn test_vault(current_tick: i32, tick_spacing: i32, price1: f64) -> VaultDetails {
+    let token = |address: &str, decimals| Token {
+        address: address.to_string(),
+        name: "Token".to_string(),
+        symbol: "TKN".to_string(),
+        decimals,
+        is_native_wrapper: false,
+    };
+
+    VaultDetails {
+        address: "0x0000000000000000000000000000000000000001".to_string(),
+        pool: Pool {
+            address: "0x0000000000000000000000000000000000000002".to_string(),
+            token0: token("0x0000000000000000000000000000000000000003", 8),
+            token1: token("0x0000000000000000000000000000000000000004", 6),
+            fee: 0.3,
+            tick_spacing,
+            current_tick,
+            sqrt_price_x96: U256::ZERO,
+            price1,
+            price0: if price1 > 0.0 { 1.0 / price1 } else { 0.0 },
+        },
+        name: "Vault".to_string(),
+        symbol: "VLT".to_string(),
+        decimals: 18,
+        total_supply: 0.0,
+        lower_tick: 0,
+        upper_tick: 0,
+        is_active: false,
+        is_vault_tokens_associated: true,
+        position: Position::empty(8, 6),
+        tvl: VaultTVL {
+            tvl0: TokenAmount::zero(8),
+            tvl1: TokenAmount::zero(6),
+        },
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct VaultTokenBalances {
-    pub token0_balance: f64,
-    pub token1_balance: f64,
-    pub token0_balance_u256: U256,
-    pub token1_balance_u256: U256,
+    pub token0_balance: TokenAmount,
+    pub token1_balance: TokenAmount,
 }
 
 #[derive(Debug, Clone)]
 pub struct PrepareSwapArgs {
-    pub exact_amount_out: f64,
-    pub parsed_exact_amount_out: U256,
+    pub exact_amount_out: TokenAmount,
     pub token_in: Token,
     pub token_out: Token,
     pub is_swap_0_to_1: bool,
-    pub max_amount_in: U256,
-    pub formatted_max_amount_in: f64,
+    pub max_amount_in: TokenAmount,
 }
 
 /// Top-level config struct matching the TOML file structure
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct TomlConfig {
-    pub rpc_url: String,
+    /// JSON-RPC endpoints, tried in order. The next ones take over when an endpoint fails (see `core::rpc_pool`)
+    pub rpc_urls: Vec<String>,
+    /// Cross check the pool price and vault range on two endpoints before each rebalance
+    #[serde(default)]
+    pub cross_check_reads: bool,
+    /// Multicall3 contract the vault reads are batched through, JSON-RPC batches are used when unset
+    pub multicall_address: Option<String>,
     pub chain_id: u64,
     pub non_fungible_position_manager_address: String,
     pub hbar_evm_address: String,
-    pub vaults: Vec<String>,
+    /// Model behind the AI strategy and the chat agent, Gemini when the table is missing
+    #[serde(default)]
+    pub llm: LlmConfig,
+    /// Settings of the `volatility` strategy, defaults when the table is missing
+    #[serde(default)]
+    pub volatility: VolatilityStrategyConfig,
+    /// Settings of the `ensemble` strategy, defaults when the table is missing
+    #[serde(default)]
+    pub ensemble: EnsembleConfig,
+    #[serde(rename = "vault")]
+    pub vaults: Vec<VaultConfig>,
+}
+
+/// `[llm]` table of the config, see `core::llm`
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct LlmConfig {
+    #[serde(default)]
+    pub provider: LlmProvider,
+    #[serde(default = "default_llm_model")]
+    pub model: String,
+    /// Endpoint of an OpenAI compatible API, e.g. `http://localhost:11434/v1` for a local model.
+    /// The OpenAI API when unset
+    pub base_url: Option<String>,
+    /// Env var holding the key of the OpenAI compatible API, local models usually don't need one
+    #[serde(default = "default_llm_api_key_env")]
+    pub api_key_env: String,
+    /// Answer of the mock model, a strategy answer keeping the current range when unset
+    pub mock_reply: Option<String>,
+}
+
+impl Default for LlmConfig {
+    fn default() -> Self {
+        Self {
+            provider: LlmProvider::default(),
+            model: default_llm_model(),
+            base_url: None,
+            api_key_env: default_llm_api_key_env(),
+            mock_reply: None,
+        }
+    }
+}
+
+impl LlmConfig {
+    /// `provider/model`, as recorded in the AI journal
+    pub fn model_name(&self) -> String {
+        let provider = match self.provider {
+            LlmProvider::Gemini => "gemini",
+            LlmProvider::OpenaiCompatible => "openai_compatible",
+            LlmProvider::Mock => "mock",
+        };
+
+        format!("{}/{}", provider, self.model)
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum LlmProvider {
+    /// Google Gemini, the key is read from `GEMINI_API_KEY`
+    #[default]
+    Gemini,
+    /// Any API implementing the OpenAI chat completions endpoint
+    OpenaiCompatible,
+    /// Deterministic model answering `mock_reply` to every prompt, for tests and offline runs
+    Mock,
+}
+
+fn default_llm_model() -> String {
+    "gemini-2.0-flash".to_string()
+}
+
+fn default_llm_api_key_env() -> String {
+    "OPENAI_API_KEY".to_string()
+}
+
+/// `[volatility]` table of the config, see `strategies::volatility`
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct VolatilityStrategyConfig {
+    /// Candles the realized volatility and the trend are measured on
+    #[serde(default = "default_volatility_window")]
+    pub window: usize,
+    /// Candles the range should hold the price for, the band grows with the square root of it
+    #[serde(default = "default_volatility_horizon_candles")]
+    pub horizon_candles: u32,
+    /// Half width of the range in standard deviations of the returns, like a Bollinger band
+    #[serde(default = "default_volatility_sigma_multiple")]
+    pub sigma_multiple: f64,
+    /// Half width of the range in average true ranges, the wider of both bands is used. 0 disables it
+    #[serde(default)]
+    pub atr_multiple: f64,
+    /// Narrowest range, in tick spacings
+    #[serde(default = "default_volatility_min_width_spacings")]
+    pub min_width_spacings: u32,
+    /// Widest range, in tick spacings
+    #[serde(default = "default_volatility_max_width_spacings")]
+    pub max_width_spacings: u32,
+    /// Share of the trend expected over the horizon the range center is moved by, 0 keeps it on the price
+    #[serde(default = "default_volatility_trend_skew")]
+    pub trend_skew: f64,
+    /// An in range position is moved when its width is off the band width by more than this factor
+    #[serde(default = "default_volatility_rebalance_width_ratio")]
+    pub rebalance_width_ratio: f64,
+}
+
+impl Default for VolatilityStrategyConfig {
+    fn default() -> Self {
+        Self {
+            window: default_volatility_window(),
+            horizon_candles: default_volatility_horizon_candles(),
+            sigma_multiple: default_volatility_sigma_multiple(),
+            atr_multiple: 0.0,
+            min_width_spacings: default_volatility_min_width_spacings(),
+            max_width_spacings: default_volatility_max_width_spacings(),
+            trend_skew: default_volatility_trend_skew(),
+            rebalance_width_ratio: default_volatility_rebalance_width_ratio(),
+        }
+    }
+}
+
+/// `[ensemble]` table of the config, see `strategies::ensemble`
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct EnsembleConfig {
+    /// Strategies asked for a proposal, the first valid one besides the deterministic strategy is followed
+    #[serde(default = "default_ensemble_strategies")]
+    pub strategies: Vec<String>,
+    /// Strategy of `strategies` followed when no other proposal is valid
+    #[serde(default = "default_ensemble_deterministic_strategy")]
+    pub deterministic_strategy: String,
+    /// Proposals of a model below this confidence are rejected
+    #[serde(default = "default_ensemble_min_confidence")]
+    pub min_confidence: f64,
+    /// Narrowest range accepted, in tick spacings
+    #[serde(default = "default_ensemble_min_width_spacings")]
+    pub min_width_spacings: u32,
+    /// Widest range accepted, in tick spacings
+    #[serde(default = "default_ensemble_max_width_spacings")]
+    pub max_width_spacings: u32,
+}
+
+impl Default for EnsembleConfig {
+    fn default() -> Self {
+        Self {
+            strategies: default_ensemble_strategies(),
+            deterministic_strategy: default_ensemble_deterministic_strategy(),
+            min_confidence: default_ensemble_min_confidence(),
+            min_width_spacings: default_ensemble_min_width_spacings(),
+            max_width_spacings: default_ensemble_max_width_spacings(),
+        }
+    }
+}
+
+fn default_ensemble_strategies() -> Vec<String> {
+    vec!["ai".to_string(), "volatility".to_string()]
+}
+
+fn default_ensemble_deterministic_strategy() -> String {
+    "volatility".to_string()
+}
+
+fn default_ensemble_min_confidence() -> f64 {
+    0.6
+}
+
+fn default_ensemble_min_width_spacings() -> u32 {
+    2
+}
+
+fn default_ensemble_max_width_spacings() -> u32 {
+    400
+}
+
+fn default_volatility_window() -> usize {
+    30
+}
+
+fn default_volatility_horizon_candles() -> u32 {
+    1
+}
+
+fn default_volatility_sigma_multiple() -> f64 {
+    2.0
+}
+
+fn default_volatility_min_width_spacings() -> u32 {
+    2
+}
+
+fn default_volatility_max_width_spacings() -> u32 {
+    200
+}
+
+fn default_volatility_trend_skew() -> f64 {
+    0.5
+}
+
+fn default_volatility_rebalance_width_ratio() -> f64 {
+    2.0
+}
+
+/// Per vault settings, one `[[vault]]` table per managed vault
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct VaultConfig {
+    pub address: String,
+    /// Name of the strategy used to pick the vault ranges (see `strategies::registry`)
+    #[serde(default = "default_strategy")]
+    pub strategy: String,
+    /// Strategies tried in order when the previous one fails, e.g. `["volatility", "basic"]` after `ai`
+    #[serde(default)]
+    pub fallback_strategies: Vec<String>,
+    #[serde(default = "default_monitor_interval_seconds")]
+    pub monitor_interval_seconds: u64,
+    /// Send the rebalance transactions for this vault. The `IS_EXECUTE` env var stays a global switch
+    #[serde(default)]
+    pub is_execute: bool,
+    /// Horizon over which the expected fees of a new range are weighed against the rebalance costs
+    #[serde(default = "default_profit_horizon_hours")]
+    pub profit_horizon_hours: f64,
+    /// Factor converting the CoinGecko candle volumes into token1 units
+    #[serde(default = "default_volume_to_token1")]
+    pub volume_to_token1: f64,
+    /// Price of HBAR in token1 units, only needed to value the gas of pools without WHBAR
+    #[serde(default)]
+    pub hbar_price_token1: Option<f64>,
+    /// Tolerance over the simulated amount in of the rebalance swap, in basis points
+    #[serde(default = "default_swap_slippage_bps")]
+    pub swap_slippage_bps: u32,
+    /// Window of the pool TWAP the pool tick is checked against before a rebalance, 0 disables the check
+    #[serde(default = "default_twap_window_seconds")]
+    pub twap_window_seconds: u32,
+    /// Ticks the pool tick can be away from its TWAP, a rebalance is refused beyond that
+    #[serde(default = "default_max_twap_deviation_ticks")]
+    pub max_twap_deviation_ticks: u32,
+    /// Where the pool candles used by the strategies and the volume estimate come from
+    #[serde(default)]
+    pub ohlcv_source: OhlcvSource,
+    /// Length of the pool candles, CoinGecko only has 60, 3600 and 86400 second candles
+    #[serde(default = "default_ohlcv_interval_seconds")]
+    pub ohlcv_interval_seconds: u64,
+    /// First block scanned for swaps when the pool has no local candles yet, defaults to a week back
+    #[serde(default)]
+    pub ohlcv_start_block: Option<u64>,
+    /// Rules deciding when the strategies are run, see `core::rebalance_policy`
+    #[serde(default)]
+    pub policy: RebalancePolicyConfig,
+}
+
+/// `[vault.policy]` table of a vault, every rule is disabled by default
+#[derive(Debug, Deserialize, Serialize, Clone, ToSchema)]
+pub struct RebalancePolicyConfig {
+    /// Minimum time between two executed rebalances
+    #[serde(default)]
+    pub min_rebalance_interval_seconds: u64,
+    /// Time the price has to stay out of the trigger band before the strategies run
+    #[serde(default)]
+    pub out_of_band_seconds: u64,
+    /// Inner band of the range, each edge moved in by this share of the range width, in percent (0 - 50)
+    #[serde(default)]
+    pub trigger_band_pct: Option<f64>,
+    /// Executed rebalances allowed over the last 24 hours, 0 for no cap
+    #[serde(default)]
+    pub max_rebalances_per_day: u32,
+    /// Cron-style windows rebalances are allowed in, in UTC, e.g. `"* 8-17 * * 1-5"`. Empty allows any time
+    #[serde(default)]
+    pub schedule: Vec<String>,
+    /// Wait before running the strategies again on a price out of its trigger band when they did not rebalance
+    #[serde(default = "default_policy_reevaluate_interval_seconds")]
+    pub reevaluate_interval_seconds: u64,
+}
+
+impl Default for RebalancePolicyConfig {
+    fn default() -> Self {
+        Self {
+            min_rebalance_interval_seconds: 0,
+            out_of_band_seconds: 0,
+            trigger_band_pct: None,
+            max_rebalances_per_day: 0,
+            schedule: vec![],
+            reevaluate_interval_seconds: default_policy_reevaluate_interval_seconds(),
+        }
+    }
+}
+
+fn default_policy_reevaluate_interval_seconds() -> u64 {
+    POLICY_REEVALUATE_INTERVAL_SECONDS
+}
+
+/// Source of the pool candles of a vault
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum OhlcvSource {
+    /// Daily candles of the CoinGecko onchain API, only indexed for mainnet pools
+    #[default]
+    Coingecko,
+    /// Candles built from the pool `Swap` logs and stored locally (see `core::swap_ohlcv`)
+    SwapLogs,
+}
+
+impl VaultConfig {
+    /// Main strategy followed by the fallback ones
+    pub fn strategy_names(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self.strategy.as_str())
+            .chain(self.fallback_strategies.iter().map(String::as_str))
+    }
+}
+
+fn default_strategy() -> String {
+    "ai".to_string()
+}
+
+fn default_monitor_interval_seconds() -> u64 {
+    MONITOR_VAULT_INTERVAL_SECONDS
+}
+
+fn default_profit_horizon_hours() -> f64 {
+    24.0
+}
+
+fn default_volume_to_token1() -> f64 {
+    1.0
+}
+
+fn default_swap_slippage_bps() -> u32 {
+    50
+}
+
+fn default_twap_window_seconds() -> u32 {
+    TWAP_WINDOW_SECONDS
+}
+
+fn default_max_twap_deviation_ticks() -> u32 {
+    MAX_TWAP_DEVIATION_TICKS
+}
+
+fn default_ohlcv_interval_seconds() -> u64 {
+    SWAP_OHLCV_INTERVAL_SECONDS
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
@@ -135,6 +524,16 @@ pub struct AdminAssociateVaultTokensRequest {
     pub password: String,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct BacktestRequest {
+    pub password: String,
+    pub vault_address: String,
+    pub strategy: String,
+    pub config: BacktestConfig,
+    /// Name of a file inside the `backtest_data` directory. When missing the candles are fetched from coingecko
+    pub ohlcv_file: Option<String>,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
 pub struct ApiErrorResponse {
     pub message: String,
@@ -143,7 +542,7 @@ pub struct ApiErrorResponse {
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct CoingeckoOhlcvRes {
-    data: CoingeckoResData,
+    pub data: CoingeckoResData,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -157,17 +556,39 @@ pub struct CoingeckoResDataAttributes {
     pub ohlcv_list: Vec<OhlcvEntry>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
 pub struct OhlcvEntry(
-    i64, // timestamp (UNIX)
-    f64, // open
-    f64, // high
-    f64, // low
-    f64, // close
-    f64, // volume
+    pub i64, // timestamp (UNIX)
+    pub f64, // open
+    pub f64, // high
+    pub f64, // low
+    pub f64, // close
+    pub f64, // volume
 );
 
-#[derive(Debug, Serialize, Deserialize)]
+impl OhlcvEntry {
+    pub fn timestamp(&self) -> i64 {
+        self.0
+    }
+
+    pub fn high(&self) -> f64 {
+        self.2
+    }
+
+    pub fn low(&self) -> f64 {
+        self.3
+    }
+
+    pub fn close(&self) -> f64 {
+        self.4
+    }
+
+    pub fn volume(&self) -> f64 {
+        self.5
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct AiStrategyResponse {
     pub rebalance_required: bool,
     pub new_price_range: PriceRange,
@@ -176,7 +597,7 @@ pub struct AiStrategyResponse {
     pub confidence_score: f64,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct PriceRange {
     pub lower_price: f64,
     pub upper_price: f64,
@@ -184,8 +605,10 @@ pub struct PriceRange {
 
 #[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
 pub struct VaultTVL {
-    pub tvl0: f64,
-    pub tvl1: f64,
+    #[schema(value_type = String)]
+    pub tvl0: TokenAmount,
+    #[schema(value_type = String)]
+    pub tvl1: TokenAmount,
 }
 
 #[derive(serde::Deserialize, serde::Serialize, utoipa::ToSchema)]
@@ -194,3 +617,23 @@ pub struct ChatRequest {
     pub network: Option<String>,
     pub account_address: String,
 }
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct RebalancePlansQuery {
+    /// Only return the plans of this vault
+    pub vault_address: Option<String>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct RebalancePolicyQuery {
+    /// Only return the policy of this vault
+    pub vault_address: Option<String>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct AiDecisionsQuery {
+    /// Only return the decisions of this vault
+    pub vault_address: Option<String>,
+    /// Maximum number of decisions returned
+    pub limit: Option<usize>,
+}
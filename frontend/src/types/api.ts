@@ -32,12 +32,13 @@ export interface BackendVaultResponse {
   upper_tick: number;
   is_active: boolean;
   // TVL data might be provided in different structures
-  tvl0?: number;
-  tvl1?: number;
+  // Token amounts are exact decimal strings, e.g. "1.500000" for 6 decimals
+  tvl0?: string;
+  tvl1?: string;
   tvl?:
     | {
-        tvl0: number;
-        tvl1: number;
+        tvl0: string;
+        tvl1: string;
       }
     | string;
   // Position data from backend
@@ -45,10 +46,10 @@ export interface BackendVaultResponse {
     tick_lower: number;
     tick_upper: number;
     liquidity: number;
-    amount0: number;
-    amount1: number;
-    fees0: number;
-    fees1: number;
+    amount0: string;
+    amount1: string;
+    fees0: string;
+    fees1: string;
   };
 }
 
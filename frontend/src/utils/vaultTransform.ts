@@ -42,12 +42,18 @@ export const transformVaultData = (
     backendVault.tvl.tvl0 !== undefined &&
     backendVault.tvl.tvl1 !== undefined
   ) {
-    tvlData = { tvl0: backendVault.tvl.tvl0, tvl1: backendVault.tvl.tvl1 };
+    tvlData = {
+      tvl0: Number(backendVault.tvl.tvl0),
+      tvl1: Number(backendVault.tvl.tvl1),
+    };
     console.log("Using tvl.tvl0/tvl1 from backend:", tvlData);
   }
   // Check if tvl0/tvl1 are directly on the vault object (fallback)
   else if (backendVault.tvl0 !== undefined && backendVault.tvl1 !== undefined) {
-    tvlData = { tvl0: backendVault.tvl0, tvl1: backendVault.tvl1 };
+    tvlData = {
+      tvl0: Number(backendVault.tvl0),
+      tvl1: Number(backendVault.tvl1),
+    };
     console.log("Using direct tvl0/tvl1 from vault:", tvlData);
   }
   // Fallback to mock data